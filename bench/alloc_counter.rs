@@ -0,0 +1,34 @@
+// Counts heap allocations and reports them on stderr when the process exits.
+// `run.sh` adds this module to the revision it builds; it is not part of the interpreter.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+extern "C" {
+    fn atexit(callback: extern "C" fn()) -> i32;
+}
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static REGISTERED: AtomicBool = AtomicBool::new(false);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if !REGISTERED.swap(true, Ordering::Relaxed) {
+            atexit(report);
+        }
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+extern "C" fn report() {
+    eprintln!("allocations: {}", ALLOCATIONS.load(Ordering::Relaxed));
+}
@@ -0,0 +1,47 @@
+#!/bin/bash
+# Times the scripts in bench/ and counts their heap allocations.
+#
+#   bench/run.sh [<revision>...]
+#
+# Each revision (HEAD by default) is built in release mode from a temporary
+# worktree, with bench/alloc_counter.rs added as a module. Each script runs
+# RUNS times (5 by default); the best wall-clock time is reported. Uncommitted
+# changes are not measured.
+set -euo pipefail
+
+root=$(git rev-parse --show-toplevel)
+runs=${RUNS:-5}
+[ $# -eq 0 ] && set -- HEAD
+
+for rev in "$@"; do
+  sha=$(git -C "$root" rev-parse --short "$rev")
+  tree=$(mktemp -d)
+  trap 'git -C "$root" worktree remove --force "$tree"' EXIT
+  git -C "$root" worktree add --detach --quiet "$tree" "$sha"
+  cp "$root/bench/alloc_counter.rs" "$tree/src/"
+  echo 'mod alloc_counter;' >> "$tree/src/main.rs"
+  target="$root/target/bench/$sha"
+  if ! build=$(cargo build --release --manifest-path "$tree/Cargo.toml" --target-dir "$target" 2>&1); then
+    echo "$build" >&2
+    exit 1
+  fi
+  bin="$target/release/codecrafters-interpreter"
+
+  for script in "$root"/bench/*.lox; do
+    best=
+    for _ in $(seq "$runs"); do
+      start=$(date +%s%N)
+      "$bin" run "$script" > /dev/null 2> "$tree/stderr"
+      elapsed=$(( $(date +%s%N) - start ))
+      if [ -z "$best" ] || [ "$elapsed" -lt "$best" ]; then
+        best=$elapsed
+      fi
+    done
+    allocations=$(sed -n 's/^allocations: //p' "$tree/stderr")
+    awk -v rev="$sha" -v script="$(basename "$script")" -v ns="$best" -v allocations="$allocations" \
+      'BEGIN { printf "%s  %-10s %6.2fs %12s allocations\n", rev, script, ns / 1e9, allocations }'
+  done
+
+  git -C "$root" worktree remove --force "$tree"
+  trap - EXIT
+done
@@ -1,11 +1,18 @@
-use std::{borrow::Cow, cell::RefCell, collections::HashMap, process, rc::Rc};
+use std::{borrow::Cow, cell::RefCell, collections::HashMap, rc::Rc, slice};
 
-use crate::error_handler::{handle_error, ErrorType};
+use crate::error_handler::{handle_error, ErrorType, LoxResult};
+use crate::gc::{self, Node, Tracer};
 use crate::interpreter::block_scopes::BlockScopes;
+use crate::interpreter::Interpreter;
+use crate::module::ModuleRef;
+use crate::native::{has_native_properties, native_property};
 use crate::statements::FunctionDeclStatement;
-use crate::{error_handler::RUNTIME_ERROR_CODE};
-use crate::parser::expressions::{Expression, InstanceGetSetExpr};
-use crate::parser::declarations::{Object, RefObject, Type, ValueObjTrait};
+use crate::function::Function;
+use crate::parser::expressions::{Capture, Expression, InstanceGetSetExpr};
+use crate::parser::declarations::{Object, RefObject, Type, Value, ValueObjTrait};
+use crate::typecheck::{StaticType, TypeChecker};
+use crate::vm::chunk::OpCode;
+use crate::vm::emitter::{EmitResult, Emitter, Unsupported};
 
 #[derive(Clone)]
 pub struct Class {
@@ -13,7 +20,8 @@ pub struct Class {
     pub methods: HashMap<String, FunctionDeclStatement>,
     pub inherited_methods: HashMap<String, FunctionDeclStatement>,
     pub constructor: Option<FunctionDeclStatement>,
-    pub super_class: Option<Box<Class>>
+    pub super_class: Option<Box<Class>>,
+    pub fields: HashMap<String, StaticType>
 }
 
 
@@ -39,9 +47,19 @@ impl Object for Class  {
             methods: self.methods.clone(),
             inherited_methods: self.inherited_methods.clone(),
             constructor: self.constructor.clone(),
-            super_class: self.super_class.clone()
+            super_class: self.super_class.clone(),
+            fields: self.fields.clone()
         })
     }
+
+    fn trace(&self, tracer: &mut Tracer) {
+        for method in self.methods.values().chain(self.inherited_methods.values()).chain(self.constructor.iter()) {
+            method.function_decl.trace(tracer);
+        }
+        if let Some(super_class) = &self.super_class {
+            super_class.trace(tracer);
+        }
+    }
 }
 
 
@@ -60,17 +78,29 @@ impl Object for ClassInstance {
             attributes: self.attributes.clone(),
         })
     } 
+
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.attributes(&self.attributes);
+        tracer.class(&self.class);
+    }
 }
 
 impl ClassInstance {
-    pub fn get(&self, field: &String) -> Option<Box<dyn Object>> {
+    // Methods store a copy of the instance as their `this`, so instances are cycles the collector has to know of.
+    pub fn new(class: Class) -> ClassInstance {
+        let attributes = Rc::new(RefCell::new(HashMap::new()));
+        gc::track(Node::Attributes(attributes.clone()));
+        ClassInstance { class: Rc::new(class), attributes }
+    }
+
+    pub fn get(&self, field: &String) -> Option<Value> {
         if let Some(val) = self.attributes.borrow().get(field) {
-            return Some(val.borrow().dyn_clone());
+            return Some(val.borrow().clone());
         }
         None
     }
 
-    pub fn set(&mut self, field: &String, object: Box<dyn Object>) {
+    pub fn set(&mut self, field: &String, object: Value) {
         let mut attributes_mut = self.attributes.borrow_mut();
         if let Some(val) = attributes_mut.get(field) {
             let mut val_mut = val.borrow_mut();
@@ -108,51 +138,70 @@ impl ToString for ClassInstance {
 }
 
 impl Class {
-    pub fn call(&self, params: &Vec<Box<dyn Expression>>, out_func_state: &mut BlockScopes, line: &u32) -> ClassInstance {
-        let mut instance = ClassInstance {
-            class: Rc::new(self.clone()),
-            attributes: Rc::new(RefCell::new(HashMap::new())) 
-        };
-        let current_class = Box::new(self.clone());
+    pub fn bind_closure(&mut self, state: &mut BlockScopes) {
+        for method in self.methods.values_mut().chain(self.constructor.iter_mut()) {
+            method.function_decl.captures = method.capture_variables(state);
+        }
+    }
+
+    pub fn bind_module(&mut self, module: Option<ModuleRef>) {
+        for method in self.methods.values_mut() {
+            method.function_decl.module = module.clone();
+        }
+        if let Some(constructor) = &mut self.constructor {
+            constructor.function_decl.module = module;
+        }
+    }
 
+    // A new instance, with its initializer when the class has one.
+    pub fn instantiate(&self) -> (ClassInstance, Option<Function>) {
+        let mut instance = ClassInstance::new(self.clone());
+        let current_class = Box::new(self.clone());
         self.set_methods_on_instance(&mut instance, &current_class);
-        if let Some(_) = &self.constructor {
-            let func_obj = instance.get(&String::from("init")).unwrap();
-            let init_method = func_obj.as_function();
-            if let Some(init) = init_method {
-                init.call(params, out_func_state, line);
-            }
+        let init = match &self.constructor {
+            Some(_) => instance.get(&String::from("init")).and_then(|init| init.as_function().cloned()),
+            None => None
+        };
+        (instance, init)
+    }
+
+    pub fn call_with_args(&self, args: Vec<Value>, out_func_state: &mut BlockScopes, line: &u32) -> LoxResult<ClassInstance> {
+        let (instance, init) = self.instantiate();
+        if let Some(init) = init {
+            Interpreter::call_to_completion(out_func_state, &init, args, line, &Rc::new(self.name.clone()))?;
         }
-        instance
+        Ok(instance)
     }
 
     fn set_method_on_instance(instance: &mut ClassInstance, func_stmt: &FunctionDeclStatement) {
-        let mut func_copy = func_stmt.function_decl.clone();
-        if func_stmt.extern_variables.iter().any(|ident| ident.value == "this") {
-            let instance_copy: Box<dyn Object> = Box::new(instance.clone());
-            func_copy.extra_map.insert(String::from("this"), Rc::new(RefCell::new(instance_copy)));
-        }
-        instance.set(&func_stmt.function_decl.name, Box::new(func_copy));
+        let func_copy = Class::bind_method(func_stmt, instance, None);
+        instance.set(&func_stmt.function_decl.name, Value::obj(func_copy));
     }
 
     fn set_method_on_inherit_instance(&self, instance: &mut ClassInstance, parent_class: &Box<Class>, func_stmt: &FunctionDeclStatement) {
-        
-        let mut func_copy = func_stmt.function_decl.clone();
-        if func_stmt.extern_variables.iter().any(|ident| ident.value == "this") {
-            let instance_copy: Box<dyn Object> = Box::new(instance.clone());
-            func_copy.extra_map.insert(String::from("this"), Rc::new(RefCell::new(instance_copy)));
-        }
-        if func_stmt.extern_variables.iter().any(|ident| ident.value == "super") {
-            let mut parent_instance = ClassInstance {
-                class: Rc::new(*parent_class.clone()),
-                attributes: Rc::new(RefCell::new(HashMap::new())) 
-            };      
-
+        let parent_instance = func_stmt.captures.iter().any(|capture| capture.get() == Capture::Super).then(|| {
+            let mut parent_instance = ClassInstance::new(*parent_class.clone());
             self.set_methods_on_instance(&mut parent_instance, parent_class);
-            let parent_obj: Box<dyn Object> = Box::new(parent_instance.clone());
-            func_copy.extra_map.insert(String::from("super"), Rc::new(RefCell::new(parent_obj)));
+            parent_instance
+        });
+        let func_copy = Class::bind_method(func_stmt, instance, parent_instance);
+        instance.set(&func_stmt.function_decl.name, Value::obj(func_copy));
+    }
+
+    // Fills the `this` and `super` captures of a method with the instance it is bound to and its parent part.
+    fn bind_method(func_stmt: &FunctionDeclStatement, instance: &ClassInstance, parent_instance: Option<ClassInstance>) -> Function {
+        let mut func_copy = func_stmt.function_decl.clone();
+        let mut captures = func_copy.captures.to_vec();
+        for (cell, capture) in captures.iter_mut().zip(&func_stmt.captures) {
+            let value = match (capture.get(), &parent_instance) {
+                (Capture::This, _) => Value::obj(instance.clone()),
+                (Capture::Super, Some(parent)) => Value::obj(parent.clone()),
+                _ => continue
+            };
+            *cell = Some(Rc::new(RefCell::new(value)));
         }
-        instance.set(&func_stmt.function_decl.name, Box::new(func_copy));
+        func_copy.captures = captures.into();
+        func_copy
     }
 
     fn set_methods_on_instance(&self, instance: &mut ClassInstance, class: &Box<Class>) {
@@ -194,33 +243,57 @@ impl Class {
 
 
 impl Expression for InstanceGetSetExpr {
-    fn evaluate(&self, state_scope: &mut BlockScopes) -> Box<dyn Object> {
-        let mut obj = self.instance.evaluate(state_scope);
+    fn evaluate(&self, state_scope: &mut BlockScopes) -> LoxResult<Value> {
+        let mut obj = match state_scope.resume_partial(self) {
+            Some(mut partial) => partial.values.pop().unwrap(),
+            None => self.instance.evaluate(state_scope)?
+        };
+        if obj.get_type() == Type::NIL && self.is_null_safe() {
+            return Ok(Value::Nil);
+        }
         if obj.get_type() != Type::CLASSINSTANCE {
-            handle_error(&self.line, ErrorType::RuntimeError, 
+            if self.value_to_assign.is_none() {
+                let property = self.property.to_string();
+                if let Some(method) = native_property(&obj, &property) {
+                    return Ok(method);
+                }
+                if has_native_properties(&obj) {
+                    return handle_error(&self.line, ErrorType::RuntimeError, 
+                        format!("Undefined property '{}'", property).as_str());
+                }
+            }
+            return handle_error(&self.line, ErrorType::RuntimeError, 
                 "Can only access property on class instance");
         }
         
+        let instance = obj.clone();
         let class_instance: &mut ClassInstance = obj.as_class_instance().unwrap();
-        let (identifier, prop) = self.property.value_from_class_instance(class_instance, state_scope);
+        let (identifier, prop) = match self.property.value_from_class_instance(class_instance, state_scope) {
+            Err(error) if error.is_suspension() => {
+                state_scope.save_partial(self, Vec::from([instance]), false);
+                return Err(error);
+            },
+            result => result?
+        };
         
         if let Some(value) =  &self.value_to_assign {
-            let evaluated_value = value.evaluate(state_scope);
-            class_instance.set(&identifier, evaluated_value.dyn_clone());
-            return evaluated_value;
+            let evaluated_value = state_scope.evaluate_after(self, slice::from_ref(&instance), value.as_ref())?;
+            class_instance.set(&identifier, evaluated_value.clone());
+            return Ok(evaluated_value);
         }
         else {
             if let None = prop {
-                handle_error(&self.line, ErrorType::RuntimeError, 
+                return handle_error(&self.line, ErrorType::RuntimeError, 
                     format!("Undefined property '{}'", identifier).as_str());
             }
-            return prop.unwrap();
+            return Ok(prop.unwrap());
         } 
     }
 
 
-    fn value_from_class_instance(&self, _instance: &ClassInstance, _state_scope: &mut BlockScopes) -> (String, Option<Box<dyn Object>>) {
-        process::exit(RUNTIME_ERROR_CODE)
+    fn value_from_class_instance(&self, _instance: &ClassInstance, _state_scope: &mut BlockScopes) -> LoxResult<(String, Option<Value>)> {
+        handle_error(&self.line, ErrorType::RuntimeError, 
+            "Can only access property on class instance")
     }
 
     fn get_line(&self) -> u32 {
@@ -230,4 +303,52 @@ impl Expression for InstanceGetSetExpr {
     fn to_string(&self) -> String {
         self.instance.to_string()
     }
+
+    fn is_null_safe(&self) -> bool {
+        self.null_safe || self.instance.is_null_safe()
+    }
+
+    fn static_type(&self, checker: &mut TypeChecker) -> StaticType {
+        let owner = self.instance.static_type(checker);
+        let Some(value) = &self.value_to_assign else {
+            return self.property.member_type(&owner, checker);
+        };
+        let actual = value.static_type(checker);
+        if let StaticType::Instance(class_name) = &owner {
+            let field = self.property.to_string();
+            if let Some(expected) = checker.field_type(class_name, &field) {
+                if !checker.accepts(&expected, &actual) {
+                    checker.report(self.line, format!("Type mismatch: field '{}' of {} expects {} but got {}.",
+                        field, class_name, expected.name(), actual.name()));
+                }
+            }
+        }
+        actual
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        let name = self.property.identifier().ok_or(Unsupported::new("this property access", self.line))?;
+        if self.instance.identifier() == Some("super") {
+            if self.value_to_assign.is_some() {
+                return Err(Unsupported::new("assigning to a 'super' property", self.line));
+            }
+            return emitter.super_property(name, self.line);
+        }
+        self.instance.emit(emitter)?;
+        let nil_jump = self.is_null_safe().then(|| emitter.jump(OpCode::JumpIfNil(0), self.line));
+        let index = emitter.property(name)?;
+        match &self.value_to_assign {
+            Some(value) => {
+                value.emit(emitter)?;
+                emitter.op(OpCode::SetProperty(index), self.line);
+            },
+            None => {
+                emitter.op(OpCode::GetProperty(index), self.line);
+            }
+        }
+        if let Some(nil_jump) = nil_jump {
+            emitter.patch_jump(nil_jump);
+        }
+        Ok(())
+    }
 }
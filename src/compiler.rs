@@ -1,11 +1,16 @@
+use std::path::PathBuf;
+
 use crate::compiler::environment::Environment; 
+use crate::error_handler::{handle_error, ErrorType, LoxResult};
+use crate::parser::expressions::Resolution;
 use crate::parser::Parser;
 use crate::statements::controlflow_stmts::statement;
 use crate::statements::Statement;
 pub mod environment;
 pub struct Compiler<'a> {
     pub parser: Parser<'a>,
-    pub environment: Environment
+    pub environment: Environment,
+    pub source_path: PathBuf
 }
 
 impl Compiler<'_> {
@@ -19,14 +24,27 @@ impl Compiler<'_> {
     }
 
     pub fn new(parser: Parser<'_>) -> Compiler<'_> {
-        Compiler { parser, environment: Environment::new() }
+        Compiler { parser, environment: Environment::new(), source_path: PathBuf::new() }
     }
 
-    pub fn compile(&mut self) -> Vec<Box<dyn Statement>>  {
+    pub fn compile(&mut self) -> LoxResult<Vec<Box<dyn Statement>>>  {
         let mut stmts: Vec<Box<dyn Statement>> = Vec::new();
          while self.not_reach_end() {
-            stmts.append(&mut statement(self));
+            stmts.append(&mut statement(self)?);
+        }
+        self.check_resolved()?;
+        Ok(stmts)
+    }
+
+    // Every variable has to be found by the resolver: the interpreter has no
+    // lookup by name left for locals and captures.
+    fn check_resolved(&mut self) -> LoxResult<()> {
+        for identifier in self.parser.take_identifiers() {
+            if matches!(identifier.resolution.get(), Resolution::Unresolved) {
+                return handle_error(&identifier.line, ErrorType::SyntacticError, 
+                    format!("Error at '{}': Unresolved variable.", identifier.value).as_str());
+            }
         }
-        stmts
+        Ok(())
     }
 }
\ No newline at end of file
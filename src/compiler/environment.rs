@@ -1,5 +1,5 @@
-use crate::{error_handler::{handle_error, ErrorType}, parser::expressions::Identifier};
-use std::mem;
+use crate::{error_handler::{handle_error, ErrorType, LoxResult}, parser::expressions::{Capture, Identifier, Resolution}};
+use std::{cell::Cell, mem, rc::Rc};
 
 
 #[derive(PartialEq, Clone)]
@@ -22,7 +22,17 @@ struct Node {
     pub current_function: FunctionType,
     pub current_class: ClassType, 
     pub declarations: Vec<String>,
-    pub out_identifiers: Vec<Identifier>,
+    pub constants: Vec<String>,
+    // Filled in a function node: the cells its closure is given, in the order its upvalues index them.
+    pub captures: Vec<(String, Rc<Cell<Capture>>)>,
+    // Captures of names no scope had declared yet, made by functions declared in this
+    // scope; a later declaration of the name here, or in an enclosing scope, fills them in.
+    pub forwards: Vec<(String, usize, Rc<Cell<Capture>>)>,
+    pub generator: bool,
+    pub in_try: bool,
+    pub boundary: bool,
+    // A class node stands for the body of the method being compiled, so it binds `this` and `super`.
+    pub method: bool,
     pub parent: Option<Box<Node>>,
 }
 
@@ -32,78 +42,208 @@ impl Node {
         Node {
             current_class: ClassType::NONE,
             current_function: FunctionType::NONE,
-            out_identifiers: Vec::new(),
             declarations: Vec::new(),
+            constants: Vec::new(),
+            captures: Vec::new(),
+            forwards: Vec::new(),
+            generator: false,
+            in_try: false,
+            boundary: false,
+            method: false,
             parent: None
         }
     }
 
     pub fn new_class(&mut self, class: ClassType, class_name: &String)  {
-        self.declarations.push(class_name.clone());
+        self.declare(class_name);
         *self = Node {
             current_class: class,
             current_function: FunctionType::NONE,
-            out_identifiers: Vec::new(),
             declarations: Vec::new(),
+            constants: Vec::new(),
+            captures: Vec::new(),
+            forwards: Vec::new(),
+            generator: false,
+            in_try: false,
+            boundary: true,
+            method: true,
             parent: Some(Box::new(self.clone()))
         }
     }
 
     pub fn end_class(&mut self)  {
-        if let Some(parent) = &self.parent {
-            *self = *parent.clone();
-        }
+        self.end_block();
     }
 
-    pub fn new_func(&mut self, func_name: &String) {
-        self.declarations.push(func_name.clone());
+    pub fn new_func(&mut self, func_name: &String, generator: bool) {
+        self.declare(func_name);
         *self = Node {
             current_class: self.current_class.clone(),
             current_function: FunctionType::FUNCTION,
-            out_identifiers: Vec::new(),
             declarations: Vec::new(),
+            constants: Vec::new(),
+            captures: Vec::new(),
+            forwards: Vec::new(),
+            generator,
+            in_try: false,
+            boundary: true,
+            method: false,
             parent: Some(Box::new(self.clone()))
         }
     }
 
-    pub fn end_func(&mut self) -> Vec<Identifier> {
-        let out_decls =  mem::take(&mut self.out_identifiers);
+    pub fn end_func(&mut self) -> Vec<Rc<Cell<Capture>>> {
+        let captures = self.take_captures();
         if let Some(parent) = &self.parent {
             *self = *parent.clone();
         }
-        return out_decls;
+        captures
+    }
+
+    // Forward captures left unfilled in a function's own scope come from
+    // outside it, so they become captures of the function itself.
+    fn take_captures(&mut self) -> Vec<Rc<Cell<Capture>>> {
+        for (name, _, forward) in mem::take(&mut self.forwards) {
+            if let Resolution::Upvalue { index } = self.capture(&name) {
+                forward.set(Capture::Upvalue { index });
+            }
+        }
+        mem::take(&mut self.captures).into_iter().map(|(_, capture)| capture).collect()
     }
 
     pub fn new_block(&mut self) {
         *self = Node {
             current_class: self.current_class.clone(),
             current_function: self.current_function.clone(),
-            out_identifiers: Vec::new(),
             declarations: Vec::new(),
+            constants: Vec::new(),
+            captures: Vec::new(),
+            forwards: Vec::new(),
+            generator: self.generator,
+            in_try: self.in_try,
+            boundary: false,
+            method: false,
             parent: Some(Box::new(self.clone()))
         }
     }
 
-    pub fn end_block(&mut self) -> Vec<Identifier>  {
-        let out_decls =  mem::take(&mut self.out_identifiers);
+    pub fn end_block(&mut self) {
+        let forwards = mem::take(&mut self.forwards);
         if let Some(parent) = &self.parent {
             *self = *parent.clone();
         }
-        return out_decls;
+        if self.parent.is_some() {
+            self.forwards.extend(forwards.into_iter().map(|(name, depth, forward)| (name, depth + 1, forward)));
+        }
     }
 
+    // An initializer returns its instance, so it always captures `this` first.
     pub fn start_init_class_func(&mut self) {
         self.current_function = FunctionType::INITCLASSFUNC;
+        self.captures = Vec::from([(String::from("this"), Rc::new(Cell::new(Capture::This)))]);
+    }
+
+    // Declares a name in this scope and returns its slot, filling in the
+    // forward captures functions declared earlier made of it.
+    fn declare(&mut self, name: &String) -> Option<usize> {
+        let slot = self.next_slot();
+        if let Some(slot) = slot {
+            for (forward_name, depth, forward) in &self.forwards {
+                if forward_name == name {
+                    forward.set(Capture::Local { depth: *depth, slot });
+                }
+            }
+            self.forwards.retain(|(forward_name, _, _)| forward_name != name);
+        }
+        self.declarations.push(name.clone());
+        slot
+    }
+
+    fn is_declared(&self, name: &str) -> bool {
+        self.declarations.iter().any(|decl| decl == name)
+            || self.parent.as_ref().is_some_and(|parent| parent.is_declared(name))
+    }
+
+    pub fn is_constant(&self, name: &String) -> bool {
+        if self.declarations.contains(name) {
+            return self.constants.contains(name);
+        }
+        match &self.parent {
+            Some(parent) => parent.is_constant(name),
+            None => false
+        }
+    }
+
+    // Names declared in the root node, or nowhere, are globals. Past a function
+    // or class node the variable lives in an enclosing function and becomes
+    // one of the function's captures.
+    pub fn resolve(&mut self, name: &str) -> Resolution {
+        self.resolve_from(name, 0)
+    }
+
+    fn resolve_from(&mut self, name: &str, depth: usize) -> Resolution {
+        if let Some(slot) = self.declarations.iter().rposition(|decl| decl == name) {
+            return match &self.parent {
+                None => Resolution::Global,
+                Some(_) => Resolution::Local { depth, slot }
+            };
+        }
+        if self.boundary {
+            return self.capture(name);
+        }
+        match &mut self.parent {
+            Some(parent) => parent.resolve_from(name, depth + 1),
+            None => Resolution::Global
+        }
+    }
+
+    // Captures are resolved from the scope the function is declared in, where
+    // its closure is created; a name captured twice shares one upvalue. A name
+    // no scope declares yet is left to a later declaration in an enclosing
+    // scope, as when two local functions call each other, and is a global
+    // when there is none.
+    fn capture(&mut self, name: &str) -> Resolution {
+        if let Some(index) = self.captures.iter().position(|(captured, _)| captured == name) {
+            return Resolution::Upvalue { index };
+        }
+        let Some(parent) = self.parent.as_mut() else {
+            return Resolution::Global;
+        };
+        let capture = match (name, self.method) {
+            ("this", true) => Capture::This,
+            ("super", true) => Capture::Super,
+            _ if !parent.is_declared(name) => {
+                if parent.parent.is_none() {
+                    return Resolution::Global;
+                }
+                Capture::Global
+            },
+            _ => match parent.resolve(name) {
+                Resolution::Local { depth, slot } => Capture::Local { depth, slot },
+                Resolution::Upvalue { index } => Capture::Upvalue { index },
+                _ => return Resolution::Global
+            }
+        };
+        let capture = Rc::new(Cell::new(capture));
+        if capture.get() == Capture::Global {
+            parent.forwards.push((name.to_string(), 0, capture.clone()));
+        }
+        self.captures.push((name.to_string(), capture));
+        Resolution::Upvalue { index: self.captures.len() - 1 }
+    }
+
+    // Slot the next declaration of this node will get; globals have none.
+    pub fn next_slot(&self) -> Option<usize> {
+        self.parent.as_ref().map(|_| self.declarations.len())
     }
 
     pub fn new_class_func(&mut self) {
         self.current_function = FunctionType::CLASSFUNCTION;
     }
 
-    pub fn end_class_func(&mut self) -> Vec<Identifier> {
-        let identifiers =  mem::take(&mut self.out_identifiers);
+    pub fn end_class_func(&mut self) -> Vec<Rc<Cell<Capture>>> {
         self.current_function = FunctionType::NONE;
-        identifiers
+        self.take_captures()
     }
 
 }
@@ -121,53 +261,56 @@ impl Environment {
 
 
 
-    pub fn check_identifiers(&mut self, identifiers: Vec<Identifier>, expr_line: u32) {
+    pub fn check_identifiers(&mut self, identifiers: Vec<Identifier>, expr_line: u32) -> LoxResult<()> {
         for ident in identifiers {
             let ident_str = ident.value.clone();
             if ident_str == "this" {
                 if self.nodes_tree.current_class == ClassType::NONE {
-                    Environment::compile_keyword_class_err(expr_line, &ident_str);
-                }
-                else {
-                    self.nodes_tree.out_identifiers.push(ident.clone());
+                    return Environment::compile_keyword_class_err(expr_line, &ident_str);
                 }
             }
             else if ident_str == "super" {
                 if self.nodes_tree.current_class == ClassType::NONE {
-                    Environment::compile_keyword_class_err(expr_line, &ident_str);
+                    return Environment::compile_keyword_class_err(expr_line, &ident_str);
                 }
                 else if self.nodes_tree.current_class != ClassType::CHILDCLASS {
-                    Environment::compile_bad_class_super_err(expr_line);
-                }
-                else {
-                    self.nodes_tree.out_identifiers.push(ident.clone());
+                    return Environment::compile_bad_class_super_err(expr_line);
                 }
             }
-            else {
-                if !self.nodes_tree.declarations.contains(&ident_str) {
-                    self.nodes_tree.out_identifiers.push(ident.clone());
-                }
+            else if ident.modified && self.nodes_tree.is_constant(&ident_str) {
+                return handle_error(&ident.line, ErrorType::SyntacticError, 
+                    format!("Error at '{}': Cannot assign to a constant.", ident_str).as_str());
             }
+            ident.resolution.set(self.nodes_tree.resolve(&ident_str));
         }
-
+        Ok(())
     }
 
-    pub fn declaration(&mut self, var_name: &String, line: &u32, expr_identifiers: Vec<Identifier>, expr_line: u32) {
+    pub fn declaration(&mut self, var_name: &String, line: &u32, expr_identifiers: Vec<Identifier>, expr_line: u32) -> LoxResult<Option<usize>> {
 
         let decls = &self.nodes_tree.declarations;
+        if self.nodes_tree.constants.contains(var_name) {
+            return handle_error(line, ErrorType::SyntacticError, 
+                format!("Error at {}: Already a constant with this name in this scope.", var_name.clone()).as_str());
+        }
         if let Some(_) = &self.nodes_tree.parent {
             if decls.contains(var_name) {
-                handle_error(line, ErrorType::SyntacticError, 
+                return handle_error(line, ErrorType::SyntacticError, 
                 format!("Error at {}: Already a variable with this name in this scope.", var_name.clone()).as_str());
             }
             if expr_identifiers.iter().any(|ident| ident.value == *var_name ) {
-                handle_error(line, ErrorType::SyntacticError, 
+                return handle_error(line, ErrorType::SyntacticError, 
                     format!("Error at {}: Can't read local variable in its own initializer.", var_name.clone()).as_str());
             }
         }
-        self.check_identifiers(expr_identifiers, expr_line);
-        self.nodes_tree.declarations.push(var_name.clone());
-        
+        self.check_identifiers(expr_identifiers, expr_line)?;
+        Ok(self.nodes_tree.declare(var_name))
+    }
+
+    pub fn constant_declaration(&mut self, var_name: &String, line: &u32, expr_identifiers: Vec<Identifier>, expr_line: u32) -> LoxResult<Option<usize>> {
+        let slot = self.declaration(var_name, line, expr_identifiers, expr_line)?;
+        self.nodes_tree.constants.push(var_name.clone());
+        Ok(slot)
     }
 
     pub fn set_func_params(&mut self, params: &Vec<String>) {
@@ -179,34 +322,23 @@ impl Environment {
     }
 
     pub fn end_block(&mut self) {
-        let idents = self.nodes_tree.end_block();
-        for item in idents {
-            if !self.nodes_tree.declarations.contains(&item.value) {
-                self.nodes_tree.out_identifiers.push(item);
-            }
-        }
+        self.nodes_tree.end_block();
     }
 
-    pub fn start_function(&mut self, funcname: &String) {
-        self.nodes_tree.new_func(funcname);
+    pub fn start_function(&mut self, funcname: &String, generator: bool) -> Option<usize> {
+        let slot = self.nodes_tree.next_slot();
+        self.nodes_tree.new_func(funcname, generator);
+        slot
     }
 
-    pub fn end_function(&mut self) -> Vec<Identifier> {
-        let idents = self.nodes_tree.end_func();
-        if self.nodes_tree.current_class == ClassType::NONE {
-            return idents;
-        }
-        for item in &idents {
-            if (item.value == "this" || item.value == "super") && 
-                !self.nodes_tree.out_identifiers.iter().any(|ident| ident.value == item.value) {
-                self.nodes_tree.out_identifiers.push(item.clone());
-            }
-        }
-        idents
+    pub fn end_function(&mut self) -> Vec<Rc<Cell<Capture>>> {
+        self.nodes_tree.end_func()
     }
 
-    pub fn start_class(&mut self, classname: &String) {
+    pub fn start_class(&mut self, classname: &String) -> Option<usize> {
+        let slot = self.nodes_tree.next_slot();
         self.nodes_tree.new_class(ClassType::CLASS, classname);
+        slot
     }
 
     pub fn end_class(&mut self) {
@@ -217,7 +349,7 @@ impl Environment {
         self.nodes_tree.new_class_func();
     }
 
-    pub fn end_class_func(&mut self) -> Vec<Identifier>  {
+    pub fn end_class_func(&mut self) -> Vec<Rc<Cell<Capture>>> {
         self.nodes_tree.end_class_func()
     }
 
@@ -225,32 +357,57 @@ impl Environment {
         self.nodes_tree.start_init_class_func();
     }
 
-    pub fn start_child_class(&mut self, classname: &String) {
+    pub fn start_child_class(&mut self, classname: &String) -> Option<usize> {
+        let slot = self.nodes_tree.next_slot();
         self.nodes_tree.new_class(ClassType::CHILDCLASS, classname);
+        slot
     }
 
-    pub fn check_return_validity(&self, line: &u32) {
+    pub fn check_return_validity(&self, line: &u32) -> LoxResult<()> {
         if self.nodes_tree.current_function == FunctionType::NONE {
-            handle_error(&line, ErrorType::SyntacticError, 
+            return handle_error(line, ErrorType::SyntacticError, 
                 "Error at 'return': Can't return from top-level code.");
         }
+        Ok(())
+    }
+
+    // Returns the previous state so nested try statements can restore it.
+    pub fn set_in_try(&mut self, in_try: bool) -> bool {
+        mem::replace(&mut self.nodes_tree.in_try, in_try)
+    }
+
+    pub fn check_yield_validity(&self, line: &u32) -> LoxResult<()> {
+        if !self.nodes_tree.generator {
+            return handle_error(line, ErrorType::SyntacticError, 
+                "Error at 'yield': Can't yield outside a generator.");
+        }
+        Ok(())
+    }
+
+    // A returned call can reuse the caller's frame unless the frame still has
+    // work after it: an initializer returns `this`, a generator keeps its
+    // frame and a try must catch what the callee throws.
+    pub fn can_tail_call(&self) -> bool {
+        matches!(self.nodes_tree.current_function, FunctionType::FUNCTION | FunctionType::CLASSFUNCTION)
+            && !self.nodes_tree.generator && !self.nodes_tree.in_try
     }
 
-    pub fn check_constructor_return_validity(&self, line: &u32) {
+    pub fn check_constructor_return_validity(&self, line: &u32) -> LoxResult<()> {
         if self.nodes_tree.current_function == FunctionType::INITCLASSFUNC {
-            handle_error(line, ErrorType::SyntacticError, 
+            return handle_error(line, ErrorType::SyntacticError, 
                 "Error at 'return': Can't return a value from an initializer");
         }
+        Ok(())
     }
 
-    fn compile_bad_class_super_err(line: u32) {
+    fn compile_bad_class_super_err(line: u32) -> LoxResult<()> {
         handle_error(&line, ErrorType::SyntacticError, 
-    "Error at 'super': Can't use 'super' in a class with no superclass");
+    "Error at 'super': Can't use 'super' in a class with no superclass")
     }
 
-    fn compile_keyword_class_err(expr_line: u32, keyword: &str) {
+    fn compile_keyword_class_err(expr_line: u32, keyword: &str) -> LoxResult<()> {
         handle_error(&expr_line, ErrorType::SyntacticError, 
-        format!("Error at '{}': Can't use '{}' outside of a class.", keyword, keyword).as_str());
+        format!("Error at '{}': Can't use '{}' outside of a class.", keyword, keyword).as_str())
     }
 
 }
\ No newline at end of file
@@ -0,0 +1,91 @@
+use std::borrow::Cow;
+use std::rc::Rc;
+
+use crate::parser::declarations::{List, Object, Type, Value, ValueObjTrait};
+
+pub struct VariantData {
+    pub enum_name: Rc<String>,
+    pub name: String,
+    pub ordinal: usize
+}
+
+// Variants are shared through `Rc`, so two variants are equal only when they are the same one.
+#[derive(Clone)]
+pub struct EnumVariant (pub Rc<VariantData>);
+
+#[derive(Clone)]
+pub struct Enum {
+    pub name: Rc<String>,
+    pub variants: Rc<Vec<EnumVariant>>
+}
+
+impl Object for Enum {
+    fn to_str(&self) -> Cow<'static, str> {
+        Cow::Owned(format!("<enum {}>", self.name))
+    }
+
+    fn get_type(&self) -> Type {
+        Type::ENUM
+    }
+
+    fn dyn_clone(&self) -> Box<dyn Object> {
+        Box::new(self.clone())
+    }
+}
+
+impl Object for EnumVariant {
+    fn to_str(&self) -> Cow<'static, str> {
+        Cow::Owned(format!("{}.{}", self.0.enum_name, self.0.name))
+    }
+
+    fn get_type(&self) -> Type {
+        Type::ENUMVARIANT
+    }
+
+    fn dyn_clone(&self) -> Box<dyn Object> {
+        Box::new(self.clone())
+    }
+}
+
+impl ValueObjTrait for Enum {
+    fn as_enum(&self) -> Option<&Enum> {
+        Some(self)
+    }
+}
+
+impl ValueObjTrait for EnumVariant {
+    fn as_enum_variant(&self) -> Option<&EnumVariant> {
+        Some(self)
+    }
+}
+
+impl ToString for Enum {
+    fn to_string(&self) -> String {
+        self.to_str().to_string()
+    }
+}
+
+impl ToString for EnumVariant {
+    fn to_string(&self) -> String {
+        self.to_str().to_string()
+    }
+}
+
+impl Enum {
+    pub fn new(name: &String, variant_names: &[String]) -> Enum {
+        let enum_name = Rc::new(name.clone());
+        let variants = variant_names.iter().enumerate().map(|(ordinal, variant)| {
+            EnumVariant(Rc::new(VariantData { enum_name: enum_name.clone(), name: variant.clone(), ordinal }))
+        }).collect();
+        Enum { name: enum_name, variants: Rc::new(variants) }
+    }
+
+    pub fn variant(&self, name: &str) -> Option<&EnumVariant> {
+        self.variants.iter().find(|variant| variant.0.name == name)
+    }
+
+    pub fn values(&self) -> List {
+        let values: Vec<Value> = self.variants.iter().map(|variant| Value::obj(variant.clone())).collect();
+        List::new(values)
+    }
+}
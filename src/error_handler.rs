@@ -1,26 +1,191 @@
-use std::{io::{stderr, Write}, process};
+use std::cell::RefCell;
+use std::fmt;
 
+use thiserror::Error;
 
+use crate::parser::declarations::{Object, Type, Value};
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ErrorType {
+    LexicalError,
     SyntacticError,
-    RuntimeError
+    TypeError,
+    RuntimeError,
+    // Not an error: suspends a fiber whose `receive()` has to wait or that yields, see `Generator::resume_with`.
+    FiberBlocked,
+    // Not an error: unwinds a statement whose call goes on running on the frame stack, see `Interpreter::call`.
+    Suspended
 }
 
 pub const RUNTIME_ERROR_CODE: i32 = 70;
 pub const SYNTAXIC_ERROR_CODE: i32 = 65;
 pub const LEXICAL_ERROR_CODE: i32 = 65;
 
+pub type LoxResult<T> = Result<T, LoxError>;
+
+// Longest traceback printed; runs of the same frame count as one line.
+const TRACE_LIMIT: usize = 30;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub line: u32,
+    pub file: Option<String>
+}
+
+// One entry of a traceback: the function called and where it was called from,
+// with the number of calls it replaced through tail calls.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallFrame {
+    pub name: String,
+    pub span: Span,
+    pub elided: usize
+}
+
+// A value raised by a Lox `throw` statement.
+pub struct ThrownValue (pub Box<Value>);
+
+#[derive(Debug, Error)]
+#[error("{}", self.report())]
+pub struct LoxError {
+    pub kind: ErrorType,
+    pub message: String,
+    pub span: Span,
+    pub thrown: Option<ThrownValue>,
+    pub fiber: Option<String>,
+    // Calls active where the error was raised, innermost first; filled in by the innermost call it leaves.
+    pub trace: Box<[CallFrame]>
+}
+
+thread_local! {
+    static CURRENT_FILE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
 
-pub fn handle_error(line: &u32, error_type: ErrorType, error_text: &str) -> ! {
-    let mut stderr = stderr();
-    match error_type {
-        ErrorType::RuntimeError => {
-            let _ = stderr.write(format!("{error_text}\n[line {line}]\n").as_bytes());
-            process::exit(RUNTIME_ERROR_CODE)
-        },
-        ErrorType::SyntacticError => {
-            let _ = stderr.write(format!("[line {line}] {error_text}\n").as_bytes());
-            process::exit(SYNTAXIC_ERROR_CODE)
+impl fmt::Display for Span {
+    // Errors raised outside the entry file name the module they come from.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "line {} in {}", self.line, file),
+            None => write!(f, "line {}", self.line)
         }
     }
 }
+
+impl fmt::Debug for ThrownValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0.to_str())
+    }
+}
+
+impl LoxError {
+    pub fn new(line: u32, kind: ErrorType, message: &str) -> LoxError {
+        LoxError {
+            kind,
+            message: message.to_string(),
+            span: Span { line, file: current_file() },
+            thrown: None,
+            fiber: None,
+            trace: Box::default()
+        }
+    }
+
+    pub fn thrown(value: Value, line: u32) -> LoxError {
+        let mut error = LoxError::new(line, ErrorType::RuntimeError, &value.to_str());
+        error.thrown = Some(ThrownValue(Box::new(value)));
+        error
+    }
+
+    pub fn suspended() -> LoxError {
+        LoxError {
+            kind: ErrorType::Suspended,
+            message: String::new(),
+            span: Span { line: 0, file: None },
+            thrown: None,
+            fiber: None,
+            trace: Box::default()
+        }
+    }
+
+    // Either way, what the statements being unwound had done is kept to resume them with.
+    pub fn is_suspension(&self) -> bool {
+        matches!(self.kind, ErrorType::Suspended | ErrorType::FiberBlocked)
+    }
+
+    // Errors escaping a fiber belong to the scheduler, a `try` of another fiber never sees them.
+    pub fn is_catchable(&self) -> bool {
+        self.kind == ErrorType::RuntimeError && self.fiber.is_none()
+    }
+
+    fn report(&self) -> String {
+        match self.kind {
+            ErrorType::LexicalError => format!("[{}] Error: {}", self.span, self.message),
+            ErrorType::SyntacticError => format!("[{}] {}", self.span, self.message),
+            ErrorType::TypeError => format!("[{}] Type error: {}", self.span, self.message),
+            ErrorType::RuntimeError | ErrorType::FiberBlocked | ErrorType::Suspended => {
+                let (message, span) = match &self.thrown {
+                    Some(value) => uncaught_value_report(*value.0.clone(), &self.span),
+                    None => (self.message.clone(), self.span.clone())
+                };
+                let mut report = format!("{message}\n[{span}]");
+                self.write_trace(&mut report);
+                if let Some(fiber) = &self.fiber {
+                    report.push_str(&format!("\nin {fiber}"));
+                }
+                report
+            }
+        }
+    }
+}
+
+impl LoxError {
+    // Deep recursion repeats the same frame thousands of times, so a run of it is printed once.
+    fn write_trace(&self, report: &mut String) {
+        let mut frames = self.trace.iter().peekable();
+        let mut printed = 0;
+        while let Some(frame) = frames.next() {
+            if printed == TRACE_LIMIT {
+                report.push_str(&format!("\n... {} more calls", frames.count() + 1));
+                return;
+            }
+            report.push_str(&format!("\nat {}() [{}]", frame.name, frame.span));
+            if frame.elided > 0 {
+                report.push_str(&format!("\n... {} tail calls elided", frame.elided));
+            }
+            let mut repeats = 0;
+            while frames.next_if(|next| *next == frame).is_some() {
+                repeats += 1;
+            }
+            if repeats > 0 {
+                report.push_str(&format!("\n... repeated {} more times", repeats));
+            }
+            printed += 1;
+        }
+    }
+}
+
+pub fn handle_error<T>(line: &u32, error_type: ErrorType, error_text: &str) -> LoxResult<T> {
+    Err(LoxError::new(*line, error_type, error_text))
+}
+
+pub fn current_file() -> Option<String> {
+    CURRENT_FILE.with_borrow(|file| file.clone())
+}
+
+pub fn set_current_file(file: Option<String>) {
+    CURRENT_FILE.set(file);
+}
+
+fn uncaught_value_report(mut value: Value, span: &Span) -> (String, Span) {
+    if value.get_type() != Type::CLASSINSTANCE {
+        return (value.to_str().to_string(), span.clone());
+    }
+    let instance = value.as_class_instance().unwrap();
+    let message = match instance.get(&String::from("message")) {
+        Some(message) => message.to_str().to_string(),
+        None => return (instance.to_str().to_string(), span.clone())
+    };
+    match instance.get(&String::from("line")).and_then(|line| line.as_number()) {
+        Some(error_line) => (message, Span { line: error_line as u32, file: span.file.clone() }),
+        None => (message, span.clone())
+    }
+}
@@ -0,0 +1,235 @@
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::error_handler::{handle_error, ErrorType, LoxResult};
+use crate::function::Function;
+use crate::generator::Generator;
+use crate::interpreter::block_scopes::{ActivationKind, BlockScopes, CallSite};
+use crate::parser::declarations::{Object, Type, Value, ValueObjTrait};
+
+pub struct FiberData {
+    pub id: usize,
+    pub body: Generator,
+    blocked_on: RefCell<Option<Channel>>,
+    blocked_line: Cell<u32>,
+    calls: RefCell<Vec<CallSite>>,
+    active: Cell<bool>
+}
+
+#[derive(Clone)]
+pub struct Fiber (pub Rc<FiberData>);
+
+#[derive(Clone)]
+pub struct Channel (pub Rc<RefCell<VecDeque<Value>>>);
+
+struct Scheduler {
+    fibers: VecDeque<Fiber>,
+    current: Option<Fiber>,
+    next_id: usize
+}
+
+thread_local! {
+    static SCHEDULER: RefCell<Scheduler> = const {
+        RefCell::new(Scheduler { fibers: VecDeque::new(), current: None, next_id: 1 })
+    };
+}
+
+impl Object for Fiber {
+    fn to_str(&self) -> Cow<'static, str> {
+        Cow::Owned(format!("<fiber {}>", self.name()))
+    }
+
+    fn get_type(&self) -> Type {
+        Type::FIBER
+    }
+
+    fn dyn_clone(&self) -> Box<dyn Object> {
+        Box::new(self.clone())
+    }
+}
+
+impl ValueObjTrait for Fiber {
+    fn as_fiber(&self) -> Option<&Fiber> {
+        Some(self)
+    }
+}
+
+impl ToString for Fiber {
+    fn to_string(&self) -> String {
+        self.to_str().to_string()
+    }
+}
+
+impl Object for Channel {
+    fn to_str(&self) -> Cow<'static, str> {
+        Cow::Borrowed("<channel>")
+    }
+
+    fn get_type(&self) -> Type {
+        Type::CHANNEL
+    }
+
+    fn dyn_clone(&self) -> Box<dyn Object> {
+        Box::new(self.clone())
+    }
+}
+
+impl ValueObjTrait for Channel {
+    fn as_channel(&self) -> Option<&Channel> {
+        Some(self)
+    }
+}
+
+impl ToString for Channel {
+    fn to_string(&self) -> String {
+        self.to_str().to_string()
+    }
+}
+
+impl Fiber {
+    pub fn spawn(function: &Function, args: Vec<Value>) -> Fiber {
+        let body = Generator::new(function, args);
+        body.0.borrow_mut().fiber = true;
+        SCHEDULER.with_borrow_mut(|scheduler| {
+            let fiber = Fiber(Rc::new(FiberData {
+                id: scheduler.next_id,
+                body,
+                blocked_on: RefCell::new(None),
+                blocked_line: Cell::new(0),
+                calls: RefCell::new(Vec::new()),
+                active: Cell::new(false)
+            }));
+            scheduler.next_id += 1;
+            scheduler.fibers.push_back(fiber.clone());
+            fiber
+        })
+    }
+
+    pub fn name(&self) -> String {
+        format!("{} ({})", self.0.id, self.0.body.0.borrow().function.name)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.0.body.is_done()
+    }
+
+    fn is_runnable(&self) -> bool {
+        if self.is_done() || self.0.active.get() {
+            return false;
+        }
+        match self.0.blocked_on.borrow().as_ref() {
+            Some(channel) => !channel.is_empty(),
+            None => true
+        }
+    }
+
+    // Runs the fiber until it yields, blocks or finishes.
+    fn run_slice(&self, state: &mut BlockScopes) -> LoxResult<()> {
+        self.0.blocked_on.replace(None);
+        self.0.active.set(true);
+        let previous = SCHEDULER.with_borrow_mut(|scheduler| scheduler.current.replace(self.clone()));
+        let caller_calls = state.swap_calls(self.0.calls.take());
+        let result = self.0.body.resume(state);
+        self.0.calls.replace(state.swap_calls(caller_calls));
+        SCHEDULER.with_borrow_mut(|scheduler| scheduler.current = previous);
+        self.0.active.set(false);
+        result.map(|_| ()).map_err(|mut error| {
+            error.fiber.get_or_insert_with(|| format!("fiber {}", self.name()));
+            error
+        })
+    }
+}
+
+impl Channel {
+    pub fn new() -> Channel {
+        Channel(Rc::new(RefCell::new(VecDeque::new())))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().is_empty()
+    }
+
+    pub fn send(&self, value: Value) {
+        self.0.borrow_mut().push_back(value);
+    }
+
+    pub fn receive(&self, state: &mut BlockScopes, line: &u32) -> LoxResult<Value> {
+        if self.is_empty() {
+            match current_fiber().filter(|_| can_suspend(state)) {
+                Some(fiber) => {
+                    fiber.0.blocked_on.replace(Some(self.clone()));
+                    fiber.0.blocked_line.set(*line);
+                    return handle_error(line, ErrorType::FiberBlocked, "Fiber is blocked.");
+                },
+                None => run_scheduler(state, line, || !self.is_empty())?
+            }
+        }
+        Ok(self.0.borrow_mut().pop_front().unwrap())
+    }
+}
+
+pub fn current_fiber() -> Option<Fiber> {
+    SCHEDULER.with_borrow(|scheduler| scheduler.current.clone())
+}
+
+// A fiber is suspended along with the calls its body made, but not through
+// native code calling into Lox or a generator it resumed: the Rust stack is
+// in use there, so other fibers are run in place instead.
+pub fn can_suspend(state: &BlockScopes) -> bool {
+    state.activations.get(state.base).is_some_and(|activation| activation.kind == ActivationKind::Fiber)
+}
+
+// `yield()` outside a fiber's own body gives every other fiber one turn.
+pub fn yield_in_place(state: &mut BlockScopes) -> LoxResult<()> {
+    let fibers: Vec<Fiber> = SCHEDULER.with_borrow(|scheduler| scheduler.fibers.iter().cloned().collect());
+    for fiber in fibers {
+        if fiber.is_runnable() {
+            fiber.run_slice(state)?;
+        }
+    }
+    remove_finished();
+    Ok(())
+}
+
+// Runs fibers round-robin until `ready` holds; reports a deadlock when every
+// remaining fiber is blocked.
+pub fn run_scheduler<F>(state: &mut BlockScopes, line: &u32, ready: F) -> LoxResult<()>
+where F: Fn() -> bool
+{
+    while !ready() {
+        let next = SCHEDULER.with_borrow_mut(|scheduler| {
+            let position = scheduler.fibers.iter().position(|fiber| fiber.is_runnable())?;
+            let fiber = scheduler.fibers.remove(position)?;
+            scheduler.fibers.push_back(fiber.clone());
+            Some(fiber)
+        });
+        match next {
+            Some(fiber) => fiber.run_slice(state)?,
+            None => return report_deadlock(line)
+        }
+        remove_finished();
+    }
+    Ok(())
+}
+
+// Called once the main program has finished: every spawned fiber runs to completion.
+pub fn run_all(state: &mut BlockScopes) -> LoxResult<()> {
+    run_scheduler(state, &0, || SCHEDULER.with_borrow(|scheduler| scheduler.fibers.is_empty()))
+}
+
+fn remove_finished() {
+    SCHEDULER.with_borrow_mut(|scheduler| scheduler.fibers.retain(|fiber| !fiber.is_done()));
+}
+
+fn report_deadlock(line: &u32) -> LoxResult<()> {
+    let blocked = SCHEDULER.with_borrow(|scheduler| {
+        scheduler.fibers.iter().find(|fiber| !fiber.0.active.get()).cloned()
+    });
+    let line = match &blocked {
+        Some(fiber) if *line == 0 => fiber.0.blocked_line.get(),
+        _ => *line
+    };
+    handle_error(&line, ErrorType::RuntimeError, "Deadlock: all fibers are blocked.")
+}
@@ -1,23 +1,40 @@
 use std::borrow::Cow;
-use std::collections::HashMap;
 use std::rc::Rc;
 
-use crate::error_handler::{handle_error, ErrorType};
+use crate::error_handler::{handle_error, ErrorType, LoxResult};
+use crate::gc::Tracer;
 use crate::interpreter::block_scopes::BlockScopes;
 use crate::interpreter::Interpreter;
-use crate::parser::declarations::{Number, Object, RefObject, ValueObjTrait, NIL};
-use crate::parser::expressions::{Expression};
+use crate::module::ModuleRef;
+use crate::parser::declarations::{Object, RefObject, Value, ValueObjTrait};
 use crate::statements::{Statement};
+use crate::typecheck::Signature;
 use crate::parser::declarations::Type;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 
+// A call returned from a function body, run by its caller in the same frame.
+pub struct TailCall {
+    pub function: Function,
+    pub args: Vec<Value>,
+    pub line: u32
+}
+
+pub enum CallOutcome {
+    Value(Value),
+    TailCall(TailCall)
+}
+
 #[derive(Clone)]
 pub struct Function {
     pub name: Rc<String>,
     pub params_names: Rc<Vec<String>>,
     pub statements: Rc<Vec<Box<dyn Statement>>>,
-    pub extra_map: HashMap<String, RefObject>
+    pub captures: Rc<[Option<RefObject>]>,
+    pub module: Option<ModuleRef>,
+    pub signature: Rc<Signature>,
+    pub generator: bool,
+    pub initializer: bool
 }
 
 impl Object for Function  {
@@ -27,24 +44,18 @@ impl Object for Function  {
     }
 
     fn dyn_clone(&self) -> Box<dyn Object> {
-
-        let mut new_extramap: HashMap<String, RefObject> = HashMap::new();
-        for (k, v) in self.extra_map.iter() {
-            new_extramap.insert(k.to_string(), v.clone());
-        }
-        Box::new(
-            Function {
-                name: self.name.clone(),
-                params_names: self.params_names.clone(),
-                statements: self.statements.clone(),
-                extra_map: new_extramap
-            }
-        )
+        Box::new(self.clone())
     }
 
     fn to_str(&self) -> std::borrow::Cow<'static, str> {
         Cow::Owned(format!("<fn {}>", self.name))
     }
+
+    fn trace(&self, tracer: &mut Tracer) {
+        for cell in self.captures.iter().flatten() {
+            tracer.cell(cell);
+        }
+    }
 }
 
 impl ValueObjTrait for Function {
@@ -62,61 +73,23 @@ impl ToString for Function {
 
 impl Function {
 
-    pub fn call(&self, params: &Vec<Box<dyn Expression>>, out_func_state: &mut BlockScopes, line: &u32) -> Box<dyn Object> {
-        let (expect_params_len, recv_params_len) = (self.params_names.len(), params.len());
-        if expect_params_len != recv_params_len {
-            handle_error(line, ErrorType::RuntimeError, 
-                format!("Expected {} arguments but got {}", expect_params_len, recv_params_len).as_str());        }
-        if self.name.as_str() == "clock" {
-            return Box::new(Number(clock() as f64));
-        }
-        out_func_state.start_child_block();
-        let return_key = String::from("return");
-        out_func_state.set_init_variable(&return_key, Box::new(NIL));
-        for (param_name, param_val) in self.params_names.iter().zip(params.iter()) {
-            let param_value = param_val.evaluate(out_func_state);
-            out_func_state.set_init_variable(param_name, param_value);
-        }
-
-        let mut out_variables_to_edit = Vec::new();
-        let depth = out_func_state.depth;
-        for (key, value) in self.extra_map.iter()  {
-            if let None = out_func_state.get_variable_from(key, depth) {
-                let var_value = value.borrow();
-                out_func_state.set_init_variable(key, var_value.dyn_clone());
-                out_variables_to_edit.push(key);
-            }
-        }
-        Interpreter::run(out_func_state, &self.statements);
+    // Calls the function from native code, see `Interpreter::call_to_completion`.
+    pub fn call_with_args(&self, args: Vec<Value>, out_func_state: &mut BlockScopes, line: &u32) -> LoxResult<Value> {
+        Interpreter::call_to_completion(out_func_state, self, args, line, &self.name)
+    }
 
-        for var in out_variables_to_edit {
-            let new_value = out_func_state.get_variable(var).unwrap();
-            let mut val_mut = self.extra_map.get(var).unwrap().borrow_mut();
-            *val_mut = new_value;
-        }
-        let mut ret_value = match out_func_state.get_variable(&return_key) {
-            Some(ret_val ) => ret_val,
-            None => Box::new(NIL)
-        };
-        if self.name.to_string() == "init" {
-            ret_value = return_instance_on_func(out_func_state, depth);
+    pub fn check_arity(&self, recv_params_len: usize, line: &u32) -> LoxResult<()> {
+        let expect_params_len = self.params_names.len();
+        if expect_params_len != recv_params_len {
+            return handle_error(line, ErrorType::RuntimeError, 
+                format!("Expected {} arguments but got {}", expect_params_len, recv_params_len).as_str());
         }
-        
-        out_func_state.end_child_block();
-        ret_value
+        Ok(())
     }
 
 }
 
-fn return_instance_on_func(out_func_state: &mut BlockScopes, depth: usize) -> Box<dyn Object> {
-    if let Some(instance) = out_func_state.get_variable_from(&String::from("this"), depth) {
-        return instance;
-    }
-    return Box::new(NIL)
-}
-
-
-fn clock() -> u64 {
+pub fn clock() -> u64 {
     match SystemTime::now().duration_since(UNIX_EPOCH) {
         Ok(n) => n.as_secs(),
         Err(_) => panic!("SystemTime before UNIX EPOCH!"),
@@ -128,6 +101,10 @@ pub fn clock_declaration() -> Function {
         name: "clock".to_string().into(), 
         params_names: Vec::new().into(), 
         statements: Rc::new(Vec::new()),
-        extra_map: HashMap::new()
+        captures: Rc::from([]),
+        module: None,
+        signature: Rc::new(Signature::default()),
+        generator: false,
+        initializer: false
     }
 }
\ No newline at end of file
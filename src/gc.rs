@@ -0,0 +1,317 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::mem;
+use std::rc::{Rc, Weak};
+
+use crate::class::Class;
+use crate::parser::declarations::{MapEntries, Object, RefObject, Value};
+use crate::vm::value::{ObjInstance, Upvalue, Value as VmValue};
+
+const INITIAL_THRESHOLD: usize = 1024;
+
+// The containers values can form cycles through: variable cells, instance
+// attributes, lists and maps. Classes are traced for the cells their methods
+// captured, but hold nothing mutable themselves. The bytecode VM's instances
+// and captured variables are containers too.
+#[derive(Clone)]
+pub enum Node {
+    Cell(RefObject),
+    Attributes(Rc<RefCell<HashMap<String, RefObject>>>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<MapEntries>>),
+    Class(Rc<Class>),
+    Instance(Rc<ObjInstance>),
+    Upvalue(Rc<RefCell<Upvalue>>)
+}
+
+enum WeakNode {
+    Cell(Weak<RefCell<Value>>),
+    Attributes(Weak<RefCell<HashMap<String, RefObject>>>),
+    List(Weak<RefCell<Vec<Value>>>),
+    Map(Weak<RefCell<MapEntries>>),
+    Instance(Weak<ObjInstance>),
+    Upvalue(Weak<RefCell<Upvalue>>)
+}
+
+// Every container a cycle can go through, held weakly so tracking doesn't keep anything alive.
+struct Heap {
+    tracked: Vec<WeakNode>,
+    threshold: usize,
+    stress: bool,
+    collecting: bool
+}
+
+thread_local! {
+    static HEAP: RefCell<Heap> = const {
+        RefCell::new(Heap { tracked: Vec::new(), threshold: INITIAL_THRESHOLD, stress: false, collecting: false })
+    };
+}
+
+// Collects on every tracked allocation, so objects wrongly left unrooted are cleared at once.
+pub fn set_stress(stress: bool) {
+    HEAP.with_borrow_mut(|heap| heap.stress = stress);
+}
+
+// Registers a new container; a collection runs once enough of them piled up since the last one.
+pub fn track(node: Node) {
+    let due = HEAP.with_borrow_mut(|heap| {
+        heap.tracked.push(node.downgrade());
+        !heap.collecting && (heap.stress || heap.tracked.len() >= heap.threshold)
+    });
+    if due {
+        collect();
+    }
+}
+
+// Containers are told apart by address, which needs no SipHash.
+#[derive(Default)]
+struct AddressHasher(u64);
+
+impl Hasher for AddressHasher {
+    fn write(&mut self, _bytes: &[u8]) {
+        unreachable!("only addresses are hashed")
+    }
+
+    fn write_usize(&mut self, address: usize) {
+        self.0 = (address as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0.rotate_left(26)
+    }
+}
+
+// Collects whatever the visitor reports as held by a value.
+pub struct Tracer {
+    found: Vec<Node>
+}
+
+impl Tracer {
+    pub fn cell(&mut self, cell: &RefObject) {
+        self.found.push(Node::Cell(cell.clone()));
+    }
+
+    pub fn attributes(&mut self, attributes: &Rc<RefCell<HashMap<String, RefObject>>>) {
+        self.found.push(Node::Attributes(attributes.clone()));
+    }
+
+    pub fn list(&mut self, items: &Rc<RefCell<Vec<Value>>>) {
+        self.found.push(Node::List(items.clone()));
+    }
+
+    pub fn map(&mut self, entries: &Rc<RefCell<MapEntries>>) {
+        self.found.push(Node::Map(entries.clone()));
+    }
+
+    pub fn class(&mut self, class: &Rc<Class>) {
+        self.found.push(Node::Class(class.clone()));
+    }
+
+    pub fn instance(&mut self, instance: &Rc<ObjInstance>) {
+        self.found.push(Node::Instance(instance.clone()));
+    }
+
+    pub fn upvalue(&mut self, upvalue: &Rc<RefCell<Upvalue>>) {
+        self.found.push(Node::Upvalue(upvalue.clone()));
+    }
+}
+
+// Mark and sweep over the tracked containers and everything they hold. The
+// roots are the references the heap can't account for: the cells of
+// `BlockScopes`, the values native call frames are working on, and the
+// objects the collector doesn't trace. A container holding more references
+// than the traced ones pointing at it is one of them, so collecting is safe at
+// any point. Unreachable containers are emptied, which breaks their cycles and
+// lets the reference counts free them. Returns how many were reclaimed.
+pub fn collect() -> usize {
+    let Some(tracked) = HEAP.with_borrow_mut(|heap| {
+        (!heap.collecting).then(|| {
+            heap.collecting = true;
+            mem::take(&mut heap.tracked)
+        })
+    }) else {
+        return 0;
+    };
+
+    // Every container reachable from a tracked one, each held once by `nodes`.
+    // The children of node `i` are `edges[starts[i]..starts[i + 1]]`.
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut indices: HashMap<usize, usize, BuildHasherDefault<AddressHasher>> = HashMap::default();
+    let mut index_of = |node: Node, nodes: &mut Vec<Node>| {
+        *indices.entry(node.address()).or_insert_with(|| {
+            nodes.push(node);
+            nodes.len() - 1
+        })
+    };
+    for node in tracked.iter().filter_map(WeakNode::upgrade) {
+        index_of(node, &mut nodes);
+    }
+    let mut starts: Vec<usize> = vec![0];
+    let mut edges: Vec<usize> = Vec::new();
+    let mut opaque: Vec<bool> = Vec::new();
+    let mut tracer = Tracer { found: Vec::new() };
+    while opaque.len() < nodes.len() {
+        // A container borrowed right now can't be looked into, so it is kept with all it holds.
+        opaque.push(!nodes[opaque.len()].trace(&mut tracer));
+        for child in tracer.found.drain(..) {
+            edges.push(index_of(child, &mut nodes));
+        }
+        starts.push(edges.len());
+    }
+
+    // Mark from the containers referenced from outside the traced graph.
+    let mut internal = vec![0; nodes.len()];
+    for child in &edges {
+        internal[*child] += 1;
+    }
+    let mut marked = vec![false; nodes.len()];
+    let mut stack: Vec<usize> = (0..nodes.len())
+        .filter(|index| opaque[*index] || nodes[*index].strong_count() - 1 > internal[*index])
+        .collect();
+    while let Some(index) = stack.pop() {
+        if !mem::replace(&mut marked[index], true) {
+            stack.extend(edges[starts[index]..starts[index + 1]].iter().filter(|child| !marked[**child]));
+        }
+    }
+
+    // Sweep: empty the unreachable containers and keep tracking the rest.
+    let mut reclaimed = 0;
+    let mut survivors = Vec::new();
+    for (node, marked) in nodes.iter().zip(marked) {
+        if marked {
+            if let Some(weak) = node.tracked() {
+                survivors.push(weak);
+            }
+        } else {
+            node.clear();
+            reclaimed += 1;
+        }
+    }
+    drop(nodes);
+    HEAP.with_borrow_mut(|heap| {
+        survivors.append(&mut heap.tracked);
+        heap.threshold = INITIAL_THRESHOLD.max(survivors.len() * 2);
+        heap.tracked = survivors;
+        heap.collecting = false;
+    });
+    reclaimed
+}
+
+impl Node {
+    fn address(&self) -> usize {
+        match self {
+            Node::Cell(cell) => Rc::as_ptr(cell) as *const () as usize,
+            Node::Attributes(attributes) => Rc::as_ptr(attributes) as *const () as usize,
+            Node::List(items) => Rc::as_ptr(items) as *const () as usize,
+            Node::Map(entries) => Rc::as_ptr(entries) as *const () as usize,
+            Node::Class(class) => Rc::as_ptr(class) as *const () as usize,
+            Node::Instance(instance) => Rc::as_ptr(instance) as *const () as usize,
+            Node::Upvalue(upvalue) => Rc::as_ptr(upvalue) as *const () as usize
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Cell(cell) => Rc::strong_count(cell),
+            Node::Attributes(attributes) => Rc::strong_count(attributes),
+            Node::List(items) => Rc::strong_count(items),
+            Node::Map(entries) => Rc::strong_count(entries),
+            Node::Class(class) => Rc::strong_count(class),
+            Node::Instance(instance) => Rc::strong_count(instance),
+            Node::Upvalue(upvalue) => Rc::strong_count(upvalue)
+        }
+    }
+
+    fn downgrade(&self) -> WeakNode {
+        match self {
+            Node::Cell(cell) => WeakNode::Cell(Rc::downgrade(cell)),
+            Node::Attributes(attributes) => WeakNode::Attributes(Rc::downgrade(attributes)),
+            Node::List(items) => WeakNode::List(Rc::downgrade(items)),
+            Node::Map(entries) => WeakNode::Map(Rc::downgrade(entries)),
+            Node::Instance(instance) => WeakNode::Instance(Rc::downgrade(instance)),
+            Node::Upvalue(upvalue) => WeakNode::Upvalue(Rc::downgrade(upvalue)),
+            Node::Class(_) => unreachable!("classes are only found by tracing")
+        }
+    }
+
+    // Containers found by tracing are tracked from then on, so a cycle left behind later is still seen.
+    fn tracked(&self) -> Option<WeakNode> {
+        match self {
+            Node::Class(_) => None,
+            node => Some(node.downgrade())
+        }
+    }
+
+    // Reports what the container holds; false when it is borrowed and can't be looked into.
+    fn trace(&self, tracer: &mut Tracer) -> bool {
+        match self {
+            Node::Cell(cell) => cell.try_borrow().map(|value| value.trace(tracer)).is_ok(),
+            Node::Attributes(attributes) => attributes.try_borrow()
+                .map(|attributes| attributes.values().for_each(|cell| tracer.cell(cell)))
+                .is_ok(),
+            Node::List(items) => items.try_borrow()
+                .map(|items| items.iter().for_each(|item| item.trace(tracer)))
+                .is_ok(),
+            Node::Map(entries) => entries.try_borrow()
+                .map(|entries| entries.values.values().for_each(|cell| tracer.cell(cell)))
+                .is_ok(),
+            Node::Class(class) => {
+                class.trace(tracer);
+                true
+            },
+            Node::Instance(instance) => match (instance.fields.try_borrow(), instance.supers.try_borrow()) {
+                (Ok(fields), Ok(supers)) => {
+                    fields.values().for_each(|value| value.trace(tracer));
+                    supers.values().for_each(|parent| tracer.instance(parent));
+                    true
+                },
+                _ => false
+            },
+            Node::Upvalue(upvalue) => upvalue.try_borrow()
+                .map(|upvalue| if let Upvalue::Closed(value) = &*upvalue { value.trace(tracer) })
+                .is_ok()
+        }
+    }
+
+    fn clear(&self) {
+        match self {
+            Node::Cell(cell) => {
+                let _value = mem::replace(&mut *cell.borrow_mut(), Value::Nil);
+            },
+            Node::Attributes(attributes) => {
+                let _attributes = mem::take(&mut *attributes.borrow_mut());
+            },
+            Node::List(items) => {
+                let _items = mem::take(&mut *items.borrow_mut());
+            },
+            Node::Map(entries) => {
+                let _values = mem::take(&mut entries.borrow_mut().values);
+                entries.borrow_mut().order.clear();
+            },
+            Node::Class(_) => {},
+            Node::Instance(instance) => {
+                let _fields = mem::take(&mut *instance.fields.borrow_mut());
+                let _supers = mem::take(&mut *instance.supers.borrow_mut());
+            },
+            Node::Upvalue(upvalue) => {
+                if let Upvalue::Closed(value) = &mut *upvalue.borrow_mut() {
+                    let _value = mem::replace(value, VmValue::Nil);
+                }
+            }
+        }
+    }
+}
+
+impl WeakNode {
+    fn upgrade(&self) -> Option<Node> {
+        match self {
+            WeakNode::Cell(cell) => cell.upgrade().map(Node::Cell),
+            WeakNode::Attributes(attributes) => attributes.upgrade().map(Node::Attributes),
+            WeakNode::List(items) => items.upgrade().map(Node::List),
+            WeakNode::Map(entries) => entries.upgrade().map(Node::Map),
+            WeakNode::Instance(instance) => instance.upgrade().map(Node::Instance),
+            WeakNode::Upvalue(upvalue) => upvalue.upgrade().map(Node::Upvalue)
+        }
+    }
+}
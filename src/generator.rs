@@ -0,0 +1,150 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+
+use crate::error_handler::{ErrorType, LoxResult};
+use crate::function::Function;
+use crate::interpreter::block_scopes::{Activation, ActivationKind, BlockScopes};
+use crate::interpreter::Interpreter;
+use crate::module::ModuleRef;
+use crate::parser::declarations::{Object, RefObject, Type, Value, ValueObjTrait};
+
+// A suspended generator keeps the frames its body had opened and its
+// activation, which resumes at the statement following the last `yield`.
+// A fiber's body is a generator that suspends when the fiber yields or
+// blocks, possibly inside the calls it made: they are kept above the body,
+// with their frames counted from the body's, along with the module they run in.
+pub struct GeneratorState {
+    pub function: Function,
+    pub frames: Vec<Vec<RefObject>>,
+    pub stack: Vec<Activation>,
+    pub module: Option<ModuleRef>,
+    pub fiber: bool,
+    pub done: bool
+}
+
+#[derive(Clone)]
+pub struct Generator (pub Rc<RefCell<GeneratorState>>);
+
+impl Object for Generator {
+    fn to_str(&self) -> Cow<'static, str> {
+        Cow::Owned(format!("<generator {}>", self.0.borrow().function.name))
+    }
+
+    fn get_type(&self) -> Type {
+        Type::GENERATOR
+    }
+
+    fn dyn_clone(&self) -> Box<dyn Object> {
+        Box::new(Generator(self.0.clone()))
+    }
+}
+
+impl ValueObjTrait for Generator {
+    fn as_generator(&self) -> Option<&Generator> {
+        Some(self)
+    }
+}
+
+impl ToString for Generator {
+    fn to_string(&self) -> String {
+        self.to_str().to_string()
+    }
+}
+
+impl Generator {
+    pub fn new(function: &Function, args: Vec<Value>) -> Generator {
+        let params = args.into_iter().map(|value| Rc::new(RefCell::new(value))).collect();
+        Generator(Rc::new(RefCell::new(GeneratorState {
+            function: function.clone(),
+            frames: Vec::from([params]),
+            stack: Vec::new(),
+            module: function.module.clone(),
+            fiber: false,
+            done: false
+        })))
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.0.borrow().done
+    }
+
+    // Runs the body until the next `yield`; `None` once the body has finished.
+    pub fn resume(&self, state: &mut BlockScopes) -> LoxResult<Option<Value>> {
+        self.resume_with(state, Interpreter::run_base)
+    }
+
+    // A body that fails is finished: its frames are dropped and it stays done.
+    pub fn resume_with<F>(&self, state: &mut BlockScopes, run: F) -> LoxResult<Option<Value>>
+    where F: FnOnce(&mut BlockScopes, usize) -> LoxResult<()>
+    {
+        let (function, frames, stack, module, fiber) = {
+            let mut generator = self.0.borrow_mut();
+            if generator.done {
+                return Ok(None);
+            }
+            generator.done = true;
+            (generator.function.clone(), mem::take(&mut generator.frames), mem::take(&mut generator.stack),
+                generator.module.take(), generator.fiber)
+        };
+        let caller_module = state.switch_module(module);
+        let frame = state.depth + 1;
+        state.push_frames(frames);
+        let base = state.activations.len();
+        if stack.is_empty() {
+            let kind = if fiber { ActivationKind::Fiber } else { ActivationKind::Generator };
+            state.activations.push(Activation::new(kind, function.statements.clone(), 0, function.captures.clone()));
+        }
+        state.activations.extend(stack);
+        for activation in &mut state.activations[base..] {
+            activation.frame += frame;
+        }
+        state.native_depth += 1;
+        let result = run(state, base);
+        state.native_depth -= 1;
+        match result {
+            Err(error) if error.kind != ErrorType::FiberBlocked => {
+                state.activations.truncate(base);
+                state.take_frames(frame);
+                state.switch_module(caller_module);
+                return Err(error);
+            },
+            _ => ()
+        }
+
+        let mut stack = state.activations.split_off(base);
+        for activation in &mut stack {
+            activation.frame -= frame;
+        }
+        let frames = state.take_frames(frame);
+        let module = state.switch_module(caller_module);
+        if result.is_ok() {
+            let Some((position, _)) = stack[0].suspended else {
+                return Ok(None);
+            };
+            stack[0].position = position;
+        }
+        let yielded = stack[0].suspended.take().map_or(Value::Nil, |(_, value)| value);
+        let mut generator = self.0.borrow_mut();
+        generator.frames = frames;
+        generator.stack = stack;
+        generator.module = module;
+        generator.done = false;
+        Ok(Some(yielded))
+    }
+}
+
+// While a `yield` unwinds through a `try`, the generator resumes at that `try`
+// instead; returns the position inside the `try` the `yield` recorded.
+pub fn resume_at_try(state: &mut BlockScopes, try_position: usize) -> Option<usize> {
+    let (position, _) = state.activations.get_mut(state.running)?.suspended.as_mut()?;
+    Some(mem::replace(position, try_position))
+}
+
+// Records the yielded value in the running generator, see `Generator::resume`.
+pub fn suspend(state: &mut BlockScopes, value: Value, resume_at: usize) {
+    if let Some(activation) = state.activations.get_mut(state.running) {
+        activation.suspended = Some((resume_at, value));
+    }
+}
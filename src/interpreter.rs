@@ -1,11 +1,34 @@
+use std::mem;
+use std::rc::Rc;
 
 use crate::compiler::Compiler;
-use crate::function::clock_declaration;
-use crate::interpreter::block_scopes::BlockScopes;
+use crate::error_handler::{handle_error, ErrorType, LoxError, LoxResult};
+use crate::fiber::run_all;
+use crate::function::{clock, clock_declaration, Function, TailCall};
+use crate::generator::Generator;
+use crate::native::{fiber_methods, gc_methods};
+use crate::interpreter::block_scopes::{Activation, ActivationKind, BlockScopes};
+use crate::parser::declarations::Value;
+use crate::parser::Parser;
+use crate::scanner::tokenize;
 use crate::statements::Statement;
 pub mod block_scopes;
 pub mod expr_impl;
-mod utils;
+pub mod utils;
+
+// Calls nested on the native stack before one is left for the frame stack
+// to run, and native calls into Lox that may be nested in each other.
+const NESTED_CALLS: usize = 32;
+pub const MAX_NATIVE_DEPTH: usize = 256;
+
+const PRELUDE: &str = "
+class Error {
+    init(message) {
+        this.message = message;
+        this.line = nil;
+    }
+}
+";
 
 pub struct Interpreter<'a> {
     pub compiler: Compiler<'a>,
@@ -13,23 +36,217 @@ pub struct Interpreter<'a> {
 }
 
 impl Interpreter<'_> {
-    
+
     pub fn new(compiler: Compiler<'_>) -> Interpreter {
         Interpreter { compiler, state: BlockScopes::new() }
     }
 
-    pub fn exec(&mut self) {
-        self.state.define_function(&String::from("clock"), clock_declaration());
-        let mut stmts = self.compiler.compile();
-        Self::run(&mut self.state, &mut stmts);
+    pub fn compile(&mut self) -> LoxResult<Vec<Box<dyn Statement>>> {
+        self.compiler.compile()
+    }
+
+    pub fn exec(&mut self, stmts: &Rc<Vec<Box<dyn Statement>>>) -> LoxResult<()> {
+        Self::init_globals(&mut self.state)?;
+        Self::run(&mut self.state, stmts)?;
+        run_all(&mut self.state)
+    }
+
+    pub fn init_globals(state: &mut BlockScopes) -> LoxResult<()> {
+        state.define_global("clock", Value::obj(clock_declaration()));
+        fiber_methods::define_globals(state);
+        gc_methods::define_globals(state);
+        Self::load_prelude(state)
+    }
+
+    fn load_prelude(state: &mut BlockScopes) -> LoxResult<()> {
+        let stmts = Self::compile_prelude()?;
+        Self::run(state, &Rc::new(stmts))
+    }
+
+    pub fn compile_prelude() -> LoxResult<Vec<Box<dyn Statement>>> {
+        let tokens = tokenize(PRELUDE.to_string(), &mut Vec::new());
+        let mut compiler = Compiler::new(Parser::new(&tokens, 0));
+        compiler.compile()
+    }
+
+    // Runs a script or module body to its end, with the calls it makes.
+    pub fn run(state: &mut BlockScopes, stmts: &Rc<Vec<Box<dyn Statement>>>) -> LoxResult<()> {
+        let frame = state.depth;
+        state.activations.push(Activation::new(ActivationKind::Script, stmts.clone(), frame, Rc::from([])));
+        let result = Self::run_base(state, state.activations.len() - 1);
+        state.activations.pop();
+        if result.is_err() {
+            while state.depth > frame {
+                state.end_child_block();
+            }
+        }
+        result
+    }
+
+    // Runs `stmts` from `index`. A statement a call suspended is where the running body resumes.
+    pub fn run_from(state: &mut BlockScopes, stmts: &[Box<dyn Statement>], index: &mut usize) -> LoxResult<()> {
+        while *index < stmts.len() {
+            let current = *index;
+            if let Err(error) = stmts[current].run(state, index) {
+                if error.is_suspension() {
+                    state.current().position = current;
+                }
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    // Runs a nested statement list and reports whether a `return` left it early.
+    pub fn run_returns(state: &mut BlockScopes, stmts: &[Box<dyn Statement>], start: usize) -> LoxResult<bool> {
+        let mut index = start;
+        Self::run_from(state, stmts, &mut index)?;
+        Ok(index == usize::MAX)
+    }
+
+    // Runs the body of the top activation from where it stopped until it
+    // finishes or a call inside it suspends. A returned call takes over the
+    // activation, so tail recursion keeps a single frame.
+    pub fn run_body(state: &mut BlockScopes) -> LoxResult<()> {
+        let index = state.activations.len() - 1;
+        let outer = mem::replace(&mut state.running, index);
+        let result = loop {
+            let stmts = state.activations[index].statements.clone();
+            let mut position = state.activations[index].position;
+            let result = Self::run_from(state, &stmts, &mut position);
+            match state.tail_call.take() {
+                Some(tail_call) if result.is_ok() => Self::replace_call(state, tail_call),
+                _ => break result
+            }
+        };
+        state.running = outer;
+        result
+    }
+
+    // Runs the activation at `base` until its body finishes, along with the
+    // calls it suspends on. A blocked fiber leaves them all on the frame stack.
+    pub fn run_base(state: &mut BlockScopes, base: usize) -> LoxResult<()> {
+        let outer = mem::replace(&mut state.base, base);
+        let result = loop {
+            if let Err(error) = Self::drive(state, base) {
+                break Err(error);
+            }
+            match Self::run_body(state) {
+                Err(error) if error.kind == ErrorType::Suspended => continue,
+                result => break result
+            }
+        };
+        state.base = outer;
+        result
     }
 
-    pub fn run(state: &mut BlockScopes, stmts: &Vec<Box<dyn Statement>>) {
-        let mut index = 0;
-        while index < stmts.len() {
-            let statement: &Box<dyn Statement>  = &stmts[index];
-            statement.run(state, &mut index);
+    // Runs the calls above `base`, innermost first, handing each result to
+    // the body that is waiting for it, until `base` is back on top.
+    fn drive(state: &mut BlockScopes, base: usize) -> LoxResult<()> {
+        while state.activations.len() - 1 > base {
+            let result = Self::run_body(state);
+            match &result {
+                Err(error) if error.kind == ErrorType::Suspended => continue,
+                Err(error) if error.kind == ErrorType::FiberBlocked => return result,
+                _ => ()
+            }
+            let value = Self::finish(state, result);
+            state.activations.last_mut().unwrap().awaited = Some(value);
         }
+        Ok(())
     }
 
-}
\ No newline at end of file
+    // Calls `function` from an expression. The body runs nested while the
+    // native stack allows; past that, or when a call inside it suspends, the
+    // expression suspends its statement and the body is left on the frame
+    // stack for `drive` to run.
+    pub fn call(state: &mut BlockScopes, function: &Function, args: Vec<Value>, line: &u32, name: &Rc<String>) -> LoxResult<Value> {
+        if let Some(value) = Self::enter(state, function, args, line, name)? {
+            return Ok(value);
+        }
+        if state.native_depth >= NESTED_CALLS {
+            return Err(LoxError::suspended());
+        }
+        state.native_depth += 1;
+        let result = Self::run_body(state);
+        state.native_depth -= 1;
+        match result {
+            Err(error) if error.is_suspension() => Err(error),
+            result => Self::finish(state, result)
+        }
+    }
+
+    // Calls `function` from native code, which can't be suspended: the
+    // calls it makes are run here until it returns.
+    pub fn call_to_completion(state: &mut BlockScopes, function: &Function, args: Vec<Value>, line: &u32, 
+        name: &Rc<String>) -> LoxResult<Value> 
+    {
+        if state.native_depth >= MAX_NATIVE_DEPTH {
+            return handle_error(line, ErrorType::RuntimeError, "Stack overflow.");
+        }
+        if let Some(value) = Self::enter(state, function, args, line, name)? {
+            return Ok(value);
+        }
+        state.native_depth += 1;
+        let result = Self::run_base(state, state.activations.len() - 1);
+        state.native_depth -= 1;
+        Self::finish(state, result)
+    }
+
+    // Puts a call of `function` on the frame stack, unless it is answered right away.
+    fn enter(state: &mut BlockScopes, function: &Function, args: Vec<Value>, line: &u32, 
+        name: &Rc<String>) -> LoxResult<Option<Value>> 
+    {
+        state.start_call(name, line)?;
+        if let Err(error) = function.check_arity(args.len(), line) {
+            return state.end_call(Err(error));
+        }
+        if function.name.as_str() == "clock" {
+            return state.end_call(Ok(Some(Value::Number(clock() as f64))));
+        }
+        if function.generator {
+            return state.end_call(Ok(Some(Value::obj(Generator::new(function, args)))));
+        }
+        let caller_module = state.switch_module(function.module.clone());
+        Self::push_body(state, function, args);
+        state.activations.last_mut().unwrap().caller_module = caller_module;
+        Ok(None)
+    }
+
+    fn push_body(state: &mut BlockScopes, function: &Function, args: Vec<Value>) {
+        state.start_child_block();
+        for (slot, (param_name, param_value)) in function.params_names.iter().zip(args).enumerate() {
+            state.define_variable(param_name, param_value, Some(slot));
+        }
+        let mut activation = Activation::new(ActivationKind::Call, function.statements.clone(), state.depth, 
+            function.captures.clone());
+        activation.initializer = function.initializer;
+        state.activations.push(activation);
+    }
+
+    fn replace_call(state: &mut BlockScopes, tail_call: TailCall) {
+        let activation = state.activations.pop().unwrap();
+        while state.depth >= activation.frame {
+            state.end_child_block();
+        }
+        state.replace_call(&tail_call.function.name, &tail_call.line);
+        state.switch_module(tail_call.function.module.clone());
+        Self::push_body(state, &tail_call.function, tail_call.args);
+        state.activations.last_mut().unwrap().caller_module = activation.caller_module;
+    }
+
+    // Pops the call on top of the frame stack once its body is done and gives what it returns.
+    pub fn finish(state: &mut BlockScopes, result: LoxResult<()>) -> LoxResult<Value> {
+        let activation = state.activations.pop().expect("no running call");
+        while state.depth >= activation.frame {
+            state.end_child_block();
+        }
+        state.switch_module(activation.caller_module);
+        state.end_call(result)?;
+        // An initializer returns its instance, which it always captures first.
+        if let (true, Some(Some(this))) = (activation.initializer, activation.captures.first()) {
+            return Ok(this.borrow().clone());
+        }
+        Ok(activation.returned)
+    }
+}
@@ -1,104 +1,327 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
-use crate::{class::Class, function::Function};
-use crate::parser::declarations::{Object, RefObject};
+use std::{cell::{Cell, RefCell}, collections::{HashMap, HashSet}, mem, rc::Rc};
+use crate::error_handler::{handle_error, set_current_file, CallFrame, ErrorType, LoxResult, Span};
+use crate::module::ModuleRef;
+use crate::function::TailCall;
+use crate::parser::declarations::{RefObject, Value};
+use crate::parser::expressions::{Expression, Resolution};
+use crate::statements::{Statement, SuspendedTry};
 
+// Calls live on the heap-allocated frame stack, so this only stops runaway recursion.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1_000_000;
+
+// Counted across fibers and modules, which all share the frame stack.
+thread_local! {
+    static CALL_DEPTH: Cell<usize> = const { Cell::new(0) };
+    static MAX_CALL_DEPTH: Cell<usize> = const { Cell::new(DEFAULT_MAX_CALL_DEPTH) };
+}
+
+pub fn set_max_call_depth(max_call_depth: usize) {
+    MAX_CALL_DEPTH.set(max_call_depth);
+}
+
+pub fn max_call_depth() -> usize {
+    MAX_CALL_DEPTH.get()
+}
+
+// A call in progress, kept cheap to push; it only becomes a `CallFrame` for a traceback.
+pub struct CallSite {
+    name: Rc<String>,
+    line: u32,
+    module: Option<ModuleRef>,
+    elided: usize
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ActivationKind {
+    Script,
+    Call,
+    Generator,
+    Fiber
+}
+
+// A body on the frame stack: the statement it runs next, the frame holding
+// its parameters, the cells its closure captured and, once it is done, what
+// it returned or yielded. A body suspended by a call keeps what the
+// suspended statement had evaluated and gets the call's result in `awaited`.
+pub struct Activation {
+    pub kind: ActivationKind,
+    pub statements: Rc<Vec<Box<dyn Statement>>>,
+    pub position: usize,
+    pub frame: usize,
+    pub captures: Rc<[Option<RefObject>]>,
+    pub initializer: bool,
+    pub caller_module: Option<ModuleRef>,
+    pub returned: Value,
+    pub suspended: Option<(usize, Value)>,
+    pub tries: Vec<SuspendedTry>,
+    pub partials: Vec<Partial>,
+    pub awaited: Option<LoxResult<Value>>
+}
+
+impl Activation {
+    pub fn new(kind: ActivationKind, statements: Rc<Vec<Box<dyn Statement>>>, frame: usize, 
+        captures: Rc<[Option<RefObject>]>) -> Activation 
+    {
+        Activation {
+            kind,
+            statements,
+            position: 0,
+            frame,
+            captures,
+            initializer: false,
+            caller_module: None,
+            returned: Value::Nil,
+            suspended: None,
+            tries: Vec::new(),
+            partials: Vec::new(),
+            awaited: None
+        }
+    }
+}
+
+// What an expression had evaluated when a call inside it suspended the
+// statement, keyed by the expression's address. `awaiting` marks the call
+// itself, which takes its result instead of running again.
+pub struct Partial {
+    node: usize,
+    pub values: Vec<Value>,
+    pub awaiting: bool
+}
+
+// Locals live in the slots of their frame and globals in a table by name;
+// the resolver decides which one an identifier reads. `running` is the
+// activation whose statements are being run, below any call it started,
+// and `base` the lowest one a suspension can leave on the frame stack:
+// native code calling into Lox starts a new one.
 pub struct BlockScopes {
-    pub vars_nodes_map: Vec<HashMap<String, RefObject>>,
-    pub depth: usize
+    pub globals: HashMap<String, RefObject>,
+    pub global_constants: HashSet<String>,
+    pub slots: Vec<Vec<RefObject>>,
+    pub depth: usize,
+    pub activations: Vec<Activation>,
+    pub running: usize,
+    pub base: usize,
+    pub native_depth: usize,
+    pub current_module: Option<ModuleRef>,
+    pub call_stack: Vec<CallSite>,
+    pub tail_call: Option<TailCall>
 }
 
 impl BlockScopes {
     pub fn new() -> BlockScopes {
         BlockScopes { 
-            vars_nodes_map: Vec::from([
-                HashMap::new(),
+            globals: HashMap::new(),
+            global_constants: HashSet::new(),
+            slots: Vec::from([
+                Vec::new(),
             ]),
-            depth: 0
+            depth: 0,
+            activations: Vec::new(),
+            running: 0,
+            base: 0,
+            native_depth: 0,
+            current_module: None,
+            call_stack: Vec::new(),
+            tail_call: None
+        }
+    }
+
+    // The global table always holds the globals of `current_module`; the globals
+    // of an inactive module are parked in its scope until it is switched back in.
+    pub fn switch_module(&mut self, target: Option<ModuleRef>) -> Option<ModuleRef> {
+        let same_module = match (&self.current_module, &target) {
+            (Some(current), Some(target)) => Rc::ptr_eq(current, target),
+            (None, None) => true,
+            _ => false
+        };
+        if same_module {
+            return target;
         }
+        if let Some(current) = &self.current_module {
+            mem::swap(&mut self.globals, &mut current.globals.borrow_mut());
+            mem::swap(&mut self.global_constants, &mut current.constants.borrow_mut());
+        }
+        if let Some(target) = &target {
+            mem::swap(&mut self.globals, &mut target.globals.borrow_mut());
+            mem::swap(&mut self.global_constants, &mut target.constants.borrow_mut());
+        }
+        set_current_file(target.as_ref().map(|module| module.file.clone()));
+        mem::replace(&mut self.current_module, target)
+    }
+
+    pub fn define_global(&mut self, identifier: &str, value: Value) {
+        self.globals.insert(identifier.to_string(), Rc::new(RefCell::new(value)));
     }
 
-    // pub fn set_global_variable(&mut self, identifier: &String, value: Box<dyn Object>) {
-    //     match self.vars_nodes_map.get_mut(0) {
-    //         Some(node_map) => {
-    //             node_map.insert(identifier.to_string(), value);
-    //         },
-    //         None => {}
-    //     };
-    // }
-
-    // pub fn get_global_variable(&mut self, identifier: &String) -> Option<Box<dyn Object>> {
-    //     if let Some(hashmap) = self.vars_nodes_map.first() {
-    //         match hashmap.get(identifier) {
-    //             Some(value) => { return Some(value.dyn_clone()); },
-    //             None => {}
-    //         } 
-    //     }
-    //     None
-    // }
-
-    pub fn define_function(&mut self, func_name: &String, function: Function) {
-        self.set_init_variable(func_name, Box::new(function));
-    }
-
-    // pub fn get_func(&mut self, func_name: &String) -> Option<&Function> {
-    //     for hashmap in self.vars_nodes_map.iter().rev() {
-    //         if let Some(value) = hashmap.get(func_name) {
-    //             if value.get_type() == Type::FUNCTION {
-    //                 return Some(value.as_function().unwrap());
-    //             }
-    //         }
-    //     }
-    //     None
-    // }
-
-    pub fn define_class(&mut self, classname: &String, class: Class) {
-        self.set_init_variable(classname, Box::new(class));
+    pub fn global(&self, identifier: &str) -> Option<Value> {
+        self.globals.get(identifier).map(|value| value.borrow().clone())
     }
 
     pub fn start_child_block(&mut self) {
-        self.vars_nodes_map.push(HashMap::new());
+        self.slots.push(Vec::new());
         self.depth += 1;
     }
 
     pub fn end_child_block(&mut self) {
-        self.vars_nodes_map.pop();
+        self.slots.pop();
         self.depth -= 1;
     }
 
-    pub fn set_init_variable(&mut self, identifier: &String, value: Box<dyn Object>) {
-        match self.vars_nodes_map.get_mut(self.depth) {
-            Some(node_map) => {
-                node_map.insert(identifier.to_string(), Rc::new(RefCell::new(value)));
-            },
-            None => {}
-        };
+    // A call named `name` is counted against the call depth and shows up in tracebacks until `end_call`.
+    pub fn start_call(&mut self, name: &Rc<String>, line: &u32) -> LoxResult<()> {
+        let depth = CALL_DEPTH.get();
+        if depth >= MAX_CALL_DEPTH.get() {
+            return handle_error(line, ErrorType::RuntimeError, "Stack overflow.");
+        }
+        CALL_DEPTH.set(depth + 1);
+        self.call_stack.push(CallSite { name: name.clone(), line: *line, module: self.current_module.clone(), elided: 0 });
+        Ok(())
+    }
+
+    // A runtime error leaving its innermost call records the whole call stack as its traceback.
+    pub fn end_call<T>(&mut self, result: LoxResult<T>) -> LoxResult<T> {
+        let result = result.map_err(|mut error| {
+            if error.kind == ErrorType::RuntimeError && error.trace.is_empty() && error.fiber.is_none() {
+                error.trace = self.traceback();
+            }
+            error
+        });
+        self.call_stack.pop();
+        CALL_DEPTH.set(CALL_DEPTH.get() - 1);
+        result
     }
 
-    pub fn modif_variable(&mut self, identifier: &String, new_value: Box<dyn Object>) {
-        for hashmap in self.vars_nodes_map.iter_mut().rev() {
-            if let Some(value) = hashmap.get(identifier) {
-                let mut value_mut = value.borrow_mut();
-                *value_mut = new_value;
-                break;
-            } 
+    // A tail call takes over the frame of the call it returns from, which only leaves a count behind.
+    pub fn replace_call(&mut self, name: &Rc<String>, line: &u32) {
+        if let Some(site) = self.call_stack.last_mut() {
+            *site = CallSite { name: name.clone(), line: *line, module: self.current_module.clone(), elided: site.elided + 1 };
         }
     }
 
-    pub fn get_variable(&mut self, identifier: &String) -> Option<Box<dyn Object>> {
-        for hashmap in self.vars_nodes_map.iter().rev() {
-            if let Some(value) = hashmap.get(identifier) {
-                return Some(value.borrow().dyn_clone());
+    // Puts `calls` in place of the call stack, for a fiber to resume the calls
+    // it was suspended in; parked calls don't count against the call depth.
+    pub fn swap_calls(&mut self, calls: Vec<CallSite>) -> Vec<CallSite> {
+        let parked = mem::replace(&mut self.call_stack, calls);
+        CALL_DEPTH.set(CALL_DEPTH.get() + self.call_stack.len() - parked.len());
+        parked
+    }
+
+    fn traceback(&self) -> Box<[CallFrame]> {
+        self.call_stack.iter().rev().map(|site| CallFrame {
+            name: site.name.to_string(),
+            span: Span { line: site.line, file: site.module.as_ref().map(|module| module.file.clone()) },
+            elided: site.elided
+        }).collect()
+    }
+
+    pub fn push_frames(&mut self, slots: Vec<Vec<RefObject>>) {
+        self.depth += slots.len();
+        self.slots.extend(slots);
+    }
+
+    pub fn take_frames(&mut self, from: usize) -> Vec<Vec<RefObject>> {
+        let slots = self.slots.split_off(from);
+        self.depth = from - 1;
+        slots
+    }
+
+    // Declares a variable in the slot the resolver gave it, or as a global when it has none.
+    pub fn define_variable(&mut self, identifier: &str, value: Value, slot: Option<usize>) -> RefObject {
+        match slot {
+            Some(slot) => {
+                let cell = self.slot(0, slot);
+                *cell.borrow_mut() = value;
+                cell
+            },
+            None => {
+                let cell = Rc::new(RefCell::new(value));
+                self.globals.insert(identifier.to_string(), cell.clone());
+                cell
             }
         }
-        None
     }
 
-    pub fn get_variable_from(&mut self, identifier: &String, depth: usize) -> Option<Box<dyn Object>> {
-        for hashmap in self.vars_nodes_map[depth..].iter().rev() {
-            if let Some(value) = hashmap.get(identifier) {
-                return Some(value.borrow().dyn_clone());
-            }
+    // The cell of a local `depth` frames out. A closure can capture a local
+    // before it is declared, so the cell is made by whichever comes first.
+    pub fn slot(&mut self, depth: usize, slot: usize) -> RefObject {
+        let frame = &mut self.slots[self.depth - depth];
+        if frame.len() <= slot {
+            frame.resize_with(slot + 1, || Rc::new(RefCell::new(Value::Nil)));
+        }
+        frame[slot].clone()
+    }
+
+    // Finds the cell of a variable where the resolver placed it.
+    pub fn lookup(&self, identifier: &str, resolution: Resolution) -> Option<RefObject> {
+        match resolution {
+            Resolution::Local { depth, slot } => self.slots[self.depth.checked_sub(depth)?].get(slot).cloned(),
+            Resolution::Upvalue { index } => match self.activations.last()?.captures.get(index)? {
+                Some(cell) => Some(cell.clone()),
+                None => self.globals.get(identifier).cloned()
+            },
+            Resolution::Global => self.globals.get(identifier).cloned(),
+            Resolution::Unresolved => None
+        }
+    }
+
+    // Locals are checked by the resolver, but a global may be declared constant after the code assigning it.
+    pub fn mark_constant(&mut self, identifier: &str, slot: Option<usize>) {
+        if slot.is_none() {
+            self.global_constants.insert(identifier.to_string());
+        }
+    }
+
+    pub fn is_constant(&self, identifier: &str, resolution: Resolution) -> bool {
+        matches!(resolution, Resolution::Global) && self.global_constants.contains(identifier)
+    }
+
+    // The activation of the body being run.
+    pub fn current(&mut self) -> &mut Activation {
+        &mut self.activations[self.running]
+    }
+
+    // The last frame of the running body: a call it suspended on has its frames above.
+    pub fn own_depth(&self) -> usize {
+        match self.activations.get(self.running + 1) {
+            Some(callee) if callee.kind == ActivationKind::Call => callee.frame - 1,
+            _ => self.depth
+        }
+    }
+
+    pub fn save_partial<T>(&mut self, node: &T, values: Vec<Value>, awaiting: bool) {
+        let node = node as *const T as usize;
+        self.current().partials.push(Partial { node, values, awaiting });
+    }
+
+    // What `node` had evaluated before it suspended, when the running statement is resumed.
+    pub fn resume_partial<T>(&mut self, node: &T) -> Option<Partial> {
+        let activation = self.activations.get_mut(self.running)?;
+        match activation.partials.last() {
+            Some(partial) if partial.node == node as *const T as usize => activation.partials.pop(),
+            _ => None
+        }
+    }
+
+    // Evaluates a part of `node` after the `evaluated` ones, which are kept if a call inside it suspends the statement.
+    pub fn evaluate_after<T>(&mut self, node: &T, evaluated: &[Value], expression: &dyn Expression) -> LoxResult<Value> {
+        let result = expression.evaluate(self);
+        if matches!(&result, Err(error) if error.is_suspension()) {
+            self.save_partial(node, evaluated.to_vec(), false);
+        }
+        result
+    }
+
+    pub fn take_awaited(&mut self) -> LoxResult<Value> {
+        self.current().awaited.take().expect("resumed call without a result")
+    }
+
+    // Records what the running call returns and drops the frames its body opened.
+    pub fn return_value(&mut self, value: Value) {
+        let activation = self.activations.last_mut().expect("return outside a call");
+        activation.returned = value;
+        let frame = activation.frame;
+        while self.depth > frame {
+            self.end_child_block();
         }
-        None
     }
 }
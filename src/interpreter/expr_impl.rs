@@ -1,40 +1,75 @@
 
 
+use std::rc::Rc;
+use std::slice;
+
 use crate::class::ClassInstance;
-use crate::interpreter::{block_scopes::BlockScopes, utils::*};
+use crate::interpreter::{block_scopes::BlockScopes, utils::*, Interpreter};
 use crate::parser::declarations::*;
 use crate::parser::expressions::*;
 use crate::parser::operators_decl::*;
 use crate::error_handler::*;
+use crate::fiber::can_suspend;
+use crate::function::{CallOutcome, TailCall};
+use crate::native::map_methods::map_key;
+use crate::typecheck::{StaticType, TypeChecker};
+use crate::vm::chunk::OpCode;
+use crate::vm::emitter::{EmitResult, Emitter, Unsupported};
+use crate::vm::value::Value as VmValue;
 
 impl Expression for CallExpr  {
 
-    fn evaluate(&self, state_scope: &mut BlockScopes) -> Box<dyn Object> {
-        let callable_val = self.callable.evaluate(state_scope);
-        if callable_val.get_type() == Type::FUNCTION {
-            let func = callable_val.as_function().unwrap();
-            func.call(&self.params, state_scope, &self.line)
-        }
-        else if callable_val.get_type() == Type::CLASS {
-            let class_call = callable_val.as_class().unwrap();
-            let instance = class_call.call(&self.params, state_scope, &self.line);
-            Box::new(instance)
-        }
-        else {
-            handle_error(&self.line, ErrorType::RuntimeError, 
-                "Can only call functions and classes.");    
+    fn evaluate(&self, state_scope: &mut BlockScopes) -> LoxResult<Value> {
+        let values = match state_scope.resume_partial(self) {
+            Some(partial) if partial.awaiting => return state_scope.take_awaited(),
+            Some(partial) => partial.values,
+            None => Vec::new()
+        };
+        let Some(mut values) = self.operands(values, state_scope)? else {
+            return Ok(Value::Nil);
+        };
+        let callable_val = values.remove(0);
+        self.call_value(callable_val, values, state_scope)
+    }
+
+    fn is_call(&self) -> bool {
+        true
+    }
+
+    fn evaluate_tail(&self, state_scope: &mut BlockScopes) -> LoxResult<CallOutcome> {
+        let values = match state_scope.resume_partial(self) {
+            Some(partial) if partial.awaiting => return state_scope.take_awaited().map(CallOutcome::Value),
+            Some(partial) => partial.values,
+            None => Vec::new()
+        };
+        let Some(mut values) = self.operands(values, state_scope)? else {
+            return Ok(CallOutcome::Value(Value::Nil));
+        };
+        let callable_val = values.remove(0);
+        if let Some(func) = callable_val.as_function() {
+            if !func.generator && func.name.as_str() != "clock" {
+                return Ok(CallOutcome::TailCall(TailCall { function: func.clone(), args: values, line: self.line }));
+            }
         }
+        Ok(CallOutcome::Value(self.call_value(callable_val, values, state_scope)?))
     }
 
-    fn value_from_class_instance(&self, instance: &ClassInstance, state_scope: &mut BlockScopes) -> (String, Option<Box<dyn Object>>) {
-        let (identifier, func_option) = self.callable.value_from_class_instance(instance, state_scope);
+    fn value_from_class_instance(&self, instance: &ClassInstance, state_scope: &mut BlockScopes) -> LoxResult<(String, Option<Value>)> {
+        let (identifier, func_option) = self.callable.value_from_class_instance(instance, state_scope)?;
         if let Some(func) = func_option {
             if func.get_type() != Type::FUNCTION {
-                handle_error(&self.line, ErrorType::RuntimeError, "Expect function");
+                return handle_error(&self.line, ErrorType::RuntimeError, "Expect function");
             }
-            return (identifier, Some((func.as_function().unwrap()).call(&self.params, state_scope, &self.line)));
+            let mut values = match state_scope.resume_partial(self) {
+                Some(partial) if partial.awaiting => return Ok((identifier, Some(state_scope.take_awaited()?))),
+                Some(partial) => partial.values,
+                None => Vec::from([func])
+            };
+            self.arguments(&mut values, state_scope)?;
+            let callable_val = values.remove(0);
+            return Ok((identifier, Some(self.call_value(callable_val, values, state_scope)?)));
         }
-        handle_error(&self.line, ErrorType::RuntimeError, format!("No Callable with name '{}'", identifier).as_str());
+        handle_error(&self.line, ErrorType::RuntimeError, format!("No Callable with name '{}'", identifier).as_str())
     }
 
     fn get_line(&self) -> u32 {
@@ -44,28 +79,144 @@ impl Expression for CallExpr  {
     fn to_string(&self) -> String {
         self.callable.to_string()
     }
+
+    fn is_null_safe(&self) -> bool {
+        self.callable.is_null_safe()
+    }
+
+    fn static_type(&self, checker: &mut TypeChecker) -> StaticType {
+        let callee = self.callable.static_type(checker);
+        checker.check_call(&callee, &self.params, self.line)
+    }
+
+    fn member_type(&self, owner: &StaticType, checker: &mut TypeChecker) -> StaticType {
+        let method = self.callable.member_type(owner, checker);
+        checker.check_call(&method, &self.params, self.line)
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        let (argc, nil_jump) = self.emit_callee_and_args(emitter)?;
+        emitter.op(OpCode::Call(argc), self.line);
+        if let Some(nil_jump) = nil_jump {
+            emitter.patch_jump(nil_jump);
+        }
+        Ok(())
+    }
+
+    fn emit_tail_call(&self, emitter: &mut Emitter) -> EmitResult {
+        let (argc, nil_jump) = self.emit_callee_and_args(emitter)?;
+        emitter.op(OpCode::TailCall(argc), self.line);
+        if let Some(nil_jump) = nil_jump {
+            emitter.patch_jump(nil_jump);
+        }
+        emitter.return_value(self.line);
+        Ok(())
+    }
+}
+
+impl CallExpr {
+    // The callee followed by the arguments, or `None` for a `?.` call on nil.
+    // A statement resumed after a call inside them suspended it picks up
+    // after the ones already evaluated.
+    fn operands(&self, mut values: Vec<Value>, state_scope: &mut BlockScopes) -> LoxResult<Option<Vec<Value>>> {
+        if values.is_empty() {
+            let callable_val = self.callable.evaluate(state_scope)?;
+            if callable_val.get_type() == Type::NIL && self.callable.is_null_safe() {
+                return Ok(None);
+            }
+            if let Some(func) = callable_val.as_function() {
+                func.check_arity(self.params.len(), &self.line)?;
+            }
+            if let Some(method) = callable_val.as_native_method() {
+                method.check_arity(self.params.len(), &self.line)?;
+            }
+            values.push(callable_val);
+        }
+        self.arguments(&mut values, state_scope)?;
+        Ok(Some(values))
+    }
+
+    fn arguments(&self, values: &mut Vec<Value>, state_scope: &mut BlockScopes) -> LoxResult<()> {
+        for param in &self.params[values.len() - 1..] {
+            let value = state_scope.evaluate_after(self, values, param.as_ref())?;
+            values.push(value);
+        }
+        Ok(())
+    }
+
+    // A Lox function or initializer that doesn't return right away leaves the
+    // statement suspended until its result is handed back.
+    fn call_value(&self, callable_val: Value, args: Vec<Value>, state_scope: &mut BlockScopes) -> LoxResult<Value> {
+        let result = match callable_val.get_type() {
+            Type::FUNCTION => {
+                let func = callable_val.as_function().unwrap();
+                Interpreter::call(state_scope, func, args, &self.line, &func.name)
+            },
+            Type::CLASS => {
+                let class = callable_val.as_class().unwrap();
+                match class.instantiate() {
+                    (_, Some(init)) => Interpreter::call(state_scope, &init, args, &self.line, &Rc::new(class.name.clone())),
+                    (instance, None) => Ok(Value::obj(instance))
+                }
+            },
+            Type::NATIVEMETHOD => {
+                // A native that blocks the fiber, like `receive()`, runs again once it resumes.
+                let operands = can_suspend(state_scope).then(|| [slice::from_ref(&callable_val), &args].concat());
+                let method = callable_val.as_native_method().unwrap();
+                let result = method.call_with_args(args, state_scope, &self.line);
+                if let (Some(operands), Err(error)) = (operands, &result) {
+                    if error.is_suspension() {
+                        state_scope.save_partial(self, operands, false);
+                    }
+                }
+                return result;
+            },
+            _ => handle_error(&self.line, ErrorType::RuntimeError, "Can only call functions and classes.")
+        };
+        if matches!(&result, Err(error) if error.is_suspension()) {
+            state_scope.save_partial(self, Vec::new(), true);
+        }
+        result
+    }
+
+    // A `?.` callee that is nil skips the arguments and the call, and is the result.
+    fn emit_callee_and_args(&self, emitter: &mut Emitter) -> Result<(u8, Option<usize>), Unsupported> {
+        let argc = emitter.argument_count(self.params.len())?;
+        self.callable.emit(emitter)?;
+        let nil_jump = self.is_null_safe().then(|| emitter.jump(OpCode::JumpIfNil(0), self.line));
+        emitter.op(OpCode::CheckCall(argc), self.line);
+        for param in self.params.iter() {
+            param.emit(emitter)?;
+        }
+        Ok((argc, nil_jump))
+    }
 }
 
 impl Expression for IdentifierExpr {
-    fn evaluate(&self, state_scope: &mut BlockScopes) -> Box<dyn Object> {
-        if let Some(value) = state_scope.get_variable(&self.ident_name) {
+    fn evaluate(&self, state_scope: &mut BlockScopes) -> LoxResult<Value> {
+        let resolution = self.resolution.get();
+        if let Some(cell) = state_scope.lookup(&self.ident_name, resolution) {
             match &self.value_to_assign {
                 Some(expr_value) => {
-                    let val = expr_value.evaluate(state_scope);
-                    state_scope.modif_variable(&self.ident_name, val.dyn_clone());
-                    return val;
+                    if state_scope.is_constant(&self.ident_name, resolution) {
+                        return handle_error(&self.line, ErrorType::RuntimeError, 
+                            format!("Cannot assign to constant '{}'.", self.ident_name).as_str());
+                    }
+                    let val = expr_value.evaluate(state_scope)?;
+                    *cell.borrow_mut() = val.clone();
+                    return Ok(val);
                 },
                 None => {
-                    return value;
+                    return Ok(cell.borrow().clone());
                 }
             }
         }
         handle_error(&self.line, ErrorType::RuntimeError, 
-            format!("Undefined variable '{}'.", self.ident_name).as_str());
+            format!("Undefined variable '{}'.", self.ident_name).as_str())
     }
 
-    fn value_from_class_instance(&self, instance: &ClassInstance, _state_scope: &mut BlockScopes) -> (String, Option<Box<dyn Object>>) {
-        (self.ident_name.clone(), instance.get(&self.ident_name))
+    fn value_from_class_instance(&self, instance: &ClassInstance, _state_scope: &mut BlockScopes) -> LoxResult<(String, Option<Value>)> {
+        Ok((self.ident_name.clone(), instance.get(&self.ident_name)))
     }
 
     fn get_line(&self) -> u32 {
@@ -75,16 +226,50 @@ impl Expression for IdentifierExpr {
     fn to_string(&self) -> String {
         self.ident_name.to_string()
     }
+
+    fn static_type(&self, checker: &mut TypeChecker) -> StaticType {
+        let declared = checker.lookup(&self.ident_name);
+        let Some(expr_value) = &self.value_to_assign else {
+            return declared;
+        };
+        let actual = expr_value.static_type(checker);
+        if !checker.accepts(&declared, &actual) {
+            checker.report(self.line, format!("Type mismatch: cannot assign {} to '{}' of type {}.", 
+                actual.name(), self.ident_name, declared.name()));
+        }
+        actual
+    }
+
+    fn member_type(&self, owner: &StaticType, checker: &mut TypeChecker) -> StaticType {
+        checker.member_type(owner, &self.ident_name)
+    }
+
+    fn identifier(&self) -> Option<&str> {
+        match self.value_to_assign {
+            Some(_) => None,
+            None => Some(&self.ident_name)
+        }
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        match &self.value_to_assign {
+            Some(expr_value) => {
+                expr_value.emit(emitter)?;
+                emitter.set_variable(&self.ident_name, self.line)
+            },
+            None => emitter.get_variable(&self.ident_name, self.line)
+        }
+    }
 }
 
 impl Expression for LiteralExpr {
-    fn evaluate(&self, _state_scope: &mut BlockScopes) -> Box <dyn Object> {
-        return self.value.dyn_clone();
+    fn evaluate(&self, _state_scope: &mut BlockScopes) -> LoxResult<Value> {
+        Ok(self.value.clone())
     }
 
-    fn value_from_class_instance(&self, _instance: &ClassInstance, _state_scope: &mut BlockScopes) -> (String, Option<Box<dyn Object>>) {
+    fn value_from_class_instance(&self, _instance: &ClassInstance, _state_scope: &mut BlockScopes) -> LoxResult<(String, Option<Value>)> {
         handle_error(&self.line, ErrorType::RuntimeError, 
-            "Can only access property on class instance");
+            "Can only access property on class instance")
     }
 
     fn get_line(&self) -> u32 {
@@ -94,16 +279,30 @@ impl Expression for LiteralExpr {
     fn to_string(&self) -> String {
         self.value.to_string()
     }
+
+    fn static_type(&self, _checker: &mut TypeChecker) -> StaticType {
+        StaticType::of_value(&self.value)
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        match VmValue::from_value(&self.value) {
+            VmValue::Nil => emitter.op(OpCode::Nil, self.line),
+            VmValue::Bool(true) => emitter.op(OpCode::True, self.line),
+            VmValue::Bool(false) => emitter.op(OpCode::False, self.line),
+            value => return emitter.constant(value, self.line)
+        };
+        Ok(())
+    }
 }
 
 impl Expression for GroupExpr {
-    fn evaluate(&self, state_scope: &mut BlockScopes) -> Box <dyn Object> {
-        return self.value.evaluate(state_scope);
+    fn evaluate(&self, state_scope: &mut BlockScopes) -> LoxResult<Value> {
+        self.value.evaluate(state_scope)
     }
 
-    fn value_from_class_instance(&self, _instance: &ClassInstance, _state_scope: &mut BlockScopes) -> (String, Option<Box<dyn Object>>) {
+    fn value_from_class_instance(&self, _instance: &ClassInstance, _state_scope: &mut BlockScopes) -> LoxResult<(String, Option<Value>)> {
         handle_error(&self.line, ErrorType::RuntimeError, 
-            "Can only access property on class instance");
+            "Can only access property on class instance")
     }
 
     fn get_line(&self) -> u32 {
@@ -114,42 +313,52 @@ impl Expression for GroupExpr {
         let child = self.value.to_string();
         format!("(group {child})")
     }
+
+    fn static_type(&self, checker: &mut TypeChecker) -> StaticType {
+        self.value.static_type(checker)
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        self.value.emit(emitter)
+    }
 }
 
 
 impl  Expression for UnaryExpr {
-    fn evaluate(&self, state_scope: &mut BlockScopes) -> Box <dyn Object> {
-        let value_evaluated = self.value.evaluate(state_scope);
-        match self.operator {
+    fn evaluate(&self, state_scope: &mut BlockScopes) -> LoxResult<Value> {
+        let mut value_evaluated = self.value.evaluate(state_scope)?;
+        let value: Value = match self.operator {
             UnaryOperator::BANG => {
                 match value_evaluated.get_type() {
                     Type::BOOLEAN => {
                         let bool = value_evaluated.as_bool().unwrap();
-                        Box::new(Bool(!bool.0))
+                        Value::Bool(!bool)
                     },
-                    Type::NIL => Box::new(Bool(true)),
-                    _ => Box::new(Bool(false))
+                    Type::NIL => Value::Bool(true),
+                    _ => Value::Bool(false)
                 }
             },
             UnaryOperator::MINUS => {
                 match value_evaluated.get_type() {
                     Type::NUMBER => {
                         let num = value_evaluated.as_number().unwrap();
-                        Box::new(Number(-num.0))
+                        Value::Number(-num)
                     },
                     _ => {
-                        handle_error(&self.line, ErrorType::RuntimeError, "Operand must be a number.");
-                        
+                        if let Some(result) = call_operator_method(&mut value_evaluated, "__neg__", Vec::new(), state_scope, &self.line)? {
+                            return Ok(result);
+                        }
+                        return handle_error(&self.line, ErrorType::RuntimeError, "Operand must be a number.");
                     }
                 }
             }
-        }
+        };
+        Ok(value)
     }
 
-    fn value_from_class_instance(&self, _instance: &ClassInstance, _state_scope: &mut BlockScopes) -> (String, Option<Box<dyn Object>>) {
+    fn value_from_class_instance(&self, _instance: &ClassInstance, _state_scope: &mut BlockScopes) -> LoxResult<(String, Option<Value>)> {
         handle_error(&self.line, ErrorType::RuntimeError, 
-            "Can only access property on class instance");
-        
+            "Can only access property on class instance")
     }
 
     fn get_line(&self) -> u32 {
@@ -164,77 +373,113 @@ impl  Expression for UnaryExpr {
         };
         format!("({op} {child})")
     }
+
+    fn static_type(&self, checker: &mut TypeChecker) -> StaticType {
+        let operand = self.value.static_type(checker);
+        match self.operator {
+            UnaryOperator::BANG => StaticType::Bool,
+            UnaryOperator::MINUS if matches!(operand, StaticType::Instance(_)) => StaticType::Any,
+            UnaryOperator::MINUS => {
+                if operand.is_known() && operand != StaticType::Number {
+                    checker.report(self.line, String::from("Operand must be a number."));
+                }
+                StaticType::Number
+            }
+        }
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        self.value.emit(emitter)?;
+        let op = match self.operator {
+            UnaryOperator::BANG => OpCode::Not,
+            UnaryOperator::MINUS => OpCode::Negate
+        };
+        emitter.op(op, self.line);
+        Ok(())
+    }
 }
 
 impl  Expression for BinaryExpr {
     
-    fn evaluate(&self, state_scope: &mut BlockScopes) -> Box<dyn Object> {
+    fn evaluate(&self, state_scope: &mut BlockScopes) -> LoxResult<Value> {
 
-        let val1 = self.value1.evaluate(state_scope);
+        let val1 = match state_scope.resume_partial(self) {
+            Some(mut partial) => partial.values.pop().unwrap(),
+            None => self.value1.evaluate(state_scope)?
+        };
         match self.operator {
             BinaryOperator::PLUS => {
-                let val2 = self.value2.evaluate(state_scope);
-                perform_add(val1, val2, &self.line)
+                let val2 = self.operand2(&val1, state_scope)?;
+                perform_add(val1, val2, state_scope, &self.line)
             },
             BinaryOperator::MINUS => {
-                let val2 = self.value2.evaluate(state_scope);
-                perform_num_op(val1, val2, |x, y| x - y, &self.line)
+                let val2 = self.operand2(&val1, state_scope)?;
+                perform_num_op(val1, val2, |x, y| x - y, "__sub__", state_scope, &self.line)
             },
             BinaryOperator::STAR => {
-                let val2 = self.value2.evaluate(state_scope);
-                perform_num_op(val1, val2, |x, y| x * y, &self.line)
+                let val2 = self.operand2(&val1, state_scope)?;
+                perform_num_op(val1, val2, |x, y| x * y, "__mul__", state_scope, &self.line)
             },
             BinaryOperator::SLASH => {
-                let val2 = self.value2.evaluate(state_scope);
-                perform_num_op(val1, val2, |x, y| x / y, &self.line)
+                let val2 = self.operand2(&val1, state_scope)?;
+                perform_num_op(val1, val2, |x, y| x / y, "__div__", state_scope, &self.line)
             },
             BinaryOperator::EQUALEQUAL => {
-                let val2 = self.value2.evaluate(state_scope);
-                check_equality(val1, val2, true)
+                let val2 = self.operand2(&val1, state_scope)?;
+                check_equality(val1, val2, true, state_scope, &self.line)
             },
             BinaryOperator::BANGEQUAL => {
-                let val2 = self.value2.evaluate(state_scope);
-                check_equality(val1, val2, false)
+                let val2 = self.operand2(&val1, state_scope)?;
+                check_equality(val1, val2, false, state_scope, &self.line)
             },
             BinaryOperator::GREATER => {
-                let val2 = self.value2.evaluate(state_scope);
-                perform_comparison(val1, val2, |x, y| x > y, &self.line)
+                let val2 = self.operand2(&val1, state_scope)?;
+                perform_comparison(val1, val2, |x, y| x > y, "__gt__", state_scope, &self.line)
             },
             BinaryOperator::GREATEREQUAL => {
-                let val2 = self.value2.evaluate(state_scope);
-                perform_comparison(val1, val2, |x, y| x >= y, &self.line)                
+                let val2 = self.operand2(&val1, state_scope)?;
+                perform_comparison(val1, val2, |x, y| x >= y, "__ge__", state_scope, &self.line)                
             },
             BinaryOperator::LESS => {
-                let val2 = self.value2.evaluate(state_scope);
-                perform_comparison(val1, val2, |x, y| x < y, &self.line)                
+                let val2 = self.operand2(&val1, state_scope)?;
+                perform_comparison(val1, val2, |x, y| x < y, "__lt__", state_scope, &self.line)                
             },
             BinaryOperator::LESSEQUAL => {
-                let val2 = self.value2.evaluate(state_scope);
-                perform_comparison(val1, val2, |x, y| x <= y, &self.line)                
+                let val2 = self.operand2(&val1, state_scope)?;
+                perform_comparison(val1, val2, |x, y| x <= y, "__le__", state_scope, &self.line)                
+            },
+            BinaryOperator::RANGE => {
+                let val2 = self.operand2(&val1, state_scope)?;
+                make_range(val1, val2, false, &self.line)
+            },
+            BinaryOperator::RANGEINCLUSIVE => {
+                let val2 = self.operand2(&val1, state_scope)?;
+                make_range(val1, val2, true, &self.line)
             },
             BinaryOperator::OR => {
-                if let Some(boolean) = val1.as_bool() {
-                    if boolean.0 {
-                        return val1;
-                    }
+                if val1.is_truthy() {
+                    return Ok(val1);
                 }
-                self.value2.evaluate(state_scope)
+                self.operand2(&val1, state_scope)
             },
             BinaryOperator::AND => {
-                if let Some(boolean) = val1.as_bool() {
-                    if !boolean.0 {
-                        return val1;
-                    }
+                if !val1.is_truthy() {
+                    return Ok(val1);
+                }
+                self.operand2(&val1, state_scope)            
+            },
+            BinaryOperator::NILCOALESCE => {
+                if val1.get_type() != Type::NIL {
+                    return Ok(val1);
                 }
-                self.value2.evaluate(state_scope)            
+                self.operand2(&val1, state_scope)
             }        
         }
     }
 
-    fn value_from_class_instance(&self, _instance: &ClassInstance, _state_scope: &mut BlockScopes) -> (String, Option<Box<dyn Object>>) {
+    fn value_from_class_instance(&self, _instance: &ClassInstance, _state_scope: &mut BlockScopes) -> LoxResult<(String, Option<Value>)> {
         handle_error(&self.line, ErrorType::RuntimeError, 
-            "Can only access property on class instance");
-        
+            "Can only access property on class instance")
     }
 
     fn get_line(&self) -> u32 {
@@ -247,4 +492,302 @@ impl  Expression for BinaryExpr {
         let operator_map = binary_op_map();
         format!("({} {child1} {child2})", operator_map[&self.operator]) 
     }
-}
\ No newline at end of file
+
+    fn static_type(&self, checker: &mut TypeChecker) -> StaticType {
+        let left = self.value1.static_type(checker);
+        let right = self.value2.static_type(checker);
+        match self.operator {
+            BinaryOperator::PLUS => {
+                match (&left, &right) {
+                    (StaticType::Number, StaticType::Number) => StaticType::Number,
+                    (StaticType::String, StaticType::String) => StaticType::String,
+                    (StaticType::Any, _) | (_, StaticType::Any) | (StaticType::Instance(_), _) => StaticType::Any,
+                    _ => {
+                        checker.report(self.line, String::from("Operands must be two numbers or two strings."));
+                        StaticType::Any
+                    }
+                }
+            },
+            BinaryOperator::MINUS | BinaryOperator::STAR | BinaryOperator::SLASH 
+            | BinaryOperator::LESS | BinaryOperator::LESSEQUAL | BinaryOperator::GREATER | BinaryOperator::GREATEREQUAL 
+                if matches!(left, StaticType::Instance(_)) => StaticType::Any,
+            BinaryOperator::MINUS | BinaryOperator::STAR | BinaryOperator::SLASH => {
+                checker.check_operands(&left, &right, self.line);
+                StaticType::Number
+            },
+            BinaryOperator::LESS | BinaryOperator::LESSEQUAL | BinaryOperator::GREATER | BinaryOperator::GREATEREQUAL => {
+                checker.check_operands(&left, &right, self.line);
+                StaticType::Bool
+            },
+            BinaryOperator::RANGE | BinaryOperator::RANGEINCLUSIVE => {
+                checker.check_operands(&left, &right, self.line);
+                StaticType::Range
+            },
+            BinaryOperator::EQUALEQUAL | BinaryOperator::BANGEQUAL => StaticType::Bool,
+            BinaryOperator::OR | BinaryOperator::AND => {
+                if left == right { left } else { StaticType::Any }
+            },
+            BinaryOperator::NILCOALESCE => {
+                if left == StaticType::Nil { right } else { left }
+            }
+        }
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        self.value1.emit(emitter)?;
+        let op = match self.operator {
+            BinaryOperator::PLUS => OpCode::Add,
+            BinaryOperator::MINUS => OpCode::Subtract,
+            BinaryOperator::STAR => OpCode::Multiply,
+            BinaryOperator::SLASH => OpCode::Divide,
+            BinaryOperator::EQUALEQUAL => OpCode::Equal,
+            BinaryOperator::BANGEQUAL => OpCode::NotEqual,
+            BinaryOperator::GREATER => OpCode::Greater,
+            BinaryOperator::GREATEREQUAL => OpCode::GreaterEqual,
+            BinaryOperator::LESS => OpCode::Less,
+            BinaryOperator::LESSEQUAL => OpCode::LessEqual,
+            BinaryOperator::RANGE => OpCode::Range(false),
+            BinaryOperator::RANGEINCLUSIVE => OpCode::Range(true),
+            BinaryOperator::OR | BinaryOperator::AND | BinaryOperator::NILCOALESCE => {
+                let jump = match self.operator {
+                    BinaryOperator::OR => OpCode::Or(0),
+                    BinaryOperator::AND => OpCode::And(0),
+                    _ => OpCode::NilCoalesce(0)
+                };
+                let at = emitter.jump(jump, self.line);
+                self.value2.emit(emitter)?;
+                emitter.patch_jump(at);
+                return Ok(());
+            }
+        };
+        self.value2.emit(emitter)?;
+        emitter.op(op, self.line);
+        Ok(())
+    }
+}
+impl BinaryExpr {
+    // The right operand; a call inside it that suspends the statement keeps the left one.
+    fn operand2(&self, val1: &Value, state_scope: &mut BlockScopes) -> LoxResult<Value> {
+        state_scope.evaluate_after(self, slice::from_ref(val1), self.value2.as_ref())
+    }
+}
+
+impl Expression for ListExpr {
+    fn evaluate(&self, state_scope: &mut BlockScopes) -> LoxResult<Value> {
+        let mut items = state_scope.resume_partial(self).map_or_else(Vec::new, |partial| partial.values);
+        for element in &self.elements[items.len()..] {
+            let item = state_scope.evaluate_after(self, &items, element.as_ref())?;
+            items.push(item);
+        }
+        Ok(Value::obj(List::new(items)))
+    }
+
+    fn value_from_class_instance(&self, _instance: &ClassInstance, _state_scope: &mut BlockScopes) -> LoxResult<(String, Option<Value>)> {
+        handle_error(&self.line, ErrorType::RuntimeError, 
+            "Can only access property on class instance")
+    }
+
+    fn get_line(&self) -> u32 {
+        self.line
+    }
+
+    fn to_string(&self) -> String {
+        let mut repr = String::from("(list");
+        for element in &self.elements {
+            repr.push(' ');
+            repr.push_str(&element.to_string());
+        }
+        repr.push(')');
+        repr
+    }
+
+    fn static_type(&self, checker: &mut TypeChecker) -> StaticType {
+        for element in &self.elements {
+            element.static_type(checker);
+        }
+        StaticType::List
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        let count = u16::try_from(self.elements.len()).map_err(|_| emitter.unsupported("more than 65536 list elements"))?;
+        for element in &self.elements {
+            element.emit(emitter)?;
+        }
+        emitter.op(OpCode::List(count), self.line);
+        Ok(())
+    }
+}
+
+impl Expression for IndexExpr {
+    fn evaluate(&self, state_scope: &mut BlockScopes) -> LoxResult<Value> {
+        let mut values = state_scope.resume_partial(self).map_or_else(Vec::new, |partial| partial.values);
+        if values.is_empty() {
+            let collection = self.collection.evaluate(state_scope)?;
+            if collection.get_type() == Type::NIL && self.collection.is_null_safe() {
+                return Ok(Value::Nil);
+            }
+            values.push(collection);
+        }
+        if values.len() == 1 {
+            let index = state_scope.evaluate_after(self, &values, self.index.as_ref())?;
+            values.push(index);
+        }
+        let index = &values[1];
+        match values[0].get_type() {
+            Type::LIST => {
+                let list = values[0].as_list().unwrap();
+                let position = resolve_index(index, list.len(), &self.line)?;
+                if let Some(value) = &self.value_to_assign {
+                    let evaluated_value = state_scope.evaluate_after(self, &values, value.as_ref())?;
+                    list.set(position, evaluated_value.clone());
+                    return Ok(evaluated_value);
+                }
+                Ok(list.get(position))
+            },
+            Type::MAP => {
+                let map = values[0].as_map().unwrap();
+                let key = map_key(index, &self.line)?;
+                if let Some(value) = &self.value_to_assign {
+                    let evaluated_value = state_scope.evaluate_after(self, &values, value.as_ref())?;
+                    map.set(key, evaluated_value.clone());
+                    return Ok(evaluated_value);
+                }
+                match map.get(&key) {
+                    Some(value) => Ok(value),
+                    None => handle_error(&self.line, ErrorType::RuntimeError, 
+                        format!("Undefined key '{}'.", index.to_str()).as_str())
+                }
+            },
+            Type::STRING => {
+                if self.value_to_assign.is_some() {
+                    return handle_error(&self.line, ErrorType::RuntimeError, "Strings are immutable.");
+                }
+                let chars: Vec<char> = values[0].as_str().unwrap().0.chars().collect();
+                let position = resolve_index(index, chars.len(), &self.line)?;
+                Ok(Value::obj(Str(chars[position].to_string())))
+            },
+            Type::CLASSINSTANCE => {
+                let overloaded = match &self.value_to_assign {
+                    Some(value) => {
+                        let evaluated_value = state_scope.evaluate_after(self, &values, value.as_ref())?;
+                        let index = values.pop().unwrap();
+                        call_operator_method(&mut values[0], "__setindex__", Vec::from([index, evaluated_value]), state_scope, &self.line)?
+                    },
+                    None => {
+                        let index = values.pop().unwrap();
+                        call_operator_method(&mut values[0], "__index__", Vec::from([index]), state_scope, &self.line)?
+                    }
+                };
+                match overloaded {
+                    Some(result) => Ok(result),
+                    None => handle_error(&self.line, ErrorType::RuntimeError, "Can only index lists, maps and strings.")
+                }
+            },
+            _ => {
+                handle_error(&self.line, ErrorType::RuntimeError, "Can only index lists, maps and strings.")
+            }
+        }
+    }
+
+    fn value_from_class_instance(&self, _instance: &ClassInstance, _state_scope: &mut BlockScopes) -> LoxResult<(String, Option<Value>)> {
+        handle_error(&self.line, ErrorType::RuntimeError, 
+            "Can only access property on class instance")
+    }
+
+    fn get_line(&self) -> u32 {
+        self.line
+    }
+
+    fn to_string(&self) -> String {
+        format!("(index {} {})", self.collection.to_string(), self.index.to_string())
+    }
+
+    fn is_null_safe(&self) -> bool {
+        self.collection.is_null_safe()
+    }
+
+    fn static_type(&self, checker: &mut TypeChecker) -> StaticType {
+        self.collection.static_type(checker);
+        self.index.static_type(checker);
+        if let Some(value) = &self.value_to_assign {
+            return value.static_type(checker);
+        }
+        StaticType::Any
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        self.collection.emit(emitter)?;
+        let nil_jump = self.collection.is_null_safe().then(|| emitter.jump(OpCode::JumpIfNil(0), self.line));
+        self.index.emit(emitter)?;
+        match &self.value_to_assign {
+            Some(value) => {
+                emitter.op(OpCode::CheckIndex, self.line);
+                value.emit(emitter)?;
+                emitter.op(OpCode::SetIndex, self.line);
+            },
+            None => {
+                emitter.op(OpCode::GetIndex, self.line);
+            }
+        }
+        if let Some(nil_jump) = nil_jump {
+            emitter.patch_jump(nil_jump);
+        }
+        Ok(())
+    }
+}
+
+impl Expression for MapExpr {
+    fn evaluate(&self, state_scope: &mut BlockScopes) -> LoxResult<Value> {
+        // Keys and values alternate in `values`.
+        let mut values = state_scope.resume_partial(self).map_or_else(Vec::new, |partial| partial.values);
+        let map = Map::new();
+        for (index, (key_expr, value_expr)) in self.entries.iter().enumerate() {
+            if values.len() == 2 * index {
+                let key = state_scope.evaluate_after(self, &values, key_expr.as_ref())?;
+                values.push(key);
+            }
+            if values.len() == 2 * index + 1 {
+                let value = state_scope.evaluate_after(self, &values, value_expr.as_ref())?;
+                values.push(value);
+            }
+            map.set(map_key(&values[2 * index], &self.line)?, values[2 * index + 1].clone());
+        }
+        Ok(Value::obj(map))
+    }
+
+    fn value_from_class_instance(&self, _instance: &ClassInstance, _state_scope: &mut BlockScopes) -> LoxResult<(String, Option<Value>)> {
+        handle_error(&self.line, ErrorType::RuntimeError, 
+            "Can only access property on class instance")
+    }
+
+    fn get_line(&self) -> u32 {
+        self.line
+    }
+
+    fn to_string(&self) -> String {
+        let mut repr = String::from("(map");
+        for (key, value) in &self.entries {
+            repr.push_str(&format!(" ({} {})", key.to_string(), value.to_string()));
+        }
+        repr.push(')');
+        repr
+    }
+
+    fn static_type(&self, checker: &mut TypeChecker) -> StaticType {
+        for (key, value) in &self.entries {
+            key.static_type(checker);
+            value.static_type(checker);
+        }
+        StaticType::Map
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        emitter.op(OpCode::MapNew, self.line);
+        for (key, value) in &self.entries {
+            key.emit(emitter)?;
+            value.emit(emitter)?;
+            emitter.op(OpCode::MapInsert, self.line);
+        }
+        Ok(())
+    }
+}
@@ -1,48 +1,59 @@
-use crate::error_handler::{handle_error, ErrorType};
-use crate::parser::declarations::{Bool, Number, Object, Type};
+use std::rc::Rc;
+use crate::error_handler::{handle_error, ErrorType, LoxResult};
+use crate::interpreter::block_scopes::BlockScopes;
+use crate::parser::declarations::{Range, Type, Value};
 
-pub fn perform_comparison<F>(data1: Box<dyn Object>, data2: Box<dyn Object>, f: F, line: &u32) -> Box<dyn Object>  
+// Dispatches to a special method such as `__add__` when the receiver is a class instance defining it.
+pub fn call_operator_method(receiver: &mut Value, method: &str, args: Vec<Value>, 
+    state: &mut BlockScopes, line: &u32) -> LoxResult<Option<Value>> 
+{
+    let Some(function) = receiver.as_class_instance().and_then(|instance| instance.get(&method.to_string())) else {
+        return Ok(None);
+    };
+    match function.as_function() {
+        Some(function) => function.call_with_args(args, state, line).map(Some),
+        None => Ok(None)
+    }
+}
+
+pub fn perform_comparison<F>(mut data1: Value, data2: Value, f: F, method: &str, 
+    state: &mut BlockScopes, line: &u32) -> LoxResult<Value>  
 where F: Fn(f64, f64) -> bool
 {
-    match (data1.get_type(), data2.get_type()) {
-        (Type::NUMBER, Type::NUMBER) => {
-            let num1 = data1.as_number().unwrap();
-            let num2 = data2.as_number().unwrap();
-            return Box::new(Bool(f(num1.0, num2.0)));
-        },
+    match (&data1, &data2) {
+        (Value::Number(num1), Value::Number(num2)) => Ok(Value::Bool(f(*num1, *num2))),
         _ => {
-            handle_error(line, ErrorType::RuntimeError, "Operand must be a number.");
+            if let Some(result) = call_operator_method(&mut data1, method, Vec::from([data2]), state, line)? {
+                return Ok(result);
+            }
+            handle_error(line, ErrorType::RuntimeError, "Operand must be a number.")
         }
     }
 }
 
-pub fn perform_num_op<F>(data1: Box<dyn Object>, data2: Box<dyn Object>, f: F, line: &u32) -> Box<dyn Object>  
+pub fn perform_num_op<F>(mut data1: Value, data2: Value, f: F, method: &str, 
+    state: &mut BlockScopes, line: &u32) -> LoxResult<Value>  
 where F: Fn(f64, f64) -> f64
 {
-    match (data1.get_type(), data2.get_type()) {
-        (Type::NUMBER, Type::NUMBER) => {
-            let num1 = data1.as_number().unwrap();
-            let num2 = data2.as_number().unwrap();
-            return Box::new(Number(f(num1.0, num2.0)));
-        },
+    match (&data1, &data2) {
+        (Value::Number(num1), Value::Number(num2)) => Ok(Value::Number(f(*num1, *num2))),
         _ => {
-            handle_error(line, ErrorType::RuntimeError, "Operand must be a number.");
+            if let Some(result) = call_operator_method(&mut data1, method, Vec::from([data2]), state, line)? {
+                return Ok(result);
+            }
+            handle_error(line, ErrorType::RuntimeError, "Operand must be a number.")
         }
     }
 }
 
-pub fn perform_add(data1: Box<dyn Object>, data2: Box<dyn Object>, line: &u32) -> Box<dyn Object> 
+pub fn perform_add(mut data1: Value, data2: Value, state: &mut BlockScopes, line: &u32) -> LoxResult<Value> 
 {
-    match (data1.get_type(), data2.get_type()) {
-        (Type::NUMBER, Type::NUMBER) => {
-            let num1 = data1.as_number().unwrap();
-            let num2 = data2.as_number().unwrap();
-            return Box::new(num1.clone() + num2.clone());
-        },
-        (Type::STRING, Type::STRING) => {
-            let str1 = data1.as_str().unwrap();
-            let str2 = data2.as_str().unwrap();
-            return Box::new(str1.clone() + str2.clone());            
+    if let (Value::Number(num1), Value::Number(num2)) = (&data1, &data2) {
+        return Ok(Value::Number(num1 + num2));
+    }
+    match (data1.as_str(), data2.as_str()) {
+        (Some(str1), Some(str2)) => {
+            return Ok(Value::obj(str1.clone() + str2.clone()));            
         },
         _ => {
             // if data1.get_type() == Type::STRING || data2.get_type() == Type::STRING {
@@ -51,35 +62,74 @@ pub fn perform_add(data1: Box<dyn Object>, data2: Box<dyn Object>, line: &u32) -
             //     let str = str1 + str2;
             //     return Box::new(Str(str.to_string())); 
             // }
-            handle_error(line, ErrorType::RuntimeError, "Operands must be two numbers or two strings.");
+            if let Some(result) = call_operator_method(&mut data1, "__add__", Vec::from([data2]), state, line)? {
+                return Ok(result);
+            }
+            handle_error(line, ErrorType::RuntimeError, "Operands must be two numbers or two strings.")
         }
     }
 }
 
 
+pub fn make_range(data1: Value, data2: Value, inclusive: bool, line: &u32) -> LoxResult<Value>
+{
+    match (data1, data2) {
+        (Value::Number(start), Value::Number(end)) => Ok(Value::obj(Range::new(start, end, inclusive))),
+        _ => {
+            handle_error(line, ErrorType::RuntimeError, "Operands must be numbers.")
+        }
+    }
+}
+
 
-pub fn check_equality(data1: Box<dyn Object>, data2: Box<dyn Object>, check: bool) -> Box<dyn Object>  
+pub fn check_equality(mut data1: Value, mut data2: Value, check: bool, 
+    state: &mut BlockScopes, line: &u32) -> LoxResult<Value>  
 {
-    let boolean = match (data1.get_type(), data2.get_type()) {
-        (Type::BOOLEAN, Type::BOOLEAN) => {
-            let b1 = data1.as_bool().unwrap();
-            let b2 = data2.as_bool().unwrap();
-            Bool((b1.0 == b2.0) == check)          
-        },
-        (Type::NUMBER, Type::NUMBER) => {
-            let num1 = data1.as_number().unwrap();
-            let num2 = data2.as_number().unwrap();
-            Bool((num1.0 == num2.0) == check)
+    if data1.get_type() == Type::CLASSINSTANCE || data2.get_type() == Type::CLASSINSTANCE {
+        let overloaded = match call_operator_method(&mut data1, "__eq__", Vec::from([data2.clone()]), state, line)? {
+            Some(result) => Some(result),
+            None => call_operator_method(&mut data2, "__eq__", Vec::from([data1.clone()]), state, line)?
+        };
+        if let Some(result) = overloaded {
+            return Ok(Value::Bool(result.is_truthy() == check));
+        }
+    }
+    Ok(Value::Bool(values_equal(&mut data1, &mut data2) == check))
+}
+
+
+// Equality without the `__eq__` methods: by value for primitives, by identity for containers.
+pub fn values_equal(data1: &mut Value, data2: &mut Value) -> bool {
+    match (data1.get_type(), data2.get_type()) {
+        (Type::NIL, Type::NIL) => true,
+        (Type::BOOLEAN, Type::BOOLEAN) => data1.as_bool() == data2.as_bool(),
+        (Type::NUMBER, Type::NUMBER) => data1.as_number() == data2.as_number(),
+        (Type::STRING, Type::STRING) => data1.as_str().unwrap().0 == data2.as_str().unwrap().0,
+        (Type::LIST, Type::LIST) => Rc::ptr_eq(&data1.as_list().unwrap().0, &data2.as_list().unwrap().0),
+        (Type::MAP, Type::MAP) => Rc::ptr_eq(&data1.as_map().unwrap().0, &data2.as_map().unwrap().0),
+        (Type::RANGE, Type::RANGE) => data1.as_range() == data2.as_range(),
+        (Type::ENUMVARIANT, Type::ENUMVARIANT) => {
+            Rc::ptr_eq(&data1.as_enum_variant().unwrap().0, &data2.as_enum_variant().unwrap().0)
         },
-        (Type::STRING, Type::STRING) => {
-            let str1 = data1.as_str().unwrap();
-            let str2 = data2.as_str().unwrap();
-            Bool((str1.0 == str2.0) == check)
+        (Type::CLASSINSTANCE, Type::CLASSINSTANCE) => {
+            Rc::ptr_eq(&data1.as_class_instance().unwrap().attributes, &data2.as_class_instance().unwrap().attributes)
         },
-        _  => {
-            Bool(false == check)
-        }      
-    };
-    Box::new(boolean)
+        (Type::ENUM, Type::ENUM) => Rc::ptr_eq(&data1.as_enum().unwrap().variants, &data2.as_enum().unwrap().variants),
+        (Type::VMOBJECT, Type::VMOBJECT) => data1.as_vm_object().unwrap().0.equals(&data2.as_vm_object().unwrap().0),
+        _ => false
+    }
 }
 
+
+pub fn resolve_index(index: &Value, len: usize, line: &u32) -> LoxResult<usize> {
+    let position = match index.as_number() {
+        Some(num) if num.fract() == 0.0 => num as i64,
+        Some(_) => return handle_error(line, ErrorType::RuntimeError, "Index must be an integer."),
+        None => return handle_error(line, ErrorType::RuntimeError, "Index must be a number.")
+    };
+    let position = if position < 0 { position + len as i64 } else { position };
+    if position < 0 || position >= len as i64 {
+        return handle_error(line, ErrorType::RuntimeError, "Index out of bounds.");
+    }
+    Ok(position as usize)
+}
@@ -0,0 +1,138 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::class::ClassInstance;
+use crate::error_handler::{handle_error, ErrorType, LoxResult};
+use crate::generator::Generator;
+use crate::interpreter::block_scopes::BlockScopes;
+use crate::parser::declarations::{List, MapKey, Object, Range, Str, Type, Value, ValueObjTrait};
+
+pub const ITERATOR_KEY: &str = "@iterator";
+
+pub enum IterSource {
+    List(List),
+    Chars(Vec<char>),
+    Keys(Vec<MapKey>),
+    Range(Range),
+    Generator(Generator),
+    Instance(ClassInstance)
+}
+
+pub struct IteratorState {
+    pub source: IterSource,
+    pub position: usize
+}
+
+#[derive(Clone)]
+pub struct LoxIterator (pub Rc<RefCell<IteratorState>>);
+
+impl Object for LoxIterator {
+    fn to_str(&self) -> Cow<'static, str> {
+        Cow::Borrowed("<iterator>")
+    }
+
+    fn get_type(&self) -> Type {
+        Type::ITERATOR
+    }
+
+    fn dyn_clone(&self) -> Box<dyn Object> {
+        Box::new(LoxIterator(self.0.clone()))
+    }
+}
+
+impl ValueObjTrait for LoxIterator {
+    fn as_iterator(&self) -> Option<&LoxIterator> {
+        Some(self)
+    }
+}
+
+impl ToString for LoxIterator {
+    fn to_string(&self) -> String {
+        self.to_str().to_string()
+    }
+}
+
+impl LoxIterator {
+    fn new(source: IterSource) -> LoxIterator {
+        LoxIterator(Rc::new(RefCell::new(IteratorState { source, position: 0 })))
+    }
+
+    pub fn from_object(mut iterable: Value, state: &mut BlockScopes, line: &u32) -> LoxResult<LoxIterator> {
+        let iterator = match iterable.get_type() {
+            Type::LIST => LoxIterator::new(IterSource::List(iterable.as_list().unwrap().clone())),
+            Type::STRING => LoxIterator::new(IterSource::Chars(iterable.as_str().unwrap().0.chars().collect())),
+            Type::MAP => LoxIterator::new(IterSource::Keys(iterable.as_map().unwrap().keys())),
+            Type::RANGE => LoxIterator::new(IterSource::Range(iterable.as_range().unwrap().clone())),
+            Type::ENUM => LoxIterator::new(IterSource::List(iterable.as_enum().unwrap().values())),
+            Type::ITERATOR => iterable.as_iterator().unwrap().clone(),
+            Type::GENERATOR => LoxIterator::new(IterSource::Generator(iterable.as_generator().unwrap().clone())),
+            Type::CLASSINSTANCE => {
+                let instance = iterable.as_class_instance().unwrap().clone();
+                if instance.get(&String::from("iterator")).is_none() {
+                    return Ok(LoxIterator::new(IterSource::Instance(instance)));
+                }
+                let mut iterator = call_method(&instance, "iterator", state, line)?;
+                if iterator.get_type() == Type::CLASSINSTANCE {
+                    let iterator_instance = iterator.as_class_instance().unwrap().clone();
+                    return Ok(LoxIterator::new(IterSource::Instance(iterator_instance)));
+                }
+                return LoxIterator::from_object(iterator, state, line);
+            },
+            _ => return handle_error(line, ErrorType::RuntimeError,
+                "Can only iterate over lists, maps, strings, ranges, enums, generators and iterators.")
+        };
+        Ok(iterator)
+    }
+
+    pub fn next_value(&self, state: &mut BlockScopes, line: &u32) -> LoxResult<Option<Value>> {
+        let mut iter_state = self.0.borrow_mut();
+        let position = iter_state.position;
+        let value: Value = match &iter_state.source {
+            IterSource::List(list) => {
+                if position >= list.len() {
+                    return Ok(None);
+                }
+                list.get(position)
+            },
+            IterSource::Chars(chars) => match chars.get(position) {
+                Some(c) => Value::obj(Str(c.to_string())),
+                None => return Ok(None)
+            },
+            IterSource::Keys(keys) => match keys.get(position) {
+                Some(key) => key.to_value(),
+                None => return Ok(None)
+            },
+            IterSource::Range(range) => match range.value_at(position) {
+                Some(value) => Value::Number(value),
+                None => return Ok(None)
+            },
+            IterSource::Generator(generator) => {
+                let generator = generator.clone();
+                drop(iter_state);
+                return generator.resume(state);
+            },
+            IterSource::Instance(instance) => {
+                let instance = instance.clone();
+                drop(iter_state);
+                let has_next = call_method(&instance, "hasNext", state, line)?;
+                if !has_next.is_truthy() {
+                    return Ok(None);
+                }
+                return call_method(&instance, "next", state, line).map(Some);
+            }
+        };
+        iter_state.position += 1;
+        Ok(Some(value))
+    }
+}
+
+fn call_method(instance: &ClassInstance, name: &str, state: &mut BlockScopes, line: &u32) -> LoxResult<Value> {
+    match instance.get(&name.to_string()) {
+        Some(method) if method.get_type() == Type::FUNCTION => {
+            method.as_function().unwrap().call_with_args(Vec::new(), state, line)
+        },
+        _ => handle_error(line, ErrorType::RuntimeError,
+            format!("Iterator must define a '{}' method.", name).as_str())
+    }
+}
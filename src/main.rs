@@ -1,23 +1,39 @@
 use std::env;
 use std::fs;
 use std::io::{self, Write};
+use std::path::PathBuf;
 use std::process;
+use std::rc::Rc;
 
 use crate::compiler::Compiler;
-use crate::error_handler::LEXICAL_ERROR_CODE;
-use crate::interpreter::block_scopes::BlockScopes;
+use crate::error_handler::{ErrorType, LoxError, LoxResult, LEXICAL_ERROR_CODE, RUNTIME_ERROR_CODE, SYNTAXIC_ERROR_CODE};
+use crate::interpreter::block_scopes::{set_max_call_depth, BlockScopes, DEFAULT_MAX_CALL_DEPTH};
 use crate::interpreter::Interpreter;
 use crate::parser::Parser;
+use crate::scanner::declarations::Token;
 use crate::scanner::display_token;
 use crate::scanner::tokenize;
+use crate::statements::Statement;
 mod scanner;
 mod error_handler;
 mod parser;
 mod statements;
+mod fiber;
+mod gc;
 mod function;
+mod generator;
 mod interpreter;
 mod class;
+mod enums;
 mod compiler;
+mod native;
+mod iterator;
+mod module;
+mod typecheck;
+mod vm;
+
+// Flags followed by a value.
+const VALUE_FLAGS: [&str; 2] = ["--max-call-depth", "--backend"];
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -27,60 +43,75 @@ fn main() {
     }
 
     let command = &args[1];
-    let filename = &args[2];
+    let (flags, filename) = split_args(&args[2..]);
+    let max_call_depth = match flag_value(&flags, "--max-call-depth") {
+        Some(value) => value.parse().unwrap_or_else(|_| {
+            writeln!(io::stderr(), "Invalid --max-call-depth: {}", value).unwrap();
+            process::exit(64);
+        }),
+        None => DEFAULT_MAX_CALL_DEPTH
+    };
+    set_max_call_depth(max_call_depth);
+    gc::set_stress(flags.contains(&"--gc-stress"));
+    let backend = flag_value(&flags, "--backend").unwrap_or("tree");
+    if !["tree", "vm", "auto"].contains(&backend) {
+        writeln!(io::stderr(), "Invalid --backend: {}", backend).unwrap();
+        process::exit(64);
+    }
     // You can use print statements as follows for debugging, they'll be visible when running tests.
     writeln!(io::stderr(), "Logs from your program will appear here!").unwrap();
 
     match command.as_str() {
         "tokenize" => {
             let file_contents = file_text(filename);
-            let mut errors = false;
+            let mut errors = Vec::new();
             let tokens = tokenize(file_contents, &mut errors);
             display_token(tokens);
-            println!("EOF  null"); 
-            if errors {
-                process::exit(LEXICAL_ERROR_CODE);
+            if !errors.is_empty() {
+                report_all_and_exit(&errors);
             }
         } ,
         "parse" => {
-            let file_contents = file_text(filename);
-            let mut errors = false;
-            let tokens = tokenize(file_contents, &mut errors);
-            if errors {
-                process::exit(LEXICAL_ERROR_CODE);
-            }
+            let tokens = tokens_or_exit(filename);
             let mut parser = Parser::new(&tokens, 0);
-            let express = parser.expression();
+            let express = or_exit(parser.expression());
             println!("{}", express.to_string());
 
         },
         "evaluate" => {
-            let file_contents = file_text(filename);
-            let mut errors = false;
-
-            let tokens = tokenize(file_contents, &mut errors);
-            if errors {
-                process::exit(LEXICAL_ERROR_CODE);
-            }
+            let tokens = tokens_or_exit(filename);
             let mut parser = Parser::new(&tokens, 0);
-            let express = parser.expression();
+            let express = or_exit(parser.expression());
             let mut scope: BlockScopes = BlockScopes::new();
             
-            let result = express.evaluate(&mut scope);
+            let result = or_exit(express.evaluate(&mut scope));
             println!("{}", result.to_str());
         },
         "run" => {
-            let file_contents = file_text(filename);
-            let mut errors = false;
-
-            let tokens = tokenize(file_contents, &mut errors);
-            if errors {
-                process::exit(LEXICAL_ERROR_CODE);
-            }
+            let tokens = tokens_or_exit(filename);
             let parser = Parser::new(&tokens, 0);
-            let compiler = Compiler::new(parser);
+            let mut compiler = Compiler::new(parser);
+            compiler.source_path = PathBuf::from(filename);
+            module::set_entry_file(filename);
             let mut interpreter = Interpreter::new(compiler);
-            interpreter.exec();
+            let stmts = Rc::new(or_exit(interpreter.compile()));
+            if flags.contains(&"--typecheck") {
+                typecheck_or_exit(&stmts);
+            }
+            // `auto` runs on the tree-walker the scripts the VM doesn't compile.
+            if backend != "tree" && or_exit(vm::run(&stmts, backend == "auto")) {
+                return;
+            }
+            or_exit(interpreter.exec(&stmts));
+        },
+        "check" => {
+            let tokens = tokens_or_exit(filename);
+            let mut compiler = Compiler::new(Parser::new(&tokens, 0));
+            compiler.source_path = PathBuf::from(filename);
+            let stmts = or_exit(compiler.compile());
+            if flags.contains(&"--types") {
+                typecheck_or_exit(&stmts);
+            }
         },
         _ => {
             writeln!(io::stderr(), "Unknown command: {}", command).unwrap();
@@ -90,6 +121,72 @@ fn main() {
 }
 
 
+// Options start with `--` and some take the next argument as their value;
+// the first other argument is the script.
+fn split_args(args: &[String]) -> (Vec<&str>, &String) {
+    let mut flags: Vec<&str> = Vec::new();
+    let mut filename = None;
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        if VALUE_FLAGS.contains(&arg.as_str()) {
+            flags.push(arg);
+            flags.extend(rest.next().map(|value| value.as_str()));
+        }
+        else if arg.starts_with("--") {
+            flags.push(arg);
+        }
+        else if filename.is_none() {
+            filename = Some(arg);
+        }
+    }
+    let filename = filename.unwrap_or_else(|| {
+        writeln!(io::stderr(), "Missing <filename>").unwrap();
+        process::exit(64);
+    });
+    (flags, filename)
+}
+
+fn flag_value<'a>(flags: &[&'a str], name: &str) -> Option<&'a str> {
+    let position = flags.iter().position(|flag| *flag == name)?;
+    flags.get(position + 1).copied()
+}
+
+fn exit_code(kind: ErrorType) -> i32 {
+    match kind {
+        ErrorType::LexicalError => LEXICAL_ERROR_CODE,
+        ErrorType::SyntacticError | ErrorType::TypeError => SYNTAXIC_ERROR_CODE,
+        ErrorType::RuntimeError | ErrorType::FiberBlocked | ErrorType::Suspended => RUNTIME_ERROR_CODE
+    }
+}
+
+// Errors surface here and only here: they are reported and mapped to the exit code.
+fn or_exit<T>(result: LoxResult<T>) -> T {
+    result.unwrap_or_else(|error| report_all_and_exit(&[error]))
+}
+
+fn report_all_and_exit(errors: &[LoxError]) -> ! {
+    for error in errors {
+        eprintln!("{error}");
+    }
+    process::exit(exit_code(errors[0].kind));
+}
+
+fn tokens_or_exit(filename: &String) -> Vec<Token> {
+    let mut errors = Vec::new();
+    let tokens = tokenize(file_text(filename), &mut errors);
+    if !errors.is_empty() {
+        report_all_and_exit(&errors);
+    }
+    tokens
+}
+
+fn typecheck_or_exit(stmts: &Vec<Box<dyn Statement>>) {
+    let errors = typecheck::check(stmts);
+    if !errors.is_empty() {
+        report_all_and_exit(&errors);
+    }
+}
+
 fn file_text(filename: &String) -> String {
     let file_contents = fs::read_to_string(filename).unwrap_or_else(|_| {
         writeln!(io::stderr(), "Failed to read file {}", filename).unwrap();
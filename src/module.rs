@@ -0,0 +1,171 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::compiler::Compiler;
+use crate::error_handler::{current_file, handle_error, set_current_file, ErrorType, LoxResult};
+use crate::interpreter::block_scopes::BlockScopes;
+use crate::interpreter::Interpreter;
+use crate::parser::declarations::{Object, RefObject, Type, Value, ValueObjTrait};
+use crate::parser::Parser;
+use crate::scanner::tokenize;
+use crate::statements::Statement;
+
+pub const LOX_PATH_VAR: &str = "LOX_PATH";
+
+pub struct ModuleScope {
+    pub file: String,
+    pub globals: RefCell<HashMap<String, RefObject>>,
+    pub constants: RefCell<HashSet<String>>
+}
+
+pub type ModuleRef = Rc<ModuleScope>;
+
+#[derive(Clone)]
+pub struct Module {
+    pub name: String,
+    pub scope: ModuleRef
+}
+
+thread_local! {
+    static MODULE_CACHE: RefCell<HashMap<PathBuf, Module>> = RefCell::new(HashMap::new());
+    static LOADING: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) };
+}
+
+impl Object for Module {
+    fn to_str(&self) -> Cow<'static, str> {
+        Cow::Owned(format!("<module {}>", self.name))
+    }
+
+    fn get_type(&self) -> Type {
+        Type::MODULE
+    }
+
+    fn dyn_clone(&self) -> Box<dyn Object> {
+        Box::new(self.clone())
+    }
+}
+
+impl ValueObjTrait for Module {
+    fn as_module(&self) -> Option<&Module> {
+        Some(self)
+    }
+}
+
+impl ToString for Module {
+    fn to_string(&self) -> String {
+        self.to_str().to_string()
+    }
+}
+
+impl Module {
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.scope.globals.borrow().get(name).map(|value| value.borrow().clone())
+    }
+}
+
+pub fn set_entry_file(filename: &str) {
+    if let Ok(path) = fs::canonicalize(filename) {
+        LOADING.with_borrow_mut(|loading| loading.push(path));
+    }
+}
+
+pub fn resolve_path(base_dir: &Path, spec: &str, line: &u32) -> LoxResult<PathBuf> {
+    let relative = base_dir.join(spec);
+    if relative.is_file() {
+        return Ok(relative);
+    }
+    if let Some(search_path) = env::var_os(LOX_PATH_VAR) {
+        for dir in env::split_paths(&search_path) {
+            let candidate = dir.join(spec);
+            if candidate.is_file() {
+                return Ok(candidate);
+            }
+        }
+    }
+    handle_error(line, ErrorType::RuntimeError, format!("Cannot find module '{}'.", spec).as_str())
+}
+
+pub fn load_module(path: &Path, line: &u32) -> LoxResult<Module> {
+    let key = module_key(path);
+    if let Some(module) = MODULE_CACHE.with_borrow(|cache| cache.get(&key).cloned()) {
+        return Ok(module);
+    }
+    let file = path.display().to_string();
+    let scope = Rc::new(ModuleScope { file, globals: RefCell::new(HashMap::new()),
+        constants: RefCell::new(HashSet::new()) });
+    load(path, &key, line, |stmts| run_module(stmts, &scope))?;
+
+    let module = Module { name: module_name(path), scope };
+    MODULE_CACHE.with_borrow_mut(|cache| cache.insert(key, module.clone()));
+    Ok(module)
+}
+
+// Modules are cached under their canonical path.
+pub fn module_key(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+pub fn module_name(path: &Path) -> String {
+    path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().to_string())
+}
+
+// Compiles the module at `path` and has `run` run it, with its file as the
+// current one; a module importing one still being loaded is an error.
+pub fn load<T, F>(path: &Path, key: &Path, line: &u32, run: F) -> LoxResult<T>
+where F: FnOnce(Vec<Box<dyn Statement>>) -> LoxResult<T>
+{
+    let cycle = LOADING.with_borrow(|loading| {
+        let start = loading.iter().position(|loaded| *loaded == key)?;
+        let mut chain: Vec<String> = loading[start..].iter().map(|loaded| file_label(loaded)).collect();
+        chain.push(file_label(key));
+        Some(chain.join(" -> "))
+    });
+    if let Some(chain) = cycle {
+        return handle_error(line, ErrorType::RuntimeError, format!("Circular import: {}.", chain).as_str());
+    }
+
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(_) => return handle_error(line, ErrorType::RuntimeError,
+            format!("Could not read module '{}'.", path.display()).as_str())
+    };
+
+    LOADING.with_borrow_mut(|loading| loading.push(key.to_path_buf()));
+    let previous_file = current_file();
+    set_current_file(Some(path.display().to_string()));
+    let result = compile_module(source, path).and_then(run);
+    set_current_file(previous_file);
+    LOADING.with_borrow_mut(|loading| loading.pop());
+    result
+}
+
+fn compile_module(source: String, path: &Path) -> LoxResult<Vec<Box<dyn Statement>>> {
+    let mut errors = Vec::new();
+    let tokens = tokenize(source, &mut errors);
+    if let Some(error) = errors.into_iter().next() {
+        return Err(error);
+    }
+    let mut compiler = Compiler::new(Parser::new(&tokens, 0));
+    compiler.source_path = path.to_path_buf();
+    compiler.compile()
+}
+
+fn run_module(stmts: Vec<Box<dyn Statement>>, scope: &ModuleRef) -> LoxResult<()> {
+    let mut state = BlockScopes::new();
+    state.current_module = Some(scope.clone());
+    Interpreter::init_globals(&mut state)?;
+    Interpreter::run(&mut state, &Rc::new(stmts))?;
+    *scope.globals.borrow_mut() = mem::take(&mut state.globals);
+    *scope.constants.borrow_mut() = mem::take(&mut state.global_constants);
+    Ok(())
+}
+
+fn file_label(path: &Path) -> String {
+    path.file_name().map_or(path.display().to_string(), |name| name.to_string_lossy().to_string())
+}
@@ -0,0 +1,137 @@
+use std::borrow::Cow;
+use std::ops::RangeInclusive;
+
+use crate::error_handler::{handle_error, ErrorType, LoxResult};
+use crate::gc::Tracer;
+use crate::interpreter::block_scopes::BlockScopes;
+use crate::parser::declarations::{Object, Type, Value, ValueObjTrait};
+pub mod enum_methods;
+pub mod fiber_methods;
+pub mod gc_methods;
+pub mod generator_methods;
+pub mod list_methods;
+pub mod map_methods;
+pub mod range_methods;
+pub mod string_methods;
+
+pub type NativeFn = fn(&Value, Vec<Value>, &mut dyn Host, &u32) -> LoxResult<Value>;
+
+// The backend a native method runs in, which calls the callbacks it is given.
+pub trait Host {
+    fn call(&mut self, callee: &Value, args: Vec<Value>, line: &u32) -> LoxResult<Value>;
+
+    // The tree-walker's state, for the natives only it provides.
+    fn scopes(&mut self) -> &mut BlockScopes;
+}
+
+#[derive(Clone)]
+pub struct NativeMethod {
+    pub name: &'static str,
+    pub receiver: Value,
+    pub arity: RangeInclusive<usize>,
+    pub method: NativeFn
+}
+
+impl Object for NativeMethod {
+    fn to_str(&self) -> Cow<'static, str> {
+        Cow::Owned(format!("<native fn {}>", self.name))
+    }
+
+    fn get_type(&self) -> Type {
+        Type::NATIVEMETHOD
+    }
+
+    fn dyn_clone(&self) -> Box<dyn Object> {
+        Box::new(self.clone())
+    }
+
+    fn trace(&self, tracer: &mut Tracer) {
+        self.receiver.trace(tracer);
+    }
+}
+
+impl ValueObjTrait for NativeMethod {
+    fn as_native_method(&self) -> Option<&NativeMethod> {
+        Some(self)
+    }
+}
+
+impl ToString for NativeMethod {
+    fn to_string(&self) -> String {
+        self.to_str().to_string()
+    }
+}
+
+impl NativeMethod {
+    pub fn call_with_args(&self, args: Vec<Value>, state: &mut dyn Host, line: &u32) -> LoxResult<Value> {
+        self.check_arity(args.len(), line)?;
+        (self.method)(&self.receiver, args, state, line)
+    }
+
+    pub fn check_arity(&self, recv_params_len: usize, line: &u32) -> LoxResult<()> {
+        if !self.arity.contains(&recv_params_len) {
+            let (min, max) = (*self.arity.start(), *self.arity.end());
+            let expected = if min == max { min.to_string() } else { format!("{} to {}", min, max) };
+            return handle_error(line, ErrorType::RuntimeError, 
+                format!("Expected {} arguments but got {}", expected, recv_params_len).as_str());
+        }
+        Ok(())
+    }
+}
+
+impl Host for BlockScopes {
+    fn call(&mut self, callee: &Value, args: Vec<Value>, line: &u32) -> LoxResult<Value> {
+        match callee.get_type() {
+            Type::FUNCTION => callee.as_function().unwrap().call_with_args(args, self, line),
+            Type::CLASS => Ok(Value::obj(callee.as_class().unwrap().call_with_args(args, self, line)?)),
+            Type::NATIVEMETHOD => callee.as_native_method().unwrap().call_with_args(args, self, line),
+            _ => handle_error(line, ErrorType::RuntimeError, "Can only call functions and classes.")
+        }
+    }
+
+    fn scopes(&mut self) -> &mut BlockScopes {
+        self
+    }
+}
+
+pub type NativeEntry = (&'static str, RangeInclusive<usize>, NativeFn);
+
+pub fn native_property(receiver: &Value, name: &str) -> Option<Value> {
+    if let Some(value) = native_field(receiver, name) {
+        return Some(value);
+    }
+    let (name, arity, method) = match receiver.get_type() {
+        Type::LIST => list_methods::lookup(name)?,
+        Type::MAP => map_methods::lookup(name)?,
+        Type::RANGE => range_methods::lookup(name)?,
+        Type::STRING => string_methods::lookup(name)?,
+        Type::ENUM => enum_methods::lookup(name)?,
+        Type::GENERATOR => generator_methods::lookup(name)?,
+        Type::CHANNEL => fiber_methods::channel_lookup(name)?,
+        _ => return None
+    };
+    Some(Value::obj(NativeMethod {
+        name,
+        receiver: receiver.clone(),
+        arity,
+        method
+    }))
+}
+
+pub fn has_native_properties(receiver: &Value) -> bool {
+    matches!(receiver.get_type(), Type::LIST | Type::MAP | Type::RANGE | Type::STRING | Type::MODULE
+        | Type::ENUM | Type::ENUMVARIANT | Type::GENERATOR | Type::FIBER | Type::CHANNEL)
+}
+
+fn native_field(receiver: &Value, name: &str) -> Option<Value> {
+    match receiver.get_type() {
+        Type::LIST => list_methods::field(receiver, name),
+        Type::STRING => string_methods::field(receiver, name),
+        Type::MODULE => receiver.as_module().unwrap().get(name),
+        Type::ENUM => enum_methods::field(receiver, name),
+        Type::ENUMVARIANT => enum_methods::variant_field(receiver, name),
+        Type::GENERATOR => generator_methods::field(receiver, name),
+        Type::FIBER => fiber_methods::fiber_field(receiver, name),
+        _ => None
+    }
+}
@@ -0,0 +1,28 @@
+use crate::error_handler::LoxResult;
+use crate::native::{Host, NativeEntry};
+use crate::parser::declarations::{Str, Value};
+
+pub fn lookup(name: &str) -> Option<NativeEntry> {
+    match name {
+        "values" => Some(("values", 0..=0, values)),
+        _ => None
+    }
+}
+
+pub fn field(receiver: &Value, name: &str) -> Option<Value> {
+    let variant = receiver.as_enum()?.variant(name)?;
+    Some(Value::obj(variant.clone()))
+}
+
+pub fn variant_field(receiver: &Value, name: &str) -> Option<Value> {
+    let variant = &receiver.as_enum_variant()?.0;
+    match name {
+        "name" => Some(Value::obj(Str(variant.name.clone()))),
+        "ordinal" => Some(Value::Number(variant.ordinal as f64)),
+        _ => None
+    }
+}
+
+fn values(receiver: &Value, _args: Vec<Value>, _state: &mut dyn Host, _line: &u32) -> LoxResult<Value> {
+    Ok(Value::obj(receiver.as_enum().unwrap().values()))
+}
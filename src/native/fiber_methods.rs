@@ -0,0 +1,58 @@
+use crate::error_handler::{handle_error, ErrorType, LoxResult};
+use crate::fiber::{Channel, Fiber};
+use crate::interpreter::block_scopes::BlockScopes;
+use crate::native::{Host, NativeEntry, NativeMethod};
+use crate::parser::declarations::{Type, Value};
+
+pub fn define_globals(state: &mut BlockScopes) {
+    let globals: [NativeEntry; 2] = [
+        ("spawn", 1..=255, spawn),
+        ("Channel", 0..=0, channel)
+    ];
+    for (name, arity, method) in globals {
+        state.define_global(name, Value::obj(NativeMethod {
+            name,
+            receiver: Value::Nil,
+            arity,
+            method
+        }));
+    }
+}
+
+pub fn channel_lookup(name: &str) -> Option<NativeEntry> {
+    match name {
+        "send" => Some(("send", 1..=1, send)),
+        "receive" => Some(("receive", 0..=0, receive)),
+        _ => None
+    }
+}
+
+pub fn fiber_field(receiver: &Value, name: &str) -> Option<Value> {
+    match name {
+        "done" => Some(Value::Bool(receiver.as_fiber()?.is_done())),
+        _ => None
+    }
+}
+
+fn spawn(_receiver: &Value, mut args: Vec<Value>, _state: &mut dyn Host, line: &u32) -> LoxResult<Value> {
+    let function = args.remove(0);
+    if function.get_type() != Type::FUNCTION {
+        return handle_error(line, ErrorType::RuntimeError, "Can only spawn functions.");
+    }
+    let function = function.as_function().unwrap();
+    function.check_arity(args.len(), line)?;
+    Ok(Value::obj(Fiber::spawn(function, args)))
+}
+
+fn channel(_receiver: &Value, _args: Vec<Value>, _state: &mut dyn Host, _line: &u32) -> LoxResult<Value> {
+    Ok(Value::obj(Channel::new()))
+}
+
+fn send(receiver: &Value, mut args: Vec<Value>, _state: &mut dyn Host, _line: &u32) -> LoxResult<Value> {
+    receiver.as_channel().unwrap().send(args.remove(0));
+    Ok(Value::Nil)
+}
+
+fn receive(receiver: &Value, _args: Vec<Value>, state: &mut dyn Host, line: &u32) -> LoxResult<Value> {
+    receiver.as_channel().unwrap().receive(state.scopes(), line)
+}
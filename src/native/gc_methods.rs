@@ -0,0 +1,20 @@
+use crate::error_handler::LoxResult;
+use crate::gc;
+use crate::interpreter::block_scopes::BlockScopes;
+use crate::native::{Host, NativeEntry, NativeMethod};
+use crate::parser::declarations::Value;
+
+pub fn define_globals(state: &mut BlockScopes) {
+    let (name, arity, method): NativeEntry = ("gc", 0..=0, collect);
+    state.define_global(name, Value::obj(NativeMethod {
+        name,
+        receiver: Value::Nil,
+        arity,
+        method
+    }));
+}
+
+// Runs a collection now and returns how many objects it reclaimed.
+fn collect(_receiver: &Value, _args: Vec<Value>, _state: &mut dyn Host, _line: &u32) -> LoxResult<Value> {
+    Ok(Value::Number(gc::collect() as f64))
+}
@@ -0,0 +1,24 @@
+use crate::error_handler::LoxResult;
+use crate::native::{Host, NativeEntry};
+use crate::parser::declarations::Value;
+
+pub fn lookup(name: &str) -> Option<NativeEntry> {
+    match name {
+        "next" => Some(("next", 0..=0, next)),
+        _ => None
+    }
+}
+
+pub fn field(receiver: &Value, name: &str) -> Option<Value> {
+    match name {
+        "done" => Some(Value::Bool(receiver.as_generator()?.is_done())),
+        _ => None
+    }
+}
+
+fn next(receiver: &Value, _args: Vec<Value>, state: &mut dyn Host, _line: &u32) -> LoxResult<Value> {
+    match receiver.as_generator().unwrap().resume(state.scopes())? {
+        Some(value) => Ok(value),
+        None => Ok(Value::Nil)
+    }
+}
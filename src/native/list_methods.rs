@@ -0,0 +1,231 @@
+use std::cmp::Ordering;
+
+use crate::error_handler::{handle_error, ErrorType, LoxResult};
+use crate::native::{Host, NativeEntry};
+use crate::parser::declarations::{List, Str, Type, Value};
+
+pub fn lookup(name: &str) -> Option<NativeEntry> {
+    match name {
+        "map" => Some(("map", 1..=1, map)),
+        "filter" => Some(("filter", 1..=1, filter)),
+        "reduce" => Some(("reduce", 1..=2, reduce)),
+        "forEach" => Some(("forEach", 1..=1, for_each)),
+        "find" => Some(("find", 1..=1, find)),
+        "any" => Some(("any", 1..=1, any)),
+        "all" => Some(("all", 1..=1, all)),
+        "sort" => Some(("sort", 0..=1, sort)),
+        "reverse" => Some(("reverse", 0..=0, reverse)),
+        "slice" => Some(("slice", 1..=2, slice)),
+        "join" => Some(("join", 1..=1, join)),
+        "push" => Some(("push", 1..=1, push)),
+        "pop" => Some(("pop", 0..=0, pop)),
+        "insert" => Some(("insert", 2..=2, insert)),
+        _ => None
+    }
+}
+
+pub fn field(receiver: &Value, name: &str) -> Option<Value> {
+    match name {
+        "length" => Some(Value::Number(receiver_list(receiver).len() as f64)),
+        _ => None
+    }
+}
+
+fn receiver_list(receiver: &Value) -> &List {
+    receiver.as_list().unwrap()
+}
+
+fn items(receiver: &Value) -> Vec<Value> {
+    receiver_list(receiver).0.borrow().clone()
+}
+
+fn call_unary(callback: &Value, item: Value, state: &mut dyn Host, line: &u32) -> LoxResult<Value> {
+    state.call(callback, Vec::from([item]), line)
+}
+
+fn map(receiver: &Value, args: Vec<Value>, state: &mut dyn Host, line: &u32) -> LoxResult<Value> {
+    let mapped = items(receiver).into_iter()
+        .map(|item| call_unary(&args[0], item, state, line))
+        .collect::<LoxResult<Vec<Value>>>()?;
+    Ok(Value::obj(List::new(mapped)))
+}
+
+fn filter(receiver: &Value, args: Vec<Value>, state: &mut dyn Host, line: &u32) -> LoxResult<Value> {
+    let mut kept = Vec::new();
+    for item in items(receiver) {
+        if call_unary(&args[0], item.clone(), state, line)?.is_truthy() {
+            kept.push(item);
+        }
+    }
+    Ok(Value::obj(List::new(kept)))
+}
+
+fn reduce(receiver: &Value, args: Vec<Value>, state: &mut dyn Host, line: &u32) -> LoxResult<Value> {
+    let mut remaining = items(receiver).into_iter();
+    let mut accumulator = match args.get(1) {
+        Some(init) => init.clone(),
+        None => match remaining.next() {
+            Some(first) => first,
+            None => return handle_error(line, ErrorType::RuntimeError, 
+                "Reduce of empty list with no initial value.")
+        }
+    };
+    for item in remaining {
+        accumulator = state.call(&args[0], Vec::from([accumulator, item]), line)?;
+    }
+    Ok(accumulator)
+}
+
+fn for_each(receiver: &Value, args: Vec<Value>, state: &mut dyn Host, line: &u32) -> LoxResult<Value> {
+    for item in items(receiver) {
+        call_unary(&args[0], item, state, line)?;
+    }
+    Ok(Value::Nil)
+}
+
+fn find(receiver: &Value, args: Vec<Value>, state: &mut dyn Host, line: &u32) -> LoxResult<Value> {
+    for item in items(receiver) {
+        if call_unary(&args[0], item.clone(), state, line)?.is_truthy() {
+            return Ok(item);
+        }
+    }
+    Ok(Value::Nil)
+}
+
+fn any(receiver: &Value, args: Vec<Value>, state: &mut dyn Host, line: &u32) -> LoxResult<Value> {
+    for item in items(receiver) {
+        if call_unary(&args[0], item, state, line)?.is_truthy() {
+            return Ok(Value::Bool(true));
+        }
+    }
+    Ok(Value::Bool(false))
+}
+
+fn all(receiver: &Value, args: Vec<Value>, state: &mut dyn Host, line: &u32) -> LoxResult<Value> {
+    for item in items(receiver) {
+        if !call_unary(&args[0], item, state, line)?.is_truthy() {
+            return Ok(Value::Bool(false));
+        }
+    }
+    Ok(Value::Bool(true))
+}
+
+fn sort(receiver: &Value, args: Vec<Value>, state: &mut dyn Host, line: &u32) -> LoxResult<Value> {
+    let mut compare = |a: &Value, b: &Value, state: &mut dyn Host| -> LoxResult<Ordering> {
+        match args.first() {
+            Some(comparator) => {
+                let result = state.call(comparator, Vec::from([a.clone(), b.clone()]), line)?;
+                match result.as_number() {
+                    Some(num) if num < 0.0 => Ok(Ordering::Less),
+                    Some(num) if num > 0.0 => Ok(Ordering::Greater),
+                    Some(_) => Ok(Ordering::Equal),
+                    None => handle_error(line, ErrorType::RuntimeError, "Comparator must return a number.")
+                }
+            },
+            None => natural_order(a, b, line)
+        }
+    };
+    let sorted = merge_sort(items(receiver), &mut compare, state)?;
+    *receiver_list(receiver).0.borrow_mut() = sorted;
+    Ok(receiver.clone())
+}
+
+fn natural_order(a: &Value, b: &Value, line: &u32) -> LoxResult<Ordering> {
+    match (a.get_type(), b.get_type()) {
+        (Type::NUMBER, Type::NUMBER) => {
+            let (num1, num2) = (a.as_number().unwrap(), b.as_number().unwrap());
+            Ok(num1.partial_cmp(&num2).unwrap_or(Ordering::Equal))
+        },
+        (Type::STRING, Type::STRING) => Ok(a.as_str().unwrap().0.cmp(&b.as_str().unwrap().0)),
+        _ => handle_error(line, ErrorType::RuntimeError, "Operands must be two numbers or two strings.")
+    }
+}
+
+// Stable merge sort; std's sort may panic when a Lox comparator is not a total order.
+fn merge_sort<F>(mut values: Vec<Value>, compare: &mut F, state: &mut dyn Host) -> LoxResult<Vec<Value>>
+where F: FnMut(&Value, &Value, &mut dyn Host) -> LoxResult<Ordering>
+{
+    if values.len() <= 1 {
+        return Ok(values);
+    }
+    let right_half = values.split_off(values.len() / 2);
+    let left = merge_sort(values, compare, state)?;
+    let right = merge_sort(right_half, compare, state)?;
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        if compare(r, l, state)? == Ordering::Less {
+            merged.push(right.next().unwrap());
+        }
+        else {
+            merged.push(left.next().unwrap());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+fn reverse(receiver: &Value, _args: Vec<Value>, _state: &mut dyn Host, _line: &u32) -> LoxResult<Value> {
+    receiver_list(receiver).0.borrow_mut().reverse();
+    Ok(receiver.clone())
+}
+
+fn slice_bound(arg: &Value, len: usize, line: &u32) -> LoxResult<usize> {
+    let position = match arg.as_number() {
+        Some(num) if num.fract() == 0.0 => num as i64,
+        Some(_) => return handle_error(line, ErrorType::RuntimeError, "Index must be an integer."),
+        None => return handle_error(line, ErrorType::RuntimeError, "Index must be a number.")
+    };
+    let position = if position < 0 { position + len as i64 } else { position };
+    Ok(position.clamp(0, len as i64) as usize)
+}
+
+fn slice(receiver: &Value, args: Vec<Value>, _state: &mut dyn Host, line: &u32) -> LoxResult<Value> {
+    let values = items(receiver);
+    let start = slice_bound(&args[0], values.len(), line)?;
+    let end = match args.get(1) {
+        Some(end) => slice_bound(end, values.len(), line)?,
+        None => values.len()
+    };
+    let sliced = if start < end { values[start..end].to_vec() } else { Vec::new() };
+    Ok(Value::obj(List::new(sliced)))
+}
+
+fn join(receiver: &Value, args: Vec<Value>, _state: &mut dyn Host, line: &u32) -> LoxResult<Value> {
+    let separator = match args[0].as_str() {
+        Some(string) => string.0.clone(),
+        None => return handle_error(line, ErrorType::RuntimeError, "Argument must be a string.")
+    };
+    let parts: Vec<String> = items(receiver).iter().map(|item| item.to_str().to_string()).collect();
+    Ok(Value::obj(Str(parts.join(&separator))))
+}
+
+fn push(receiver: &Value, mut args: Vec<Value>, _state: &mut dyn Host, _line: &u32) -> LoxResult<Value> {
+    receiver_list(receiver).0.borrow_mut().push(args.remove(0));
+    Ok(Value::Nil)
+}
+
+fn pop(receiver: &Value, _args: Vec<Value>, _state: &mut dyn Host, line: &u32) -> LoxResult<Value> {
+    match receiver_list(receiver).0.borrow_mut().pop() {
+        Some(value) => Ok(value),
+        None => handle_error(line, ErrorType::RuntimeError, "Cannot pop from an empty list.")
+    }
+}
+
+fn insert(receiver: &Value, mut args: Vec<Value>, _state: &mut dyn Host, line: &u32) -> LoxResult<Value> {
+    let list = receiver_list(receiver);
+    let len = list.len() as i64;
+    let position = match args[0].as_number() {
+        Some(num) if num.fract() == 0.0 => num as i64,
+        Some(_) => return handle_error(line, ErrorType::RuntimeError, "Index must be an integer."),
+        None => return handle_error(line, ErrorType::RuntimeError, "Index must be a number.")
+    };
+    let position = if position < 0 { position + len } else { position };
+    if position < 0 || position > len {
+        return handle_error(line, ErrorType::RuntimeError, "Index out of bounds.");
+    }
+    list.0.borrow_mut().insert(position as usize, args.remove(1));
+    Ok(Value::Nil)
+}
@@ -0,0 +1,47 @@
+use crate::error_handler::{handle_error, ErrorType, LoxResult};
+use crate::native::{Host, NativeEntry};
+use crate::parser::declarations::{List, Map, MapKey, Value};
+
+pub fn lookup(name: &str) -> Option<NativeEntry> {
+    match name {
+        "keys" => Some(("keys", 0..=0, keys)),
+        "values" => Some(("values", 0..=0, values)),
+        "has" => Some(("has", 1..=1, has)),
+        "remove" => Some(("remove", 1..=1, remove)),
+        _ => None
+    }
+}
+
+pub fn map_key(object: &Value, line: &u32) -> LoxResult<MapKey> {
+    match MapKey::from_value(object) {
+        Some(key) => Ok(key),
+        None => handle_error(line, ErrorType::RuntimeError, 
+            "Map keys must be strings, numbers, booleans or nil.")
+    }
+}
+
+fn receiver_map(receiver: &Value) -> &Map {
+    receiver.as_map().unwrap()
+}
+
+fn keys(receiver: &Value, _args: Vec<Value>, _state: &mut dyn Host, _line: &u32) -> LoxResult<Value> {
+    let keys = receiver_map(receiver).keys().iter().map(|key| key.to_value()).collect();
+    Ok(Value::obj(List::new(keys)))
+}
+
+fn values(receiver: &Value, _args: Vec<Value>, _state: &mut dyn Host, _line: &u32) -> LoxResult<Value> {
+    Ok(Value::obj(List::new(receiver_map(receiver).values())))
+}
+
+fn has(receiver: &Value, args: Vec<Value>, _state: &mut dyn Host, line: &u32) -> LoxResult<Value> {
+    let key = map_key(&args[0], line)?;
+    Ok(Value::Bool(receiver_map(receiver).has(&key)))
+}
+
+fn remove(receiver: &Value, args: Vec<Value>, _state: &mut dyn Host, line: &u32) -> LoxResult<Value> {
+    let key = map_key(&args[0], line)?;
+    match receiver_map(receiver).remove(&key) {
+        Some(value) => Ok(value),
+        None => Ok(Value::Nil)
+    }
+}
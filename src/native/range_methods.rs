@@ -0,0 +1,36 @@
+use crate::error_handler::{handle_error, ErrorType, LoxResult};
+use crate::native::{Host, NativeEntry};
+use crate::parser::declarations::{Range, Value};
+
+pub fn lookup(name: &str) -> Option<NativeEntry> {
+    match name {
+        "contains" => Some(("contains", 1..=1, contains)),
+        "step" => Some(("step", 1..=1, step)),
+        _ => None
+    }
+}
+
+fn receiver_range(receiver: &Value) -> &Range {
+    receiver.as_range().unwrap()
+}
+
+fn contains(receiver: &Value, args: Vec<Value>, _state: &mut dyn Host, _line: &u32) -> LoxResult<Value> {
+    let found = match args[0].as_number() {
+        Some(num) => receiver_range(receiver).contains(num),
+        None => false
+    };
+    Ok(Value::Bool(found))
+}
+
+fn step(receiver: &Value, args: Vec<Value>, _state: &mut dyn Host, line: &u32) -> LoxResult<Value> {
+    let step = match args[0].as_number() {
+        Some(num) => num,
+        None => return handle_error(line, ErrorType::RuntimeError, "Range step must be a number.")
+    };
+    if step == 0.0 {
+        return handle_error(line, ErrorType::RuntimeError, "Range step cannot be zero.");
+    }
+    let mut range = receiver_range(receiver).clone();
+    range.step = step;
+    Ok(Value::obj(range))
+}
@@ -0,0 +1,104 @@
+use crate::error_handler::{handle_error, ErrorType, LoxResult};
+use crate::native::{Host, NativeEntry};
+use crate::parser::declarations::{List, Str, Value};
+
+pub fn lookup(name: &str) -> Option<NativeEntry> {
+    match name {
+        "upper" => Some(("upper", 0..=0, upper)),
+        "lower" => Some(("lower", 0..=0, lower)),
+        "trim" => Some(("trim", 0..=0, trim)),
+        "split" => Some(("split", 1..=1, split)),
+        "replace" => Some(("replace", 2..=2, replace)),
+        "contains" => Some(("contains", 1..=1, contains)),
+        "startsWith" => Some(("startsWith", 1..=1, starts_with)),
+        "indexOf" => Some(("indexOf", 1..=1, index_of)),
+        "substring" => Some(("substring", 1..=2, substring)),
+        _ => None
+    }
+}
+
+pub fn field(receiver: &Value, name: &str) -> Option<Value> {
+    match name {
+        "length" => Some(Value::Number(receiver_str(receiver).chars().count() as f64)),
+        _ => None
+    }
+}
+
+fn receiver_str(receiver: &Value) -> &String {
+    &receiver.as_str().unwrap().0
+}
+
+fn string_arg<'a>(args: &'a [Value], position: usize, line: &u32) -> LoxResult<&'a String> {
+    match args[position].as_str() {
+        Some(string) => Ok(&string.0),
+        None => handle_error(line, ErrorType::RuntimeError, "Argument must be a string.")
+    }
+}
+
+fn position_arg(args: &[Value], position: usize, len: usize, line: &u32) -> LoxResult<usize> {
+    match args[position].as_number() {
+        Some(num) if num.fract() == 0.0 && num >= 0.0 && num <= len as f64 => Ok(num as usize),
+        Some(_) => handle_error(line, ErrorType::RuntimeError, "Index out of bounds."),
+        None => handle_error(line, ErrorType::RuntimeError, "Index must be a number.")
+    }
+}
+
+fn upper(receiver: &Value, _args: Vec<Value>, _state: &mut dyn Host, _line: &u32) -> LoxResult<Value> {
+    Ok(Value::obj(Str(receiver_str(receiver).to_uppercase())))
+}
+
+fn lower(receiver: &Value, _args: Vec<Value>, _state: &mut dyn Host, _line: &u32) -> LoxResult<Value> {
+    Ok(Value::obj(Str(receiver_str(receiver).to_lowercase())))
+}
+
+fn trim(receiver: &Value, _args: Vec<Value>, _state: &mut dyn Host, _line: &u32) -> LoxResult<Value> {
+    Ok(Value::obj(Str(receiver_str(receiver).trim().to_string())))
+}
+
+fn split(receiver: &Value, args: Vec<Value>, _state: &mut dyn Host, line: &u32) -> LoxResult<Value> {
+    let string = receiver_str(receiver);
+    let separator = string_arg(&args, 0, line)?;
+    let parts: Vec<Value> = if separator.is_empty() {
+        string.chars().map(|c| Value::obj(Str(c.to_string()))).collect()
+    }
+    else {
+        string.split(separator.as_str()).map(|part| Value::obj(Str(part.to_string()))).collect()
+    };
+    Ok(Value::obj(List::new(parts)))
+}
+
+fn replace(receiver: &Value, args: Vec<Value>, _state: &mut dyn Host, line: &u32) -> LoxResult<Value> {
+    let from = string_arg(&args, 0, line)?;
+    let to = string_arg(&args, 1, line)?;
+    Ok(Value::obj(Str(receiver_str(receiver).replace(from.as_str(), to))))
+}
+
+fn contains(receiver: &Value, args: Vec<Value>, _state: &mut dyn Host, line: &u32) -> LoxResult<Value> {
+    let pattern = string_arg(&args, 0, line)?;
+    Ok(Value::Bool(receiver_str(receiver).contains(pattern.as_str())))
+}
+
+fn starts_with(receiver: &Value, args: Vec<Value>, _state: &mut dyn Host, line: &u32) -> LoxResult<Value> {
+    let prefix = string_arg(&args, 0, line)?;
+    Ok(Value::Bool(receiver_str(receiver).starts_with(prefix.as_str())))
+}
+
+fn index_of(receiver: &Value, args: Vec<Value>, _state: &mut dyn Host, line: &u32) -> LoxResult<Value> {
+    let string = receiver_str(receiver);
+    let pattern = string_arg(&args, 0, line)?;
+    let position = match string.find(pattern.as_str()) {
+        Some(byte_index) => string[..byte_index].chars().count() as f64,
+        None => -1.0
+    };
+    Ok(Value::Number(position))
+}
+
+fn substring(receiver: &Value, args: Vec<Value>, _state: &mut dyn Host, line: &u32) -> LoxResult<Value> {
+    let chars: Vec<char> = receiver_str(receiver).chars().collect();
+    let start = position_arg(&args, 0, chars.len(), line)?;
+    let end = if args.len() > 1 { position_arg(&args, 1, chars.len(), line)? } else { chars.len() };
+    if start > end {
+        return handle_error(line, ErrorType::RuntimeError, "Index out of bounds.");
+    }
+    Ok(Value::obj(Str(chars[start..end].iter().collect())))
+}
@@ -1,7 +1,7 @@
 
 use std::rc::Rc;
-use crate::error_handler::{handle_error, ErrorType};
-use crate::parser::declarations::{Bool, Number, Str, NIL};
+use crate::error_handler::{handle_error, ErrorType, LoxResult};
+use crate::parser::declarations::{Str, Value};
 use crate::parser::operators_decl::{operators_priority_list, OpChainPriority, UnaryOperator};
 use crate::scanner::declarations::*;
 pub(crate) mod declarations;
@@ -12,20 +12,24 @@ use std::mem;
 
 pub struct Parser<'a> {
     pub tokens_list: &'a Vec<Token>,
+    // Number of tokens before the EOF token the scanner ends the list with.
     pub size: usize,
     pub current_index: usize, 
     current_identifier: bool,
     current_expr_identifiers: Vec<Identifier>,
+    // Every variable read or assigned so far, which the resolver must have resolved once compiling is done.
+    identifiers: Vec<Identifier>,
     op_priority_list: Rc<OpChainPriority>,
 }
 
-impl Parser<'_> {
-    pub fn new(tokens: &Vec<Token>, index: usize) -> Parser<'_> {
+impl<'a> Parser<'a> {
+    pub fn new(tokens: &'a Vec<Token>, index: usize) -> Parser<'a> {
         Parser {
             tokens_list: tokens,
-            size: tokens.len(),
+            size: tokens.len() - 1,
             current_index: index,
             current_expr_identifiers: Vec::new(),
+            identifiers: Vec::new(),
             current_identifier: false,
             op_priority_list: operators_priority_list().into(),
         }
@@ -35,150 +39,193 @@ impl Parser<'_> {
         mem::take(&mut self.current_expr_identifiers)
     }
 
+    pub fn take_identifiers(&mut self) -> Vec<Identifier> {
+        mem::take(&mut self.identifiers)
+    }
+
+    fn push_identifier(&mut self, identifier: Identifier) {
+        if !self.current_identifier {
+            self.identifiers.push(identifier.clone());
+            self.current_expr_identifiers.push(identifier);
+            self.current_identifier = true;
+        }
+    }
+
 
-    fn get_expr_op_priority(&mut self, prec_expr: Box<dyn Expression>, operators_list: &OpChainPriority) -> Box<dyn Expression> {
+    fn get_expr_op_priority(&mut self, prec_expr: Box<dyn Expression>, operators_list: &OpChainPriority) -> LoxResult<Box<dyn Expression>> {
         match operators_list {
             OpChainPriority::Cons(map_operators, next_map) => {
                 let mut left_expr = prec_expr;
-                left_expr = self.get_expr_op_priority(left_expr, next_map);
+                left_expr = self.get_expr_op_priority(left_expr, next_map)?;
                 'outer: while self.current_index < self.size {
                     let current_token = &self.tokens_list[self.current_index];
                     for (token_type, token_op) in map_operators.iter() {
                         if *token_type == current_token.token_type {
                             self.next();
-                            let right_expr0 = self.non_binary_expr();
-                            let right_expr = self.get_expr_op_priority(right_expr0, next_map);
+                            let right_expr0 = self.non_binary_expr()?;
+                            let right_expr = self.get_expr_op_priority(right_expr0, next_map)?;
                             left_expr = Box::new( BinaryExpr::new(*token_op, left_expr, right_expr, current_token.line));
                             continue 'outer;
                         }
                     }
                     break;
                 }
-                left_expr 
+                Ok(left_expr)
             },
             OpChainPriority::Nil => {
-                prec_expr
+                Ok(prec_expr)
             }
         }
     }
 
-    fn simple_expression(&mut self) -> Box<dyn Expression> {
-        if self.current_index >= self.size {
-            self.exit_error(&self.tokens_list[self.current_index].line, "Error: Expect expression.");
+    fn simple_expression(&mut self) -> LoxResult<Box<dyn Expression>> {
+        let token = self.current_token();
+        if token.token_type == TokenType::EOF {
+            return self.syntax_error(&token.line, "Error at end: Expect expression.");
         }
-        let token = &self.tokens_list[self.current_index];
         let expr: Box<dyn Expression>  =  match token.token_type {
             TokenType::IDENTIFIER => {
                 let ident = token.lexeme.to_string();
-                if !self.current_identifier {
-                    self.current_expr_identifiers.push(Identifier::new(ident.clone(), token.line));
-                    self.current_identifier = true;
-                }
-                Box::new(
-                    IdentifierExpr::new(ident, None, token.line)
-                )
+                let expr = IdentifierExpr::new(ident.clone(), None, token.line);
+                self.push_identifier(Identifier::new(ident, token.line, expr.resolution.clone()));
+                Box::new(expr)
             },
             TokenType::LEFTPAREN => {
                 self.next();
-                let expr = GroupExpr::new(self.expression(), token.line);
-                self.check_token_valid(TokenType::RIGHTPAREN, ")");
+                let expr = GroupExpr::new(self.expression()?, token.line);
+                self.check_token_valid(TokenType::RIGHTPAREN, ")")?;
                 Box::new(expr)
             },
+            TokenType::LEFTBRACKET => {
+                self.next();
+                let mut elements: Vec<Box<dyn Expression>> = Vec::new();
+                if self.current_token().token_type != TokenType::RIGHTBRACKET {
+                    loop {
+                        elements.push(self.expression()?);
+                        if self.current_token().token_type != TokenType::COMMA {
+                            break;
+                        }
+                        self.next();
+                    }
+                }
+                self.check_token_valid(TokenType::RIGHTBRACKET, "]")?;
+                Box::new(ListExpr::new(elements, token.line))
+            },
+            TokenType::LEFTBRACE => {
+                self.next();
+                let mut entries: Vec<(Box<dyn Expression>, Box<dyn Expression>)> = Vec::new();
+                if self.current_token().token_type != TokenType::RIGHTBRACE {
+                    loop {
+                        let key = self.expression()?;
+                        self.check_token(TokenType::COLON, ":")?;
+                        entries.push((key, self.expression()?));
+                        if self.current_token().token_type != TokenType::COMMA {
+                            break;
+                        }
+                        self.next();
+                    }
+                }
+                self.check_token_valid(TokenType::RIGHTBRACE, "}")?;
+                Box::new(MapExpr::new(entries, token.line))
+            },
             TokenType::STRING => {
                 let token_str = token.literal.clone().unwrap();
-                Box::new( LiteralExpr::new(Box::new(Str(token_str)), token.line) )
+                Box::new( LiteralExpr::new(Value::obj(Str(token_str)), token.line) )
             },
             TokenType::NUMBER => {
                 let number = token.literal.clone().unwrap().parse::<f64>().unwrap();
-                Box::new( LiteralExpr::new(Box::new(Number(number)), token.line) )
+                Box::new( LiteralExpr::new(Value::Number(number), token.line) )
             },
-            TokenType::NIL => Box::new( LiteralExpr::new(Box::new(NIL), token.line) ),
-            TokenType::TRUE => Box::new( LiteralExpr::new(Box::new(Bool(true)), token.line) ),
-            TokenType::FALSE => Box::new( LiteralExpr::new(Box::new(Bool(false)), token.line) ),
+            TokenType::NIL => Box::new( LiteralExpr::new(Value::Nil, token.line) ),
+            TokenType::TRUE => Box::new( LiteralExpr::new(Value::Bool(true), token.line) ),
+            TokenType::FALSE => Box::new( LiteralExpr::new(Value::Bool(false), token.line) ),
             _ => {
-                handle_error(&token.line, ErrorType::SyntacticError, 
+                return handle_error(&token.line, ErrorType::SyntacticError, 
                     format!("Error at {0}: Expect expression.", token.lexeme).as_str());
             }
         };
         self.next();
-        expr
+        Ok(expr)
     }
     
-    fn non_binary_expr(&mut self) -> Box<dyn Expression> 
+    fn non_binary_expr(&mut self) -> LoxResult<Box<dyn Expression>> 
     {
         self.current_identifier = false;
-        if self.current_index >= self.size {
-            self.exit_error(&self.tokens_list[self.current_index].line, "Error: Expect expression.");
+        let token = self.current_token();
+        if token.token_type == TokenType::EOF {
+            return self.syntax_error(&token.line, "Error at end: Expect expression.");
         }
-        let token = &self.tokens_list[self.current_index];
         match token.token_type {
             TokenType::MINUS => self.get_unary_expr(token, UnaryOperator::MINUS),
             TokenType::BANG => self.get_unary_expr(token, UnaryOperator::BANG),
             TokenType::THIS => {
                 let ident = token.lexeme.to_string();
-                if !self.current_identifier {
-                    self.current_expr_identifiers.push(Identifier::new(ident.clone(), token.line));
-                    self.current_identifier = true;
-                }
                 let first_term_expr = Box::new(
-                    IdentifierExpr::new(ident, None, token.line)
+                    IdentifierExpr::new(ident.clone(), None, token.line)
                 ); 
+                self.push_identifier(Identifier::new(ident, token.line, first_term_expr.resolution.clone()));
                 self.next();
                 return self.assignment_expr(first_term_expr);
             },
             TokenType::SUPER => {
                 let ident = token.lexeme.to_string();
-                if !self.current_identifier {
-                    self.current_expr_identifiers.push(Identifier::new(ident.clone(), token.line));
-                    self.current_identifier = true;
-                }
                 let first_term_expr = Box::new(
-                    IdentifierExpr::new(ident, None, token.line)
+                    IdentifierExpr::new(ident.clone(), None, token.line)
                 ); 
+                self.push_identifier(Identifier::new(ident, token.line, first_term_expr.resolution.clone()));
                 self.next();
                 let next_token = self.current_token();
                 if next_token.token_type != TokenType::DOT {
-                    self.exit_error(&token.line, 
-                        format!("Error at '{}': Expect '.' after 'super'.", next_token.lexeme).as_str());
+                    return self.syntax_error(&token.line, 
+                        format!("Error at {}: Expect '.' after 'super'.", location(next_token)).as_str());
                 }
                 return self.assignment_expr(first_term_expr);
             },
             _ => {
-                let simple_expr = self.simple_expression();
+                let simple_expr = self.simple_expression()?;
                 return self.assignment_expr(simple_expr);
             }
         }
     }
 
-    fn assignment_expr(&mut self, simple_expr: Box<dyn Expression>) -> Box<dyn Expression> {
+    fn assignment_expr(&mut self, simple_expr: Box<dyn Expression>) -> LoxResult<Box<dyn Expression>> {
         let token = &self.tokens_list[self.current_index - 1];
-        if self.current_index + 1 >= self.size || token.token_type == TokenType::RIGHTBRACE {
-            return simple_expr
-        }
         let ident_str = token.lexeme.to_string();
         
-        let mut next_token = &self.tokens_list[self.current_index];
+        let mut next_token = self.current_token();
         if next_token.token_type == TokenType::EQUAL {
+            let mut assign_expr = IdentifierExpr::new(ident_str.clone(), None, next_token.line);
+            if let Some(ident) = self.current_expr_identifiers.last_mut() {
+                if ident.value == ident_str {
+                    ident.modified = true;
+                    assign_expr.resolution = ident.resolution.clone();
+                }
+            }
             self.next();
-            let expr = self.expression();
-            return Box::new(IdentifierExpr::new(ident_str, Some(expr), next_token.line));
+            assign_expr.value_to_assign = Some(self.expression()?);
+            return Ok(Box::new(assign_expr));
         }
-        else if next_token.token_type != TokenType::DOT {
+        else if next_token.token_type != TokenType::DOT && next_token.token_type != TokenType::QUESTIONDOT {
             return self.callable_expr(simple_expr);
         }
 
         let mut get_set_expr: Box<dyn Expression> = simple_expr;
         loop {
+            let null_safe = next_token.token_type == TokenType::QUESTIONDOT;
             self.next();
             let mut get_set_expr_temp = InstanceGetSetExpr::new(get_set_expr, 
-                self.simple_expression(), None, next_token.line);
-            next_token = &self.tokens_list[self.current_index];
-            if next_token.token_type != TokenType::DOT {
+                self.simple_expression()?, None, next_token.line);
+            get_set_expr_temp.null_safe = null_safe;
+            next_token = self.current_token();
+            if next_token.token_type != TokenType::DOT && next_token.token_type != TokenType::QUESTIONDOT {
                 if next_token.token_type == TokenType::EQUAL {
+                    if get_set_expr_temp.is_null_safe() {
+                        return self.syntax_error(&next_token.line, "Error at '=': Invalid assignment target.");
+                    }
                     self.next();
-                    let expr = self.expression();
+                    let expr = self.expression()?;
                     get_set_expr_temp.value_to_assign = Some(expr);
-                    return Box::new(get_set_expr_temp);
+                    return Ok(Box::new(get_set_expr_temp));
                 }
                 else {
                     return self.callable_expr(Box::new(get_set_expr_temp)); 
@@ -190,10 +237,7 @@ impl Parser<'_> {
         
     }
 
-    fn callable_expr(&mut self, prev_func_expr: Box<dyn Expression>) -> Box<dyn Expression> {
-        if self.current_index > self.size - 2 || self.size == 1 {
-            return prev_func_expr;
-        }
+    fn callable_expr(&mut self, prev_func_expr: Box<dyn Expression>) -> LoxResult<Box<dyn Expression>> {
         let token = self.current_token();
         let line = token.line;
         if token.token_type == TokenType::LEFTPAREN {
@@ -201,67 +245,110 @@ impl Parser<'_> {
             self.next();
             if self.current_token().token_type != TokenType::RIGHTPAREN {
                 loop {
-                    params.push(self.expression());
+                    params.push(self.expression()?);
                     if self.current_token().token_type != TokenType::COMMA {
                         break;
                     } 
                     self.next();
                 }
             }
-            self.check_token(TokenType::RIGHTPAREN, ")");
+            self.check_token(TokenType::RIGHTPAREN, ")")?;
                
             let callable = CallExpr::new(prev_func_expr, params, line);
             return self.callable_expr(Box::new(callable));
         }
-        else if token.token_type == TokenType::DOT {
+        else if token.token_type == TokenType::LEFTBRACKET {
             self.next();
-            let get_set_expr = InstanceGetSetExpr::new(prev_func_expr, 
-                self.simple_expression(), None, line);
+            let index = self.expression()?;
+            self.check_token(TokenType::RIGHTBRACKET, "]")?;
+            let mut index_expr = IndexExpr::new(prev_func_expr, index, None, line);
+            if self.current_token().token_type == TokenType::EQUAL {
+                self.next();
+                index_expr.value_to_assign = Some(self.expression()?);
+                return Ok(Box::new(index_expr));
+            }
+            return self.callable_expr(Box::new(index_expr));
+        }
+        else if token.token_type == TokenType::DOT || token.token_type == TokenType::QUESTIONDOT {
+            let null_safe = token.token_type == TokenType::QUESTIONDOT;
+            self.next();
+            let mut get_set_expr = InstanceGetSetExpr::new(prev_func_expr, 
+                self.simple_expression()?, None, line);
+            get_set_expr.null_safe = null_safe;
             return self.callable_expr(Box::new(get_set_expr));
         }
-        prev_func_expr
+        Ok(prev_func_expr)
     }
 
-    fn exit_error(&self, line: &u32, text: &str) {
-        handle_error(line, ErrorType::SyntacticError, text);
+    fn syntax_error<T>(&self, line: &u32, text: &str) -> LoxResult<T> {
+        handle_error(line, ErrorType::SyntacticError, text)
     }
 
-    fn check_token_valid(&self, tokentype: TokenType, lexeme: &str) {
+    fn check_token_valid(&self, tokentype: TokenType, lexeme: &str) -> LoxResult<()> {
         if self.current_index >= self.size || (&self.tokens_list[self.current_index]).token_type != tokentype  {
-            self.exit_error(&self.tokens_list[self.current_index-1].line, 
+            return self.syntax_error(&self.tokens_list[self.current_index-1].line, 
                 format!("Error: Expected character {}", lexeme).as_str());
         }
+        Ok(())
     }
     
     pub fn next(&mut self) {
         self.current_index += 1;
     }
 
-    fn get_unary_expr(&mut self, token: &Token, op: UnaryOperator) -> Box<dyn Expression> {
+    fn get_unary_expr(&mut self, token: &Token, op: UnaryOperator) -> LoxResult<Box<dyn Expression>> {
         self.next();
-        let child_expr = self.non_binary_expr();      
+        let child_expr = self.non_binary_expr()?;      
         let expr = UnaryExpr::new(op, child_expr, token.line);
-        Box::new(expr)
+        Ok(Box::new(expr))
     }
 
-    pub fn expression(&mut self) -> Box<dyn Expression> {
-        let start_expr = self.non_binary_expr();
+    pub fn expression(&mut self) -> LoxResult<Box<dyn Expression>> {
+        let start_expr = self.non_binary_expr()?;
         let op_prior_list = self.op_priority_list.clone();
-        let expr = self.get_expr_op_priority(start_expr, &op_prior_list);
-        expr
+        self.get_expr_op_priority(start_expr, &op_prior_list)
     }
 
-    pub fn current_token(&self) -> &Token {
-        &self.tokens_list[self.current_index]
+    // Past the last token, every position reads the EOF token.
+    pub fn current_token(&self) -> &'a Token {
+        &self.tokens_list[self.current_index.min(self.size)]
     }
 
-    pub fn check_token(&mut self, tokentype: TokenType, lexeme: &str) {
+    pub fn check_token(&mut self, tokentype: TokenType, lexeme: &str) -> LoxResult<()> {
         let token = self.current_token();
         if token.token_type != tokentype {
-            handle_error(&token.line, ErrorType::SyntacticError, 
-                format!("Error at '{}': Expect {}", token.lexeme, lexeme).as_str());
+            return handle_error(&token.line, ErrorType::SyntacticError, 
+                format!("Error at {}: Expect {}", location(token), lexeme).as_str());
         }  
         self.next();
+        Ok(())
+    }
+
+    // Contextual keywords such as `in` are scanned as identifiers so they stay usable as names.
+    pub fn is_contextual(&self, index: usize, keyword: &str) -> bool {
+        self.is_token(index, TokenType::IDENTIFIER) && self.tokens_list[index].lexeme == keyword
+    }
+
+    pub fn is_token(&self, index: usize, tokentype: TokenType) -> bool {
+        index < self.size && self.tokens_list[index].token_type == tokentype
+    }
+
+    pub fn check_contextual(&mut self, keyword: &str) -> LoxResult<()> {
+        if !self.is_contextual(self.current_index, keyword) {
+            let token = self.current_token();
+            return handle_error(&token.line, ErrorType::SyntacticError, 
+                format!("Error at {}: Expect {}", location(token), keyword).as_str());
+        }
+        self.next();
+        Ok(())
     }
     
 }
+
+// Where an error was found, as reported after "Error at".
+pub fn location(token: &Token) -> String {
+    match token.token_type {
+        TokenType::EOF => String::from("end"),
+        _ => format!("'{}'", token.lexeme)
+    }
+}
@@ -1,8 +1,24 @@
 
-use std::{borrow::Cow, cell::RefCell, ops::{Add, Div, Mul, Sub}, rc::Rc};
-use crate::{class::{Class, ClassInstance}, function::Function, scanner::utils::literal_number};
+use std::{borrow::Cow, cell::RefCell, collections::HashMap, ops::Add, rc::Rc};
+use crate::{class::{Class, ClassInstance}, enums::{Enum, EnumVariant}, fiber::{Channel, Fiber}, function::Function, generator::Generator, iterator::LoxIterator, module::Module, native::NativeMethod, scanner::utils::literal_number, vm::value::VmObject};
+use crate::gc::{self, Node, Tracer};
 
-pub type RefObject = Rc<RefCell<Box<dyn Object>>>;
+pub type RefObject = Rc<RefCell<Value>>;
+
+thread_local! {
+    // Containers being printed, so one that holds itself prints a placeholder instead.
+    static PRINTING: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+fn print_once(address: usize, cycle: &'static str, print: impl FnOnce() -> String) -> Cow<'static, str> {
+    if PRINTING.with_borrow(|printing| printing.contains(&address)) {
+        return Cow::Borrowed(cycle);
+    }
+    PRINTING.with_borrow_mut(|printing| printing.push(address));
+    let text = print();
+    PRINTING.with_borrow_mut(|printing| printing.pop());
+    Cow::Owned(text)
+}
 
 #[derive(PartialEq)]
 pub enum Type {
@@ -12,13 +28,28 @@ pub enum Type {
     NIL,
     FUNCTION,
     CLASS,
-    CLASSINSTANCE  
+    CLASSINSTANCE,
+    LIST,
+    MAP,
+    NATIVEMETHOD,
+    ITERATOR,
+    RANGE,
+    MODULE,
+    ENUM,
+    ENUMVARIANT,
+    GENERATOR,
+    FIBER,
+    CHANNEL,
+    VMOBJECT
 }
 
 pub trait Object: ValueObjTrait + ToString {
     fn to_str(&self) -> Cow<'static, str>;
     fn get_type(&self) -> Type;
     fn dyn_clone(&self) -> Box<dyn Object>;
+
+    // Reports the containers the value holds to the collector; values it doesn't look into keep theirs alive.
+    fn trace(&self, _tracer: &mut Tracer) {}
 }
 
 impl Clone for Box<dyn Object> {
@@ -28,18 +59,10 @@ impl Clone for Box<dyn Object> {
 }
 
 pub trait ValueObjTrait {
-    fn as_number(&self) -> Option<&Number> {
-        None
-    }
-
     fn as_str(&self) -> Option<&Str> {
         None
     }
 
-    fn as_bool(&self) -> Option<&Bool> {
-        Some(&Bool(true))
-    }
-
     fn as_function(&self) -> Option<&Function> {
         None
     }
@@ -51,22 +74,220 @@ pub trait ValueObjTrait {
     fn as_class_instance(&mut self) -> Option<&mut ClassInstance> {
         None
     }
+
+    fn as_list(&self) -> Option<&List> {
+        None
+    }
+
+    fn as_map(&self) -> Option<&Map> {
+        None
+    }
+
+    fn as_native_method(&self) -> Option<&NativeMethod> {
+        None
+    }
+
+    fn as_iterator(&self) -> Option<&LoxIterator> {
+        None
+    }
+
+    fn as_range(&self) -> Option<&Range> {
+        None
+    }
+
+    fn as_module(&self) -> Option<&Module> {
+        None
+    }
+
+    fn as_enum(&self) -> Option<&Enum> {
+        None
+    }
+
+    fn as_enum_variant(&self) -> Option<&EnumVariant> {
+        None
+    }
+
+    fn as_generator(&self) -> Option<&Generator> {
+        None
+    }
+
+    fn as_fiber(&self) -> Option<&Fiber> {
+        None
+    }
+
+    fn as_channel(&self) -> Option<&Channel> {
+        None
+    }
+
+    fn as_vm_object(&self) -> Option<&VmObject> {
+        None
+    }
 }
 
 
+// Numbers, booleans and nil are stored inline; everything else is an object on the heap.
 #[derive(Clone)]
-pub struct Str (pub String);
+pub enum Value {
+    Number(f64),
+    Bool(bool),
+    Nil,
+    Obj(Box<dyn Object>)
+}
 
 #[derive(Clone)]
-pub struct Number (pub f64);
+pub struct Str (pub String);
 
 #[derive(Clone)]
-pub struct Bool (pub bool);
+pub struct List (pub Rc<RefCell<Vec<Value>>>);
+
+#[derive(Clone, PartialEq)]
+pub struct Range {
+    pub start: f64,
+    pub end: f64,
+    pub step: f64,
+    pub inclusive: bool
+}
+
+#[derive(Hash, Eq, PartialEq, Clone)]
+pub enum MapKey {
+    Str(String),
+    Number(u64),
+    Bool(bool),
+    Nil
+}
+
+pub struct MapEntries {
+    pub order: Vec<MapKey>,
+    pub values: HashMap<MapKey, RefObject>
+}
 
 #[derive(Clone)]
-pub struct NIL;
+pub struct Map (pub Rc<RefCell<MapEntries>>);
+
+
+
+impl Value {
+    pub fn obj<T: Object + 'static>(object: T) -> Value {
+        Value::Obj(Box::new(object))
+    }
 
+    pub fn to_str(&self) -> Cow<'static, str> {
+        match self {
+            Value::Number(num) => Cow::Owned(num.to_string()),
+            Value::Bool(true) => Cow::Borrowed("true"),
+            Value::Bool(false) => Cow::Borrowed("false"),
+            Value::Nil => Cow::Borrowed("nil"),
+            Value::Obj(object) => object.to_str()
+        }
+    }
 
+    pub fn get_type(&self) -> Type {
+        match self {
+            Value::Number(_) => Type::NUMBER,
+            Value::Bool(_) => Type::BOOLEAN,
+            Value::Nil => Type::NIL,
+            Value::Obj(object) => object.get_type()
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(num) => Some(*num),
+            _ => None
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(boolean) => Some(*boolean),
+            _ => None
+        }
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    pub fn as_object(&self) -> Option<&dyn Object> {
+        match self {
+            Value::Obj(object) => Some(object.as_ref()),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&Str> {
+        self.as_object()?.as_str()
+    }
+
+    pub fn as_function(&self) -> Option<&Function> {
+        self.as_object()?.as_function()
+    }
+
+    pub fn as_class(&self) -> Option<&Class> {
+        self.as_object()?.as_class()
+    }
+
+    pub fn as_class_instance(&mut self) -> Option<&mut ClassInstance> {
+        match self {
+            Value::Obj(object) => object.as_class_instance(),
+            _ => None
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&List> {
+        self.as_object()?.as_list()
+    }
+
+    pub fn as_map(&self) -> Option<&Map> {
+        self.as_object()?.as_map()
+    }
+
+    pub fn as_native_method(&self) -> Option<&NativeMethod> {
+        self.as_object()?.as_native_method()
+    }
+
+    pub fn as_iterator(&self) -> Option<&LoxIterator> {
+        self.as_object()?.as_iterator()
+    }
+
+    pub fn as_range(&self) -> Option<&Range> {
+        self.as_object()?.as_range()
+    }
+
+    pub fn as_module(&self) -> Option<&Module> {
+        self.as_object()?.as_module()
+    }
+
+    pub fn as_enum(&self) -> Option<&Enum> {
+        self.as_object()?.as_enum()
+    }
+
+    pub fn as_enum_variant(&self) -> Option<&EnumVariant> {
+        self.as_object()?.as_enum_variant()
+    }
+
+    pub fn as_generator(&self) -> Option<&Generator> {
+        self.as_object()?.as_generator()
+    }
+
+    pub fn as_fiber(&self) -> Option<&Fiber> {
+        self.as_object()?.as_fiber()
+    }
+
+    pub fn as_channel(&self) -> Option<&Channel> {
+        self.as_object()?.as_channel()
+    }
+
+    pub fn as_vm_object(&self) -> Option<&VmObject> {
+        self.as_object()?.as_vm_object()
+    }
+
+    pub fn trace(&self, tracer: &mut Tracer) {
+        if let Value::Obj(object) = self {
+            object.trace(tracer);
+        }
+    }
+}
 
 impl Object for Str {
     fn to_str(&self) -> Cow<'static, str> {
@@ -80,43 +301,183 @@ impl Object for Str {
     }
 }
 
-impl Object for Number {
+impl Object for List {
     fn to_str(&self) -> Cow<'static, str> {
-        return Cow::Owned(self.0.to_string());
+        print_once(Rc::as_ptr(&self.0) as usize, "[...]", || {
+            let items: Vec<String> = self.0.borrow().iter()
+                .map(|item| item.to_str().to_string())
+                .collect();
+            format!("[{}]", items.join(", "))
+        })
     }
     fn get_type(&self) -> Type {
-        Type::NUMBER
+        Type::LIST
     }
     fn dyn_clone(&self) -> Box<dyn Object> {
-        Box::new(Number(self.0))
+        Box::new(List(self.0.clone()))
+    }
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.list(&self.0);
+    }
+}
+
+impl List {
+    pub fn new(items: Vec<Value>) -> List {
+        let items = Rc::new(RefCell::new(items));
+        gc::track(Node::List(items.clone()));
+        List(items)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.borrow().len()
+    }
+
+    pub fn get(&self, index: usize) -> Value {
+        self.0.borrow()[index].clone()
+    }
+
+    pub fn set(&self, index: usize, value: Value) {
+        self.0.borrow_mut()[index] = value;
     }
 }
 
-impl Object for Bool   {
+impl Object for Map {
     fn to_str(&self) -> Cow<'static, str> {
-        if self.0 { Cow::Borrowed("true") }
-        else { Cow::Borrowed("false") }
-    }   
+        print_once(Rc::as_ptr(&self.0) as usize, "{...}", || {
+            let entries = self.0.borrow();
+            let items: Vec<String> = entries.order.iter()
+                .map(|key| format!("{}: {}", key.to_value().to_str(), entries.values[key].borrow().to_str()))
+                .collect();
+            format!("{{{}}}", items.join(", "))
+        })
+    }
     fn get_type(&self) -> Type {
-        Type::BOOLEAN
-    } 
+        Type::MAP
+    }
     fn dyn_clone(&self) -> Box<dyn Object> {
-        Box::new(Bool(self.0))
+        Box::new(Map(self.0.clone()))
+    }
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.map(&self.0);
     }
 }
 
-impl Object for NIL  {
-    fn to_str(&self) -> Cow<'static, str>  {
-        return Cow::Borrowed("nil");
+impl Object for Range {
+    fn to_str(&self) -> Cow<'static, str> {
+        let operator = if self.inclusive { "..=" } else { ".." };
+        let bounds = format!("{}{}{}", Value::Number(self.start).to_str(), operator, Value::Number(self.end).to_str());
+        if self.step == 1.0 {
+            return Cow::Owned(bounds);
+        }
+        Cow::Owned(format!("{} step {}", bounds, Value::Number(self.step).to_str()))
     }
     fn get_type(&self) -> Type {
-        Type::NIL
+        Type::RANGE
     }
     fn dyn_clone(&self) -> Box<dyn Object> {
-        Box::new(NIL)
+        Box::new(self.clone())
+    }
+}
+
+impl Range {
+    pub fn new(start: f64, end: f64, inclusive: bool) -> Range {
+        Range { start, end, step: 1.0, inclusive }
+    }
+
+    pub fn value_at(&self, position: usize) -> Option<f64> {
+        let value = self.start + self.step * position as f64;
+        let in_range = match (self.step > 0.0, self.inclusive) {
+            (true, true) => value <= self.end,
+            (true, false) => value < self.end,
+            (false, true) => value >= self.end,
+            (false, false) => value > self.end
+        };
+        if in_range { Some(value) } else { None }
+    }
+
+    pub fn contains(&self, value: f64) -> bool {
+        let steps = (value - self.start) / self.step;
+        steps >= 0.0 && steps.fract() == 0.0 && self.value_at(steps as usize).is_some()
+    }
+}
+
+impl MapKey {
+    pub fn from_value(value: &Value) -> Option<MapKey> {
+        match value {
+            Value::Number(num) => {
+                // -0.0 and 0.0 compare equal, so they must share a key
+                let num = if *num == 0.0 { 0.0 } else { *num };
+                Some(MapKey::Number(num.to_bits()))
+            },
+            Value::Bool(boolean) => Some(MapKey::Bool(*boolean)),
+            Value::Nil => Some(MapKey::Nil),
+            Value::Obj(_) => value.as_str().map(|string| MapKey::Str(string.0.clone()))
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            MapKey::Str(string) => Value::obj(Str(string.clone())),
+            MapKey::Number(bits) => Value::Number(f64::from_bits(*bits)),
+            MapKey::Bool(boolean) => Value::Bool(*boolean),
+            MapKey::Nil => Value::Nil
+        }
     }
 }
 
+impl Map {
+    pub fn new() -> Map {
+        let entries = Rc::new(RefCell::new(MapEntries { order: Vec::new(), values: HashMap::new() }));
+        gc::track(Node::Map(entries.clone()));
+        Map(entries)
+    }
+
+    pub fn get(&self, key: &MapKey) -> Option<Value> {
+        self.0.borrow().values.get(key).map(|value| value.borrow().clone())
+    }
+
+    pub fn set(&self, key: MapKey, value: Value) {
+        let mut entries = self.0.borrow_mut();
+        if let Some(current) = entries.values.get(&key) {
+            *current.borrow_mut() = value;
+            return;
+        }
+        entries.order.push(key.clone());
+        entries.values.insert(key, Rc::new(RefCell::new(value)));
+    }
+
+    pub fn has(&self, key: &MapKey) -> bool {
+        self.0.borrow().values.contains_key(key)
+    }
+
+    pub fn remove(&self, key: &MapKey) -> Option<Value> {
+        let mut entries = self.0.borrow_mut();
+        let removed = entries.values.remove(key)?;
+        entries.order.retain(|item| item != key);
+        let value = removed.borrow().clone();
+        Some(value)
+    }
+
+    pub fn keys(&self) -> Vec<MapKey> {
+        self.0.borrow().order.clone()
+    }
+
+    pub fn values(&self) -> Vec<Value> {
+        let entries = self.0.borrow();
+        entries.order.iter().map(|key| entries.values[key].borrow().clone()).collect()
+    }
+}
+
+
+impl ToString for Value {
+    fn to_string(&self) -> String {
+        match self {
+            Value::Number(_) => literal_number(&self.to_str()),
+            Value::Obj(object) => object.to_string(),
+            _ => self.to_str().to_string()
+        }
+    }
+}
 
 impl ToString for Str {
     fn to_string(&self) -> String {
@@ -124,21 +485,21 @@ impl ToString for Str {
     }
 }
 
-impl ToString for Number {
+impl ToString for List {
     fn to_string(&self) -> String {
-        literal_number(&self.to_str())
+        self.to_str().to_string()
     }
 }
 
-impl ToString for Bool  {
+impl ToString for Range {
     fn to_string(&self) -> String {
-        self.0.to_string()
+        self.to_str().to_string()
     }
 }
 
-impl ToString for NIL  {
+impl ToString for Map {
     fn to_string(&self) -> String {
-        "nil".to_string()
+        self.to_str().to_string()
     }
 }
 
@@ -152,64 +513,26 @@ impl Add for Str {
     }
 }
 
-impl Add for Number {
-    type Output = Number ;
-    fn add(self, other: Number) -> Number {
-        Number(self.0 + other.0)
-    }
-} 
-
-impl Sub for Number {
-    type Output = Number ;
-    fn sub(self, other: Number) -> Number {
-        Number(self.0 - other.0)
-    }
-}
-
-impl Mul for Number {
-    type Output = Number ;
-    fn mul(self, other: Number) -> Number  {
-        Number(self.0 * other.0)
-    }
-}
-
-impl Div for Number {
-    type Output = Number ;
-    fn div(self, other: Number) -> Number  {
-        Number(self.0 / other.0)
-    }
-}
-
-
 impl ValueObjTrait for Str {
     fn as_str(&self) -> Option<&Str> {
         Some(self)
     }
-
-    fn as_bool(&self) -> Option<&Bool> {
-        Some(&Bool(true))
-    }
 }
 
-impl ValueObjTrait for Number {
-    fn as_number(&self) -> Option<&Number> {
+impl ValueObjTrait for List {
+    fn as_list(&self) -> Option<&List> {
         Some(self)
     }
-
-    fn as_bool(&self) -> Option<&Bool> {
-        Some(&Bool(true))
-    }
 }
 
-impl ValueObjTrait for Bool {
-    fn as_bool(&self) -> Option<&Bool> {
+impl ValueObjTrait for Map {
+    fn as_map(&self) -> Option<&Map> {
         Some(self)
     }
 }
 
-impl ValueObjTrait for NIL  {
-    fn as_bool(&self) -> Option<&Bool> {
-        Some(&Bool(false))
+impl ValueObjTrait for Range {
+    fn as_range(&self) -> Option<&Range> {
+        Some(self)
     }
 }
-
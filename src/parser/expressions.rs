@@ -1,33 +1,105 @@
 
+use std::cell::Cell;
+use std::rc::Rc;
+
 use crate::class::ClassInstance;
+use crate::error_handler::LoxResult;
+use crate::function::CallOutcome;
 use crate::interpreter::block_scopes::BlockScopes;
 use crate::parser::declarations::*;
 use crate::parser::operators_decl::*;
+use crate::typecheck::{StaticType, TypeChecker};
+use crate::vm::emitter::{EmitResult, Emitter};
+
+// Where the resolver found a variable: `depth` counts frames out from the
+// innermost one and `index` is a position in the running function's captures.
+// Names not declared in any enclosing scope are globals, looked up by name.
+#[derive(Clone, Copy, Default)]
+pub enum Resolution {
+    #[default]
+    Unresolved,
+    Global,
+    Local { depth: usize, slot: usize },
+    Upvalue { index: usize }
+}
+
+// Where a function gets each captured cell from when it is created: a local
+// of the enclosing scope, a capture of the enclosing function, or the
+// instance a method is bound to. A name no enclosing scope ever declared
+// is captured as a global.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Capture {
+    Local { depth: usize, slot: usize },
+    Upvalue { index: usize },
+    This,
+    Super,
+    Global
+}
 
 #[derive(Clone)]
 pub struct Identifier {
     pub value: String,
-    // pub modified: bool,
-    pub line: u32
+    pub modified: bool,
+    pub line: u32,
+    pub resolution: Rc<Cell<Resolution>>
 }
 
 impl Identifier {
-    pub fn new(value: String, line: u32) -> Identifier {
-        Identifier { value, line }
+    pub fn new(value: String, line: u32, resolution: Rc<Cell<Resolution>>) -> Identifier {
+        Identifier { value, modified: false, line, resolution }
     }
 }
 pub trait Expression {
-    fn evaluate(&self, state_scope: &mut BlockScopes) -> Box<dyn Object>;
+    fn evaluate(&self, state_scope: &mut BlockScopes) -> LoxResult<Value>;
     fn to_string(&self) -> String;
     fn get_line(&self) -> u32;
     fn value_from_class_instance(&self, instance: &ClassInstance, 
-        state_scope: &mut BlockScopes) -> (String, Option<Box<dyn Object>>);
+        state_scope: &mut BlockScopes) -> LoxResult<(String, Option<Value>)>;
+
+    fn static_type(&self, _checker: &mut TypeChecker) -> StaticType {
+        StaticType::Any
+    }
+
+    // Type of this expression used as a property of `owner`, mirrors value_from_class_instance.
+    fn member_type(&self, _owner: &StaticType, _checker: &mut TypeChecker) -> StaticType {
+        StaticType::Any
+    }
+
+    // True when a nil result comes from a `?.` link, so the rest of the chain yields nil too.
+    fn is_null_safe(&self) -> bool {
+        false
+    }
+
+    fn is_call(&self) -> bool {
+        false
+    }
+
+    // Evaluates a returned expression; a call to a Lox function is left for the caller's frame to run.
+    fn evaluate_tail(&self, state_scope: &mut BlockScopes) -> LoxResult<CallOutcome> {
+        Ok(CallOutcome::Value(self.evaluate(state_scope)?))
+    }
+
+    // The name this expression reads, when it is a plain variable.
+    fn identifier(&self) -> Option<&str> {
+        None
+    }
+
+    // Compiles the expression for the bytecode VM.
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult;
+
+    // Compiles `return <expression>` where the expression is a call, which then runs in the returning frame.
+    fn emit_tail_call(&self, emitter: &mut Emitter) -> EmitResult {
+        self.emit(emitter)?;
+        emitter.return_value(self.get_line());
+        Ok(())
+    }
 }
 
 pub struct InstanceGetSetExpr {
     pub instance: Box<dyn Expression>,
     pub property: Box<dyn Expression>,
     pub value_to_assign: Option<Box<dyn Expression>>,
+    pub null_safe: bool,
     pub line: u32 
 }
 
@@ -38,6 +110,7 @@ impl InstanceGetSetExpr {
                 instance, 
                 property, 
                 value_to_assign, 
+                null_safe: false,
                 line
             }
         }
@@ -63,7 +136,8 @@ impl CallExpr {
 pub struct IdentifierExpr {
     pub ident_name: String,
     pub value_to_assign: Option<Box<dyn Expression>>,
-    pub line: u32
+    pub line: u32,
+    pub resolution: Rc<Cell<Resolution>>
 }
 
 impl IdentifierExpr {
@@ -71,7 +145,8 @@ impl IdentifierExpr {
         IdentifierExpr { 
             ident_name: ident, 
             value_to_assign: value, 
-            line 
+            line,
+            resolution: Rc::new(Cell::new(Resolution::Unresolved))
         }
     }
 }
@@ -110,12 +185,12 @@ impl UnaryExpr {
     }
 }
 pub struct LiteralExpr {
-    pub value: Box<dyn Object>,
+    pub value: Value,
     pub line: u32
 }
 
 impl LiteralExpr {
-    pub fn new(value: Box<dyn Object>, line: u32) -> LiteralExpr {
+    pub fn new(value: Value, line: u32) -> LiteralExpr {
         LiteralExpr { value, line }
     }
 }
@@ -131,3 +206,44 @@ impl GroupExpr {
     }
 }
 
+
+pub struct ListExpr {
+    pub elements: Vec<Box<dyn Expression>>,
+    pub line: u32
+}
+
+impl ListExpr {
+    pub fn new(elements: Vec<Box<dyn Expression>>, line: u32) -> ListExpr {
+        ListExpr { elements, line }
+    }
+}
+
+pub struct IndexExpr {
+    pub collection: Box<dyn Expression>,
+    pub index: Box<dyn Expression>,
+    pub value_to_assign: Option<Box<dyn Expression>>,
+    pub line: u32
+}
+
+impl IndexExpr {
+    pub fn new(collection: Box<dyn Expression>, index: Box<dyn Expression>, 
+        value_to_assign: Option<Box<dyn Expression>>, line: u32) -> IndexExpr {
+        IndexExpr { 
+            collection, 
+            index, 
+            value_to_assign, 
+            line 
+        }
+    }
+}
+
+pub struct MapExpr {
+    pub entries: Vec<(Box<dyn Expression>, Box<dyn Expression>)>,
+    pub line: u32
+}
+
+impl MapExpr {
+    pub fn new(entries: Vec<(Box<dyn Expression>, Box<dyn Expression>)>, line: u32) -> MapExpr {
+        MapExpr { entries, line }
+    }
+}
@@ -14,7 +14,10 @@ pub enum BinaryOperator {
     GREATER,
     GREATEREQUAL,
     OR,
-    AND
+    AND,
+    RANGE,
+    RANGEINCLUSIVE,
+    NILCOALESCE
 }
 
 pub enum UnaryOperator {
@@ -36,6 +39,10 @@ pub enum OpChainPriority {
 
 pub fn operators_priority_list() -> OpChainPriority {
     
+    let map_coalesce_op = Vec::from([
+        (TokenType::QUESTIONQUESTION, BinaryOperator::NILCOALESCE),
+    ]);
+
     let map_logical_op = Vec::from([
         (TokenType::OR, BinaryOperator::OR),
         (TokenType::AND, BinaryOperator::AND),
@@ -50,6 +57,11 @@ pub fn operators_priority_list() -> OpChainPriority {
         (TokenType::GREATEREQUAL, BinaryOperator::GREATEREQUAL),
     ]);
 
+    let map_range_op = Vec::from([
+        (TokenType::DOTDOT, BinaryOperator::RANGE),
+        (TokenType::DOTDOTEQUAL, BinaryOperator::RANGEINCLUSIVE),
+    ]);
+
     let map_slash_star_op = Vec::from([
         (TokenType::SLASH, BinaryOperator::SLASH),
         (TokenType::STAR, BinaryOperator::STAR),
@@ -61,10 +73,14 @@ pub fn operators_priority_list() -> OpChainPriority {
     ]);
 
     OpChainPriority::Cons(
-        map_logical_op.into(), Box::new(OpChainPriority::Cons(
-            map_comp_token_op.into(), Box::new(OpChainPriority::Cons(
-                map_plus_minus_op.into(), Box::new(OpChainPriority::Cons(
-                    map_slash_star_op.into(), Box::new(OpChainPriority::Nil
+        map_coalesce_op.into(), Box::new(OpChainPriority::Cons(
+            map_logical_op.into(), Box::new(OpChainPriority::Cons(
+                map_comp_token_op.into(), Box::new(OpChainPriority::Cons(
+                    map_range_op.into(), Box::new(OpChainPriority::Cons(
+                        map_plus_minus_op.into(), Box::new(OpChainPriority::Cons(
+                            map_slash_star_op.into(), Box::new(OpChainPriority::Nil
+                        ))
+                    ))
                 ))
             ))
         ))
@@ -83,5 +99,8 @@ pub fn binary_op_map() -> HashMap<BinaryOperator, &'static str> {
         (BinaryOperator::MINUS, "-"),
         (BinaryOperator::SLASH, "/"),
         (BinaryOperator::STAR, "*"),
+        (BinaryOperator::RANGE, ".."),
+        (BinaryOperator::RANGEINCLUSIVE, "..="),
+        (BinaryOperator::NILCOALESCE, "??"),
     ])
 }
\ No newline at end of file
@@ -2,11 +2,11 @@ pub mod declarations;
 pub mod utils;
 
 use std::borrow::Cow;
-use std::io::{stderr, Write};
+use crate::error_handler::{ErrorType, LoxError};
 use crate::scanner::declarations::*;
 use crate::scanner::utils::*;
 
-pub fn tokenize(file_text: String, has_error: &mut bool) -> Vec<Token> {
+pub fn tokenize(file_text: String, errors: &mut Vec<LoxError>) -> Vec<Token> {
     let mut token_list: Vec<Token> = Vec::new();
     let code_symbols: Vec<char> = file_text.chars().collect();
     let mut line = 1;
@@ -47,21 +47,64 @@ pub fn tokenize(file_text: String, has_error: &mut bool) -> Vec<Token> {
                     Token { token_type: TokenType::RIGHTBRACE, lexeme: Cow::Borrowed("}"), literal: None, line }
                 );
             },
+            '[' => {
+                token_list.push(
+                    Token { token_type: TokenType::LEFTBRACKET, lexeme: Cow::Borrowed("["), literal: None, line }
+                );
+            },
+            ']' => {
+                token_list.push(
+                    Token { token_type: TokenType::RIGHTBRACKET, lexeme: Cow::Borrowed("]"), literal: None, line }
+                );
+            },
             ',' => {
                 token_list.push(
                     Token { token_type: TokenType::COMMA, lexeme: Cow::Borrowed(","), literal: None, line }                    
                 );
             },
+            ':' => {
+                token_list.push(
+                    Token { token_type: TokenType::COLON, lexeme: Cow::Borrowed(":"), literal: None, line }
+                );
+            },
             ';' => {
                 token_list.push(
                     Token { token_type: TokenType::SEMICOLON, lexeme: Cow::Borrowed(";"), literal: None, line }
                 );
             },
             '.' => {
+                if index + 1 < n && code_symbols[index + 1] == '.' {
+                    if index + 2 < n && code_symbols[index + 2] == '=' {
+                        token_list.push(
+                            Token { token_type: TokenType::DOTDOTEQUAL, lexeme: Cow::Borrowed("..="), literal: None, line }
+                        );
+                        index += 3;
+                        continue;
+                    }
+                    token_list.push(
+                        Token { token_type: TokenType::DOTDOT, lexeme: Cow::Borrowed(".."), literal: None, line }
+                    );
+                    index += 2;
+                    continue;
+                }
                 token_list.push(
                     Token { token_type: TokenType::DOT, lexeme: Cow::Borrowed("."), literal: None, line }
                 );
             },
+            '?' if index + 1 < n && code_symbols[index + 1] == '.' => {
+                token_list.push(
+                    Token { token_type: TokenType::QUESTIONDOT, lexeme: Cow::Borrowed("?."), literal: None, line }
+                );
+                index += 2;
+                continue;
+            },
+            '?' if index + 1 < n && code_symbols[index + 1] == '?' => {
+                token_list.push(
+                    Token { token_type: TokenType::QUESTIONQUESTION, lexeme: Cow::Borrowed("??"), literal: None, line }
+                );
+                index += 2;
+                continue;
+            },
             '+' => {
                 token_list.push(
                     Token { token_type: TokenType::PLUS, lexeme: Cow::Borrowed("+"), literal: None, line }
@@ -154,9 +197,7 @@ pub fn tokenize(file_text: String, has_error: &mut bool) -> Vec<Token> {
                         );
                     },
                     Err(err) => {
-                        *has_error = true;
-                        let mut stderr = stderr();
-                        let _ = stderr.write(format!("[line {line}] Error: {err}\n").as_bytes());
+                        errors.push(LoxError::new(line, ErrorType::LexicalError, &err));
                         break;
                     }
                 }
@@ -175,9 +216,7 @@ pub fn tokenize(file_text: String, has_error: &mut bool) -> Vec<Token> {
                             );
                         },
                         Err(err) => {
-                            *has_error = true;
-                            let mut stderr = stderr();
-                            let _ = stderr.write(format!("[line {line}] Error: {err}\n").as_bytes());                            
+                            errors.push(LoxError::new(line, ErrorType::LexicalError, &err));
                             break;
                         }
                     }
@@ -200,24 +239,24 @@ pub fn tokenize(file_text: String, has_error: &mut bool) -> Vec<Token> {
                             } 
                         },
                         Err(err) => {
-                            *has_error = true;
-                            let mut stderr = stderr();
-                            let _ = stderr.write(format!("[line {line}] Error: {err}\n").as_bytes());
+                            errors.push(LoxError::new(line, ErrorType::LexicalError, &err));
                             break;
                         }
                     }
                     continue;
                 }
                 else {
-                    let mut stderr = stderr();
-                    let _ = stderr.write(format!("[line {line}] Error: Unexpected character: {c}\n").as_bytes());
-                    *has_error = true;
+                    errors.push(LoxError::new(line, ErrorType::LexicalError, 
+                        format!("Unexpected character: {c}").as_str()));
                 }
             }
         }
         index += 1;
     }
 
+    // Errors at the end of input point at the line the last token is on.
+    let line = token_list.last().map_or(line, |token| token.line);
+    token_list.push(Token { token_type: TokenType::EOF, lexeme: Cow::Borrowed(""), literal: None, line });
     token_list
 
 }
@@ -7,9 +7,16 @@ pub enum TokenType {
     RIGHTPAREN ,    
     LEFTBRACE,
     RIGHTBRACE,
+    LEFTBRACKET,
+    RIGHTBRACKET,
     COMMA,
+    COLON,
     SEMICOLON,
     DOT ,
+    DOTDOT,
+    DOTDOTEQUAL,
+    QUESTIONDOT,
+    QUESTIONQUESTION,
     PLUS,
     MINUS,
     STAR,
@@ -26,21 +33,30 @@ pub enum TokenType {
     STRING,
     NUMBER,
     AND,
+    CATCH,
     CLASS,
+    CONST,
     ELSE,
+    ENUM,
     FALSE,
+    FINALLY,
     FOR,
     FUN,
     IF,
+    IMPORT,
     NIL,
     OR,
     PRINT,
     RETURN,
     SUPER,
     THIS,
+    THROW,
     TRUE,
+    TRY,
     VAR,
-    WHILE
+    WHILE,
+    YIELD,
+    EOF
 }
 
 #[derive(Clone)]
@@ -55,21 +71,29 @@ pub struct Token {
 pub fn keywords_map() -> HashMap<&'static str, TokenType> {
     HashMap::from([
         ("and", TokenType::AND),
+        ("catch", TokenType::CATCH),
         ("class", TokenType::CLASS),
+        ("const", TokenType::CONST),
         ("else", TokenType::ELSE),
+        ("enum", TokenType::ENUM),
         ("false", TokenType::FALSE),
+        ("finally", TokenType::FINALLY),
         ("for", TokenType::FOR),
         ("fun", TokenType::FUN),
         ("if", TokenType::IF),
+        ("import", TokenType::IMPORT),
         ("nil", TokenType::NIL),
         ("or", TokenType::OR),
         ("print", TokenType::PRINT),
         ("return", TokenType::RETURN),
         ("super", TokenType::SUPER),
         ("this", TokenType::THIS),
+        ("throw", TokenType::THROW),
         ("true", TokenType::TRUE),
+        ("try", TokenType::TRY),
         ("var", TokenType::VAR),
-        ("while", TokenType::WHILE)
+        ("while", TokenType::WHILE),
+        ("yield", TokenType::YIELD)
     ])
 }
 
@@ -80,9 +104,16 @@ pub fn token_type_str_map() -> HashMap<TokenType, &'static str> {
         (TokenType::RIGHTPAREN, "RIGHT_PAREN"),    
         (TokenType::LEFTBRACE, "LEFT_BRACE"),
         (TokenType::RIGHTBRACE, "RIGHT_BRACE"),
+        (TokenType::LEFTBRACKET, "LEFT_BRACKET"),
+        (TokenType::RIGHTBRACKET, "RIGHT_BRACKET"),
         (TokenType::COMMA, "COMMA"),
+        (TokenType::COLON, "COLON"),
         (TokenType::SEMICOLON, "SEMICOLON"),
         (TokenType::DOT, "DOT"),
+        (TokenType::DOTDOT, "DOT_DOT"),
+        (TokenType::DOTDOTEQUAL, "DOT_DOT_EQUAL"),
+        (TokenType::QUESTIONDOT, "QUESTION_DOT"),
+        (TokenType::QUESTIONQUESTION, "QUESTION_QUESTION"),
         (TokenType::PLUS, "PLUS"),
         (TokenType::MINUS, "MINUS"),
         (TokenType::STAR, "STAR"),
@@ -99,21 +130,30 @@ pub fn token_type_str_map() -> HashMap<TokenType, &'static str> {
         (TokenType::STRING, "STRING"),
         (TokenType::NUMBER, "NUMBER"),
         (TokenType::AND, "AND"),
+        (TokenType::CATCH, "CATCH"),
         (TokenType::CLASS, "CLASS"),
+        (TokenType::CONST, "CONST"),
         (TokenType::ELSE, "ELSE"),
+        (TokenType::ENUM, "ENUM"),
         (TokenType::FALSE, "FALSE"),
+        (TokenType::FINALLY, "FINALLY"),
         (TokenType::FOR, "FOR"),
         (TokenType::FUN, "FUN"),
         (TokenType::IF, "IF"),
+        (TokenType::IMPORT, "IMPORT"),
         (TokenType::NIL, "NIL"),
         (TokenType::OR, "OR"),
         (TokenType::PRINT, "PRINT"),
         (TokenType::RETURN, "RETURN"),
         (TokenType::SUPER, "SUPER"),
         (TokenType::THIS, "THIS"),
+        (TokenType::THROW, "THROW"),
         (TokenType::TRUE, "TRUE"),
+        (TokenType::TRY, "TRY"),
         (TokenType::VAR, "VAR"),
-        (TokenType::WHILE, "WHILE")
+        (TokenType::WHILE, "WHILE"),
+        (TokenType::YIELD, "YIELD"),
+        (TokenType::EOF, "EOF")
     ])
 }
 
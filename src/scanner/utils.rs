@@ -12,7 +12,8 @@ pub fn number(symbols: &Vec<char>,  start_char: char, index: &mut usize, n: &usi
             break;
         }
     }
-    if last_char == '.' {
+    let fraction_follows = *index + 1 < *n && symbols[*index + 1].is_ascii_digit();
+    if last_char == '.' && fraction_follows {
         num.push(last_char);
         *index += 1;
         while *index < *n {
@@ -1,23 +1,40 @@
 
 
-use std::{cell::RefCell, collections::HashMap, rc::Rc, usize::MAX};
+use std::{cell::{Cell, RefCell}, mem, path::PathBuf, rc::Rc, usize::MAX};
 
 use crate::class::Class;
-use crate::error_handler::{handle_error, ErrorType};
-use crate::interpreter::block_scopes::BlockScopes;
-use crate::parser::declarations::{RefObject, Type};
-use crate::function::Function;
-use crate::parser::expressions::Identifier;
-use crate::parser::{declarations::Object, expressions::Expression};
-use crate::scanner::declarations::Token;
+use crate::enums::Enum;
+use crate::error_handler::{handle_error, ErrorType, LoxError, LoxResult};
+use crate::interpreter::block_scopes::{ActivationKind, BlockScopes};
+use crate::interpreter::Interpreter;
+use crate::parser::declarations::{RefObject, Str, Type, Value};
+use crate::function::{CallOutcome, Function};
+use crate::fiber::{can_suspend, current_fiber, yield_in_place};
+use crate::gc::{self, Node};
+use crate::generator::{resume_at_try, suspend};
+use crate::iterator::{LoxIterator, ITERATOR_KEY};
+use crate::module::{load_module, resolve_path};
+use crate::parser::expressions::{Capture, Expression, IdentifierExpr};
+use crate::typecheck::{ClassInfo, StaticType, TypeChecker};
+use crate::vm::chunk::OpCode;
+use crate::vm::emitter::{EmitResult, Emitter};
+use crate::vm::value::Value as VmValue;
 mod simple_statement;
 pub mod classes_decl_stmt;
 pub mod controlflow_stmts;
+pub mod enum_decl_stmt;
+pub mod exception_stmts;
 pub mod function_stmt;
+pub mod import_stmts;
 
 
 pub trait Statement {
-    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize);
+    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) -> LoxResult<()>;
+
+    fn check_types(&self, _checker: &mut TypeChecker) {}
+
+    // Compiles the statement for the bytecode VM.
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult;
 }
 
 pub struct PrintStatement {
@@ -25,23 +42,58 @@ pub struct PrintStatement {
 }
 
 impl Statement for PrintStatement  {
-    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) {
-       let value = &self.expression.evaluate(state);
+    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) -> LoxResult<()> {
+       let value = &self.expression.evaluate(state)?;
        println!("{}", value.to_str()); 
        *current_stmt_ind += 1;
+        Ok(())
+    }
+
+    fn check_types(&self, checker: &mut TypeChecker) {
+        self.expression.static_type(checker);
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        self.expression.emit(emitter)?;
+        emitter.op(OpCode::Print, self.expression.get_line());
+        Ok(())
     }
 }
 
 pub struct VarStatement {
     pub name: String,
-    pub expression: Box<dyn Expression>
+    pub expression: Box<dyn Expression>,
+    pub constant: bool,
+    pub annotation: StaticType,
+    pub slot: Option<usize>
 }
 
 impl Statement for VarStatement {
-    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) {
-        let expr_value = self.expression.evaluate(state);
-        state.set_init_variable(&self.name, expr_value);
+    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) -> LoxResult<()> {
+        let expr_value = self.expression.evaluate(state)?;
+        state.define_variable(&self.name, expr_value, self.slot);
+        if self.constant {
+            state.mark_constant(&self.name, self.slot);
+        }
        *current_stmt_ind += 1;
+        Ok(())
+    }
+
+    fn check_types(&self, checker: &mut TypeChecker) {
+        let line = self.expression.get_line();
+        checker.annotation_used(&self.annotation, line);
+        let actual = self.expression.static_type(checker);
+        if !checker.accepts(&self.annotation, &actual) {
+            checker.report(line, format!("Type mismatch: cannot assign {} to '{}' of type {}.", 
+                actual.name(), self.name, self.annotation.name()));
+        }
+        checker.declare(&self.name, self.annotation.clone());
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        self.expression.emit(emitter)?;
+        emitter.declare_variable(&self.name)?;
+        emitter.define_variable(&self.name, self.constant, self.expression.get_line())
     }
 }
 
@@ -50,9 +102,20 @@ pub struct ExprStatement {
 }
 
 impl Statement for ExprStatement {
-    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) {
-        self.expression.evaluate(state);
+    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) -> LoxResult<()> {
+        self.expression.evaluate(state)?;
        *current_stmt_ind += 1;
+        Ok(())
+    }
+
+    fn check_types(&self, checker: &mut TypeChecker) {
+        self.expression.static_type(checker);
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        self.expression.emit(emitter)?;
+        emitter.op(OpCode::Pop, self.expression.get_line());
+        Ok(())
     }
 }
 
@@ -64,8 +127,8 @@ pub struct JumpStatement {
 
 
 impl Statement for JumpStatement {
-    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) {
-        let condition = JumpStatement::get_condition(self.condition.evaluate(state));
+    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) -> LoxResult<()> {
+        let condition = JumpStatement::get_condition(self.condition.evaluate(state)?);
         if condition {
             *current_stmt_ind += 1;
         }
@@ -73,15 +136,23 @@ impl Statement for JumpStatement {
             *current_stmt_ind += self.steps;
 
         }
+        Ok(())
+    }
+
+    fn check_types(&self, checker: &mut TypeChecker) {
+        self.condition.static_type(checker);
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        self.condition.emit(emitter)?;
+        emitter.jump_if_false(self.steps);
+        Ok(())
     }
 }
 
 impl JumpStatement {
-    fn get_condition(cond_option: Box<dyn Object>) -> bool {
-        match cond_option.as_bool() {
-            Some(cond) => cond.0,
-            None => false
-        }
+    fn get_condition(cond_option: Value) -> bool {
+        cond_option.is_truthy()
     }
 }
 
@@ -90,8 +161,13 @@ pub struct BackToStatement {
 }
 
 impl Statement for BackToStatement {
-    fn run(&self, _state: &mut BlockScopes, current_stmt_ind: &mut usize) {
+    fn run(&self, _state: &mut BlockScopes, current_stmt_ind: &mut usize) -> LoxResult<()> {
         *current_stmt_ind -= self.steps;
+        Ok(())
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        emitter.back_to(self.steps)
     }
 }
 
@@ -100,8 +176,76 @@ pub struct GoToStatement {
 }
 
 impl Statement for GoToStatement {
-    fn run(&self, _state: &mut BlockScopes, current_stmt_ind: &mut usize) {
+    fn run(&self, _state: &mut BlockScopes, current_stmt_ind: &mut usize) -> LoxResult<()> {
         *current_stmt_ind += self.steps;
+        Ok(())
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        emitter.go_to(self.steps);
+        Ok(())
+    }
+}
+
+pub struct IterInitStatement {
+    pub iterable: Box<dyn Expression>,
+    pub slot: Option<usize>
+}
+
+impl Statement for IterInitStatement {
+    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) -> LoxResult<()> {
+        let iterable = self.iterable.evaluate(state)?;
+        let iterator = LoxIterator::from_object(iterable, state, &self.iterable.get_line())?;
+        state.define_variable(ITERATOR_KEY, Value::obj(iterator), self.slot);
+        *current_stmt_ind += 1;
+        Ok(())
+    }
+
+    fn check_types(&self, checker: &mut TypeChecker) {
+        let iterable = self.iterable.static_type(checker);
+        if matches!(iterable, StaticType::Number | StaticType::Bool | StaticType::Nil) {
+            checker.report(self.iterable.get_line(), format!("Cannot iterate over {}.", iterable.name()));
+        }
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        self.iterable.emit(emitter)?;
+        emitter.iter_init(self.iterable.get_line())
+    }
+}
+
+pub struct IterNextStatement {
+    pub name: String,
+    pub line: u32,
+    pub steps: usize,
+    pub slot: Option<usize>,
+    pub iterator: IdentifierExpr
+}
+
+impl Statement for IterNextStatement {
+    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) -> LoxResult<()> {
+        let iterator_obj = self.iterator.evaluate(state)?;
+        let iterator = iterator_obj.as_iterator().unwrap();
+        match iterator.next_value(state, &self.line)? {
+            Some(value) => {
+                state.start_child_block();
+                state.define_variable(&self.name, value, self.slot);
+                *current_stmt_ind += 1;
+            },
+            None => {
+                *current_stmt_ind += self.steps;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_types(&self, checker: &mut TypeChecker) {
+        checker.start_scope();
+        checker.declare(&self.name, StaticType::Any);
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        emitter.iter_next(&self.name, self.steps, self.line)
     }
 }
 
@@ -110,9 +254,19 @@ pub struct StartBlockStatement {
 }
 
 impl Statement for StartBlockStatement {
-    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) {
+    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) -> LoxResult<()> {
         state.start_child_block();
         *current_stmt_ind += 1;
+        Ok(())
+    }
+
+    fn check_types(&self, checker: &mut TypeChecker) {
+        checker.start_scope();
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        emitter.begin_scope();
+        Ok(())
     }
 }
 
@@ -121,94 +275,150 @@ pub struct EndBlockStatement {
 }
 
 impl Statement for EndBlockStatement {
-    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) {
+    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) -> LoxResult<()> {
         state.end_child_block();
         *current_stmt_ind += 1;
+        Ok(())
+    }
+
+    fn check_types(&self, checker: &mut TypeChecker) {
+        checker.end_scope();
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        emitter.end_scope();
+        Ok(())
     }
 }
 
 
 pub struct ReturnStatement {
     pub expression: Box<dyn Expression>,
+    pub tail_call: bool
 }
 
 impl Statement for ReturnStatement {
-    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) {
-        let value = self.expression.evaluate(state);
-        let return_key = String::from("return");
-        let mut ind = 0;
-        for hashmap in state.vars_nodes_map.iter_mut().rev() {
-            if let Some(_val) = hashmap.get(&return_key) {
-                hashmap.insert(return_key.clone(), Rc::new(RefCell::new(value.dyn_clone())));
-                break;
-            }
-            else {
-                ind += 1;
-            }
-        } 
-        for _ in 0..ind {
-            state.end_child_block();
-        }
+    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) -> LoxResult<()> {
+        let value = match self.tail_call && in_function_frame(state) {
+            true => match self.expression.evaluate_tail(state)? {
+                CallOutcome::Value(value) => value,
+                CallOutcome::TailCall(tail_call) => {
+                    state.tail_call = Some(tail_call);
+                    Value::Nil
+                }
+            },
+            false => self.expression.evaluate(state)?
+        };
+        state.return_value(value);
         *current_stmt_ind = MAX;
+        Ok(())
+    }
+
+    fn check_types(&self, checker: &mut TypeChecker) {
+        let actual = self.expression.static_type(checker);
+        checker.check_return(&actual, self.expression.get_line());
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        let line = self.expression.get_line();
+        if self.tail_call {
+            return self.expression.emit_tail_call(emitter);
+        }
+        self.expression.emit(emitter)?;
+        emitter.return_value(line);
+        Ok(())
     }
 }
 
 impl ReturnStatement  {
     pub fn new(expr: Box<dyn Expression>) -> ReturnStatement {
-        ReturnStatement { expression: expr }
+        ReturnStatement { expression: expr, tail_call: false }
+    }
+
+    pub fn tail_call(expr: Box<dyn Expression>) -> ReturnStatement {
+        ReturnStatement { expression: expr, tail_call: true }
     }
 }
 
+// Only a called function runs a returned call in its place; a generator or
+// fiber body returns to whoever resumed it.
+fn in_function_frame(state: &BlockScopes) -> bool {
+    state.activations.last().is_some_and(|activation| activation.kind == ActivationKind::Call)
+}
+
 #[derive(Clone)]
 pub struct FunctionDeclStatement {
     pub function_decl: Function,
-    pub extern_variables: Vec<Identifier>
+    pub captures: Vec<Rc<Cell<Capture>>>,
+    pub slot: Option<usize>
 }
 
 
 impl Statement for FunctionDeclStatement {
-    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) {
+    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) -> LoxResult<()> {
+        // The name is bound first so a local function can capture itself for recursion.
+        let cell = state.define_variable(&self.function_decl.name, Value::Nil, self.slot);
         let func_copy = Function {
-            name: self.function_decl.name.clone(),
-            params_names: self.function_decl.params_names.clone(),
-            statements: self.function_decl.statements.clone(),
-            extra_map: self.get_outfunc_variables(&state)
+            captures: self.capture_variables(state),
+            module: state.current_module.clone(),
+            ..self.function_decl.clone()
         };
-        state.define_function(&self.function_decl.name.clone(), func_copy.clone());
+        *cell.borrow_mut() = Value::obj(func_copy);
         *current_stmt_ind += 1;
+        Ok(())
+    }
+
+    fn check_types(&self, checker: &mut TypeChecker) {
+        let signature = self.function_decl.signature.clone();
+        checker.declare(&self.function_decl.name, StaticType::Function(Some(signature)));
+        self.check_body(checker, None);
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        emitter.function_declaration(self)
     }
 }
 
 impl FunctionDeclStatement {
-    fn get_outfunc_variables(&self, state: &BlockScopes) -> HashMap<String, RefObject> {
-        let mut result_map: HashMap<String, RefObject>  = HashMap::new();
-
-        'outer: for identifier in &self.extern_variables {
-            for hashmap in state.vars_nodes_map.iter().rev() {
-                if let Some(value) = hashmap.get(&identifier.value) {
-                    result_map.insert(identifier.value.to_string(), value.clone());
-                    continue 'outer;
-                }
-            }
-            // handle_error(&identifier.line, ErrorType::RuntimeError, 
-            // format!("Undefined variable '{}' .", identifier.value.as_str()).as_str());
-        }
-        result_map
+    pub fn check_body(&self, checker: &mut TypeChecker, this: Option<StaticType>) {
+        let func = &self.function_decl;
+        checker.check_function(&func.params_names, &func.signature, &func.statements, this);
+    }
+
+    // Captures the cells of the enclosing locals the body refers to, in the
+    // order the resolver numbered them. Globals are looked up when used, so a
+    // later redefinition is still seen; `this` and `super` are filled in when
+    // a method is bound.
+    pub fn capture_variables(&self, state: &mut BlockScopes) -> Rc<[Option<RefObject>]> {
+        self.captures.iter().map(|capture| match capture.get() {
+            Capture::Local { depth, slot } => {
+                let cell = state.slot(depth, slot);
+                // A captured cell can end up holding the function capturing it.
+                gc::track(Node::Cell(cell.clone()));
+                Some(cell)
+            },
+            Capture::Upvalue { index } => state.activations.last().and_then(|activation| activation.captures[index].clone()),
+            Capture::This | Capture::Super => Some(Rc::new(RefCell::new(Value::Nil))),
+            Capture::Global => None
+        }).collect()
     }
 }
 
 pub struct ClassDeclStatement {
-    super_class_token: Option<Token>,
-    class: Class
+    super_class: Option<IdentifierExpr>,
+    class: Class,
+    slot: Option<usize>
 }
 
 impl Statement for ClassDeclStatement {
-    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) {
-        if let Some(supclass_token) = &self.super_class_token {
-            let super_class_name = supclass_token.lexeme.to_string();
-            if let Some(super_class_obj) = state.get_variable(&super_class_name) {
+    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) -> LoxResult<()> {
+        let cell = state.define_variable(&self.class.name, Value::Nil, self.slot);
+        if let Some(super_class_expr) = &self.super_class {
+            if let Ok(super_class_obj) = super_class_expr.evaluate(state) {
                 if super_class_obj.get_type() == Type::CLASS {
                     let mut class = self.class.clone();
+                    class.bind_module(state.current_module.clone());
+                    class.bind_closure(state);
                     let super_class = super_class_obj.as_class().unwrap();
                     for (funcname, func)  in &super_class.methods {
                         if !class.methods.contains_key(funcname) {
@@ -223,14 +433,384 @@ impl Statement for ClassDeclStatement {
                         }
                     }
                     class.super_class = Some(Box::new(super_class.clone()));
-                    state.define_class(&class.name, class.clone());
+                    *cell.borrow_mut() = Value::obj(class);
                     *current_stmt_ind += 1;
-                    return;
+                    return Ok(());
                 }
             }
-            handle_error(&supclass_token.line, ErrorType::RuntimeError, "Superclass must be a class.");
+            return handle_error(&super_class_expr.line, ErrorType::RuntimeError, "Superclass must be a class.");
         }
-        state.define_class(&self.class.name, self.class.clone());
+        let mut class = self.class.clone();
+        class.bind_module(state.current_module.clone());
+        class.bind_closure(state);
+        *cell.borrow_mut() = Value::obj(class);
         *current_stmt_ind += 1;
+        Ok(())
     }
-}
\ No newline at end of file
+
+    fn check_types(&self, checker: &mut TypeChecker) {
+        let class = &self.class;
+        let methods = class.methods.iter()
+            .map(|(name, method)| (name.clone(), method.function_decl.signature.clone()))
+            .collect();
+        let class_info = ClassInfo {
+            super_class: self.super_class.as_ref().map(|super_class| super_class.ident_name.clone()),
+            fields: class.fields.clone(),
+            methods,
+            constructor: class.constructor.as_ref().map(|init| init.function_decl.signature.clone())
+        };
+        checker.define_class(&class.name, class_info);
+        let this = StaticType::Instance(class.name.clone());
+        for method in class.methods.values().chain(class.constructor.iter()) {
+            method.check_body(checker, Some(this.clone()));
+        }
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        let superclass = self.super_class.as_ref().map(|super_class| (super_class.ident_name.as_str(), super_class.line));
+        let methods = self.class.methods.values().chain(self.class.constructor.iter());
+        emitter.class_declaration(&self.class.name, superclass, methods)
+    }
+}
+
+pub struct YieldStatement {
+    pub expression: Box<dyn Expression>
+}
+
+impl Statement for YieldStatement {
+    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) -> LoxResult<()> {
+        let value = self.expression.evaluate(state)?;
+        suspend(state, value, *current_stmt_ind + 1);
+        *current_stmt_ind = MAX;
+        Ok(())
+    }
+
+    fn check_types(&self, checker: &mut TypeChecker) {
+        self.expression.static_type(checker);
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        self.expression.emit(emitter)?;
+        emitter.op(OpCode::Yield, self.expression.get_line());
+        Ok(())
+    }
+}
+
+// `yield()` hands control to the other fibers.
+pub struct FiberYieldStatement {
+    pub line: u32
+}
+
+impl Statement for FiberYieldStatement {
+    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) -> LoxResult<()> {
+        // A fiber resumes here after yielding and goes on past the statement.
+        if state.resume_partial(self).is_some() {
+            *current_stmt_ind += 1;
+            return Ok(());
+        }
+        if current_fiber().is_some() && can_suspend(state) {
+            state.save_partial(self, Vec::new(), false);
+            return handle_error(&self.line, ErrorType::FiberBlocked, "Fiber yielded.");
+        }
+        yield_in_place(state)?;
+        *current_stmt_ind += 1;
+        Ok(())
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        emitter.op(OpCode::FiberYield, self.line);
+        Ok(())
+    }
+}
+
+pub struct EnumDeclStatement {
+    pub name: String,
+    pub variants: Vec<String>,
+    pub slot: Option<usize>,
+    pub line: u32
+}
+
+impl Statement for EnumDeclStatement {
+    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) -> LoxResult<()> {
+        state.define_variable(&self.name, Value::obj(Enum::new(&self.name, &self.variants)), self.slot);
+        *current_stmt_ind += 1;
+        Ok(())
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        let template = VmValue::Object(Rc::new(Enum::new(&self.name, &self.variants)));
+        emitter.enum_declaration(template, &self.name, self.line)
+    }
+}
+
+pub struct ThrowStatement {
+    pub expression: Box<dyn Expression>,
+    pub line: u32
+}
+
+impl Statement for ThrowStatement {
+    fn run(&self, state: &mut BlockScopes, _current_stmt_ind: &mut usize) -> LoxResult<()> {
+        let mut value = self.expression.evaluate(state)?;
+        if let Some(instance) = value.as_class_instance() {
+            let line_key = String::from("line");
+            if instance.get(&line_key).is_some_and(|line| line.get_type() == Type::NIL) {
+                instance.set(&line_key, Value::Number(self.line as f64));
+            }
+        }
+        Err(LoxError::thrown(value, self.line))
+    }
+
+    fn check_types(&self, checker: &mut TypeChecker) {
+        self.expression.static_type(checker);
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        self.expression.emit(emitter)?;
+        emitter.op(OpCode::Throw, self.line);
+        Ok(())
+    }
+}
+
+pub enum TryOutcome {
+    Completed,
+    Returned,
+    Threw(LoxError)
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum TryStage {
+    Body,
+    Catch,
+    Finally
+}
+
+// Where a `try` left off when a `yield` inside it suspended the generator:
+// the stage and statement to resume at, the frames the try had opened and,
+// in `finally`, what the try does once `finally` is over.
+pub struct SuspendedTry {
+    stage: TryStage,
+    position: usize,
+    frames: usize,
+    outcome: TryOutcome
+}
+
+pub struct TryStatement {
+    pub body: Vec<Box<dyn Statement>>,
+    pub catch_name: Option<String>,
+    pub catch_slot: Option<usize>,
+    pub catch_body: Vec<Box<dyn Statement>>,
+    pub finally_body: Option<Vec<Box<dyn Statement>>>,
+    pub line: u32
+}
+
+impl Statement for TryStatement {
+    // A resumed try is the first statement its body runs, so the innermost
+    // suspended try on the stack is this one.
+    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) -> LoxResult<()> {
+        let resumed = state.current().tries.pop();
+        let depth = state.depth - resumed.as_ref().map_or(0, |suspended| suspended.frames);
+        let (stage, mut position, mut outcome) = match resumed {
+            Some(suspended) => (suspended.stage, suspended.position, suspended.outcome),
+            None => (TryStage::Body, 0, TryOutcome::Completed)
+        };
+        let mut catching = stage == TryStage::Catch;
+        if stage == TryStage::Body {
+            let result = TryStatement::run_protected(state, &self.body, position, depth);
+            outcome = TryStatement::suspend_call(state, TryStage::Body, depth, &mut TryOutcome::Completed, result)?;
+            if TryStatement::suspend(state, TryStage::Body, depth, &mut TryOutcome::Completed, current_stmt_ind) {
+                return Ok(());
+            }
+            position = 0;
+            if let (TryOutcome::Threw(thrown), Some(name)) = (&outcome, &self.catch_name) {
+                let error = TryStatement::thrown_to_object(thrown, state)?;
+                state.start_child_block();
+                state.define_variable(name, error, self.catch_slot);
+                catching = true;
+            }
+        }
+        if catching {
+            let result = if self.finally_body.is_some() {
+                TryStatement::run_protected(state, &self.catch_body, position, depth + 1)
+            }
+            else {
+                Interpreter::run_returns(state, &self.catch_body, position)
+                    .map(|returned| if returned { TryOutcome::Returned } else { TryOutcome::Completed })
+            };
+            outcome = TryStatement::suspend_call(state, TryStage::Catch, depth, &mut TryOutcome::Completed, result)?;
+            if TryStatement::suspend(state, TryStage::Catch, depth, &mut TryOutcome::Completed, current_stmt_ind) {
+                return Ok(());
+            }
+            position = 0;
+            if !matches!(outcome, TryOutcome::Returned) {
+                state.end_child_block();
+            }
+        }
+        if let Some(finally_body) = &self.finally_body {
+            let result = Interpreter::run_returns(state, finally_body, position);
+            let returned = TryStatement::suspend_call(state, TryStage::Finally, depth, &mut outcome, result)?;
+            if TryStatement::suspend(state, TryStage::Finally, depth, &mut outcome, current_stmt_ind) {
+                return Ok(());
+            }
+            if returned {
+                *current_stmt_ind = MAX;
+                return Ok(());
+            }
+        }
+        match outcome {
+            TryOutcome::Completed => *current_stmt_ind += 1,
+            TryOutcome::Returned => *current_stmt_ind = MAX,
+            TryOutcome::Threw(thrown) => return Err(thrown)
+        }
+        Ok(())
+    }
+
+    fn check_types(&self, checker: &mut TypeChecker) {
+        for stmt in &self.body {
+            stmt.check_types(checker);
+        }
+        checker.start_scope();
+        if let Some(name) = &self.catch_name {
+            checker.declare(name, StaticType::Any);
+        }
+        for stmt in &self.catch_body {
+            stmt.check_types(checker);
+        }
+        checker.end_scope();
+        for stmt in self.finally_body.iter().flatten() {
+            stmt.check_types(checker);
+        }
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        let catch = self.catch_name.as_deref().map(|name| (name, self.catch_body.as_slice()));
+        emitter.try_statement(&self.body, catch, self.finally_body.as_deref(), self.line)
+    }
+}
+
+impl TryStatement {
+    // Only catchable errors become a `Threw` outcome, anything else keeps propagating.
+    fn run_protected(state: &mut BlockScopes, stmts: &[Box<dyn Statement>], start: usize, depth: usize) -> LoxResult<TryOutcome> {
+        let module = state.current_module.clone();
+        let result = Interpreter::run_returns(state, stmts, start);
+        match result {
+            Ok(true) => Ok(TryOutcome::Returned),
+            Ok(false) => Ok(TryOutcome::Completed),
+            Err(error) if error.is_suspension() => Err(error),
+            Err(error) => {
+                state.switch_module(module);
+                while state.depth > depth {
+                    state.end_child_block();
+                }
+                if !error.is_catchable() {
+                    return Err(error);
+                }
+                Ok(TryOutcome::Threw(error))
+            }
+        }
+    }
+
+    // When a `yield` is unwinding through the try, records where the try
+    // resumes and makes the generator resume at the try itself.
+    fn suspend(state: &mut BlockScopes, stage: TryStage, depth: usize, outcome: &mut TryOutcome, 
+        current_stmt_ind: &mut usize) -> bool
+    {
+        let Some(position) = resume_at_try(state, *current_stmt_ind) else {
+            return false;
+        };
+        let outcome = mem::replace(outcome, TryOutcome::Completed);
+        let frames = state.depth - depth;
+        state.current().tries.push(SuspendedTry { stage, position, frames, outcome });
+        *current_stmt_ind = MAX;
+        true
+    }
+
+    // When a call suspended the statement the running body stopped at inside
+    // the try, records where the try resumes once the call has returned.
+    fn suspend_call<T>(state: &mut BlockScopes, stage: TryStage, depth: usize, outcome: &mut TryOutcome, 
+        result: LoxResult<T>) -> LoxResult<T>
+    {
+        if matches!(&result, Err(error) if error.is_suspension()) {
+            let position = state.current().position;
+            let outcome = mem::replace(outcome, TryOutcome::Completed);
+            let frames = state.own_depth() - depth;
+            state.current().tries.push(SuspendedTry { stage, position, frames, outcome });
+        }
+        result
+    }
+
+    fn thrown_to_object(thrown: &LoxError, state: &mut BlockScopes) -> LoxResult<Value> {
+        if let Some(value) = &thrown.thrown {
+            return Ok(*value.0.clone());
+        }
+        let line = thrown.span.line;
+        let error_class = state.global("Error");
+        match error_class.as_ref().and_then(|class| class.as_class()) {
+            Some(class) => {
+                let mut error = class.call_with_args(Vec::from([Value::obj(Str(thrown.message.clone()))]), state, &line)?;
+                error.set(&String::from("line"), Value::Number(line as f64));
+                Ok(Value::obj(error))
+            },
+            None => Ok(Value::obj(Str(thrown.message.clone())))
+        }
+    }
+}
+
+pub struct ImportStatement {
+    pub path: String,
+    pub base_dir: PathBuf,
+    pub alias: String,
+    pub line: u32,
+    pub slot: Option<usize>
+}
+
+impl Statement for ImportStatement {
+    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) -> LoxResult<()> {
+        let module_path = resolve_path(&self.base_dir, &self.path, &self.line)?;
+        let module = load_module(&module_path, &self.line)?;
+        state.define_variable(&self.alias, Value::obj(module), self.slot);
+        *current_stmt_ind += 1;
+        Ok(())
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        emitter.import(&self.path, &self.base_dir.to_string_lossy(), self.line)?;
+        emitter.declare_variable(&self.alias)?;
+        emitter.define_variable(&self.alias, false, self.line)
+    }
+}
+
+pub struct FromImportStatement {
+    pub path: String,
+    pub base_dir: PathBuf,
+    pub names: Vec<String>,
+    pub slots: Vec<Option<usize>>,
+    pub line: u32
+}
+
+impl Statement for FromImportStatement {
+    fn run(&self, state: &mut BlockScopes, current_stmt_ind: &mut usize) -> LoxResult<()> {
+        let module_path = resolve_path(&self.base_dir, &self.path, &self.line)?;
+        let module = load_module(&module_path, &self.line)?;
+        for (name, slot) in self.names.iter().zip(&self.slots) {
+            match module.get(name) {
+                Some(value) => {
+                    state.define_variable(name, value, *slot);
+                },
+                None => return handle_error(&self.line, ErrorType::RuntimeError, 
+                    format!("Module '{}' has no member '{}'.", self.path, name).as_str())
+            }
+        }
+        *current_stmt_ind += 1;
+        Ok(())
+    }
+
+    fn emit(&self, emitter: &mut Emitter) -> EmitResult {
+        for name in &self.names {
+            emitter.import(&self.path, &self.base_dir.to_string_lossy(), self.line)?;
+            emitter.module_member(name, &self.path, self.line)?;
+            emitter.declare_variable(name)?;
+            emitter.define_variable(name, false, self.line)?;
+        }
+        Ok(())
+    }
+}
@@ -2,70 +2,88 @@ use std::collections::HashMap;
 
 use crate::class::Class;
 use crate::compiler::Compiler;
-use crate::error_handler::{handle_error, ErrorType};
+use crate::error_handler::{handle_error, ErrorType, LoxResult};
+use crate::parser::expressions::{Identifier, IdentifierExpr};
 use crate::scanner::declarations::TokenType;
 use crate::statements::function_stmt::func_decl;
+use crate::statements::simple_statement::type_annotation;
 use crate::statements::{ClassDeclStatement, FunctionDeclStatement};
 
 
-pub fn class_decl_statement(compiler: &mut Compiler) -> ClassDeclStatement {
+pub fn class_decl_statement(compiler: &mut Compiler) -> LoxResult<ClassDeclStatement> {
     compiler.advance();
     let class_name = compiler.parser.current_token().lexeme.to_string();
     compiler.advance();        
-    let mut super_class_name = None;
+    let mut super_class = None;
     
-    if compiler.parser.current_token().token_type == TokenType::LESS {
+    let slot = if compiler.parser.current_token().token_type == TokenType::LESS {
         compiler.advance();
         let token = compiler.parser.current_token().clone();
-        compiler.parser.check_token(TokenType::IDENTIFIER, "Identifier");
+        compiler.parser.check_token(TokenType::IDENTIFIER, "Identifier")?;
         if token.lexeme.to_string() == class_name {
-            handle_error(&token.line, ErrorType::SyntacticError, 
+            return handle_error(&token.line, ErrorType::SyntacticError, 
                 format!(" Error at {}: A class can't inherit from itself", class_name).as_str());
         }
-        super_class_name = Some(token);
-        compiler.environment.start_child_class(&class_name);
+        let super_class_expr = IdentifierExpr::new(token.lexeme.to_string(), None, token.line);
+        let identifier = Identifier::new(token.lexeme.to_string(), token.line, super_class_expr.resolution.clone());
+        compiler.environment.check_identifiers(Vec::from([identifier]), token.line)?;
+        super_class = Some(super_class_expr);
+        compiler.environment.start_child_class(&class_name)
     }
     else {
-        compiler.environment.start_class(&class_name);
-    }
-    compiler.parser.check_token(TokenType::LEFTBRACE, "{");
+        compiler.environment.start_class(&class_name)
+    };
+    compiler.parser.check_token(TokenType::LEFTBRACE, "{")?;
 
     let mut methods = HashMap::new();
     let mut constructor: Option<FunctionDeclStatement> = None;  
+    let mut fields = HashMap::new();
     while compiler.parser.current_token().token_type != TokenType::RIGHTBRACE {
         let funcname = compiler.parser.current_token().lexeme.to_string();
+        let next_index = compiler.parser.current_index + 1;
+        if next_index < compiler.parser.size && compiler.parser.tokens_list[next_index].token_type == TokenType::COLON {
+            compiler.advance();
+            fields.insert(funcname, type_annotation(compiler)?);
+            compiler.parser.check_token(TokenType::SEMICOLON, ";")?;
+            continue;
+        }
         if funcname == "init" {
             compiler.environment.start_init_class_func();
-            let func = func_decl(compiler, funcname.clone());
+            let mut func = func_decl(compiler, funcname.clone())?;
+            func.initializer = true;
             let func_declaration = FunctionDeclStatement {
                 function_decl: func,
-                extern_variables: compiler.environment.end_class_func()
+                captures: compiler.environment.end_class_func(),
+                slot: None
             };
             constructor = Some(func_declaration);
             continue;
         }
         compiler.environment.start_class_func();
-        let func = func_decl(compiler, funcname.clone());
+        let func = func_decl(compiler, funcname.clone())?;
         let func_declaration = FunctionDeclStatement {
             function_decl: func,
-            extern_variables: compiler.environment.end_class_func()
+            captures: compiler.environment.end_class_func(),
+            slot: None
         };
         methods.insert(funcname, func_declaration);
 
     }
     
-    compiler.parser.check_token(TokenType::RIGHTBRACE, "}");
+    compiler.parser.check_token(TokenType::RIGHTBRACE, "}")?;
     compiler.environment.end_class();
     let class_obj = Class {
         name: class_name,
         methods,
         constructor,
         inherited_methods: HashMap::new(),
-        super_class: None
+        super_class: None,
+        fields
     };
-    ClassDeclStatement {
+    Ok(ClassDeclStatement {
         class: class_obj,
-        super_class_token: super_class_name
-    }
+        super_class,
+        slot
+    })
 }
 
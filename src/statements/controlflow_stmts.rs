@@ -1,14 +1,22 @@
 
 use crate::compiler::Compiler;
-use crate::error_handler::{handle_error, ErrorType};
+use crate::error_handler::{handle_error, ErrorType, LoxResult};
 use crate::statements::classes_decl_stmt::class_decl_statement;
-use crate::statements::function_stmt::{func_decl_statement, return_statement};
+use crate::statements::enum_decl_stmt::enum_decl_statement;
+use crate::statements::exception_stmts::{throw_statement, try_statement};
+use crate::statements::function_stmt::{fiber_yield_statement, func_decl_statement, return_statement, yield_statement};
+use crate::statements::import_stmts::{from_import_statement, import_statement};
 use crate::statements::simple_statement::{expr_statement, print_statement, var_statement};
-use crate::statements::{ BackToStatement, EndBlockStatement, ExprStatement, GoToStatement, JumpStatement, StartBlockStatement, Statement};
+use crate::statements::{ BackToStatement, EndBlockStatement, ExprStatement, GoToStatement, IterInitStatement, IterNextStatement, JumpStatement, StartBlockStatement, Statement, VarStatement};
 use crate::scanner::declarations::TokenType;
-use crate::parser::{declarations::Bool, expressions::{Expression, LiteralExpr}};
+use crate::parser::{declarations::Value, expressions::{BinaryExpr, Expression, Identifier, IdentifierExpr, LiteralExpr}};
+use crate::parser::operators_decl::BinaryOperator;
+use crate::iterator::ITERATOR_KEY;
+use crate::typecheck::StaticType;
 
-pub fn block_scope(compiler: &mut Compiler) -> Vec<Box<dyn Statement>> {
+const MATCH_KEY: &str = "@match";
+
+pub fn block_scope(compiler: &mut Compiler) -> LoxResult<Vec<Box<dyn Statement>>> {
     let mut stmts: Vec<Box<dyn Statement>> = Vec::new();
     compiler.environment.start_block();
     stmts.push(Box::new(StartBlockStatement{}));
@@ -16,92 +24,116 @@ pub fn block_scope(compiler: &mut Compiler) -> Vec<Box<dyn Statement>> {
     while compiler.not_reach_end() {
         let token = compiler.parser.current_token();
         match token.token_type {
-            TokenType::VAR => {
-                stmts.push(Box::new(var_statement(compiler)));
+            TokenType::VAR | TokenType::CONST => {
+                stmts.push(Box::new(var_statement(compiler)?));
             },
             TokenType::RIGHTBRACE => {
                 compiler.advance();
                 stmts.push(Box::new(EndBlockStatement{}));
                 compiler.environment.end_block();
-                return stmts;
+                return Ok(stmts);
             },
             TokenType::FUN => {
-                stmts.push(Box::new(func_decl_statement(compiler)));
+                stmts.push(Box::new(func_decl_statement(compiler)?));
             },
-            _ => stmts.append(&mut block_statements(compiler, token.token_type))
+            _ => stmts.append(&mut block_statements(compiler, token.token_type)?)
         } 
     }
 
     compiler.parser.current_index -= 1;
     let last_token = compiler.parser.current_token();
     handle_error(&last_token.line, ErrorType::SyntacticError, 
-        format!("Error at {}: Expect '}}'", last_token.lexeme).as_str());
+        format!("Error at {}: Expect '}}'", last_token.lexeme).as_str())
 }
 
-pub fn statement(compiler: &mut Compiler) -> Vec<Box<dyn Statement>> {
+pub fn statement(compiler: &mut Compiler) -> LoxResult<Vec<Box<dyn Statement>>> {
     let token = compiler.parser.current_token();
     match token.token_type {
         TokenType::FUN => {
-            let fun_stmt: Box<dyn Statement> = Box::new(func_decl_statement(compiler));
-            Vec::from([fun_stmt])
+            let fun_stmt: Box<dyn Statement> = Box::new(func_decl_statement(compiler)?);
+            Ok(Vec::from([fun_stmt]))
         },
-        TokenType::VAR => {
-            let var_stmt: Box<dyn Statement> = Box::new(var_statement(compiler));
-            Vec::from([var_stmt])
+        TokenType::VAR | TokenType::CONST => {
+            let var_stmt: Box<dyn Statement> = Box::new(var_statement(compiler)?);
+            Ok(Vec::from([var_stmt]))
         },
         _ => block_statements(compiler, token.token_type)
     } 
 }
 
-fn statement_condition(compiler: &mut Compiler) -> Vec<Box<dyn Statement>> {
+fn statement_condition(compiler: &mut Compiler) -> LoxResult<Vec<Box<dyn Statement>>> {
     let token = compiler.parser.current_token();
     match token.token_type {
-        TokenType::VAR | TokenType::FUN | TokenType::CLASS => {
-            handle_error(&token.line, ErrorType::SyntacticError, "Error: Expect expression.");
+        TokenType::VAR | TokenType::CONST | TokenType::FUN | TokenType::CLASS | TokenType::ENUM => {
+            handle_error(&token.line, ErrorType::SyntacticError, "Error: Expect expression.")
         },
         _ => block_statements(compiler, token.token_type)
     } 
 }
 
-pub fn block_statements(compiler: &mut Compiler, tokentype: TokenType) -> Vec<Box<dyn Statement>> {
+pub fn block_statements(compiler: &mut Compiler, tokentype: TokenType) -> LoxResult<Vec<Box<dyn Statement>>> {
     let mut stmts: Vec<Box<dyn Statement>> = Vec::new();
     match tokentype {
+        TokenType::IDENTIFIER if is_from_import(compiler) => {
+            stmts.push(Box::new(from_import_statement(compiler)?));
+        },
+        TokenType::IDENTIFIER if is_match_statement(compiler) => {
+            stmts.append(&mut match_statement(compiler)?);
+        },
         TokenType::IDENTIFIER => {
-            stmts.push(Box::new(expr_statement(compiler)));
+            stmts.push(Box::new(expr_statement(compiler)?));
         },
         TokenType::LEFTBRACE => {
-            stmts.append(&mut block_scope(compiler));
+            stmts.append(&mut block_scope(compiler)?);
         },
         TokenType::IF => {
-            stmts.append(&mut if_statement(compiler));
+            stmts.append(&mut if_statement(compiler)?);
         },
         TokenType::WHILE => {
-            stmts.append(&mut while_statement(compiler));
+            stmts.append(&mut while_statement(compiler)?);
         },
         TokenType::FOR => {
-            stmts.append(&mut for_statement(compiler));
+            stmts.append(&mut for_statement(compiler)?);
         },
         TokenType::PRINT => {
-            stmts.push(Box::new(print_statement(compiler)));
+            stmts.push(Box::new(print_statement(compiler)?));
         },
         TokenType::RETURN => {
-            stmts.push(Box::new(return_statement(compiler)));
+            stmts.push(Box::new(return_statement(compiler)?));
         },
         TokenType::CLASS => {
-            stmts.push(Box::new(class_decl_statement(compiler)));
+            stmts.push(Box::new(class_decl_statement(compiler)?));
+        },
+        TokenType::ENUM => {
+            stmts.push(Box::new(enum_decl_statement(compiler)?));
+        },
+        TokenType::TRY => {
+            stmts.push(Box::new(try_statement(compiler)?));
+        },
+        TokenType::THROW => {
+            stmts.push(Box::new(throw_statement(compiler)?));
+        },
+        TokenType::YIELD if is_fiber_yield(compiler) => {
+            stmts.push(Box::new(fiber_yield_statement(compiler)?));
+        },
+        TokenType::YIELD => {
+            stmts.push(Box::new(yield_statement(compiler)?));
+        },
+        TokenType::IMPORT => {
+            stmts.push(Box::new(import_statement(compiler)?));
         },
         _ => {
-            stmts.push(Box::new(expr_statement(compiler)));
+            stmts.push(Box::new(expr_statement(compiler)?));
         } 
     }
-    return stmts;
+    Ok(stmts)
 }
 
-pub fn if_statement(compiler: &mut Compiler) -> Vec<Box<dyn Statement>> {
+pub fn if_statement(compiler: &mut Compiler) -> LoxResult<Vec<Box<dyn Statement>>> {
     compiler.advance();
-    let cond_expr = compiler.parser.expression();
-    compiler.environment.check_identifiers(compiler.parser.get_current_expr_identifiers(), cond_expr.get_line());
-    let mut if_body = statement_condition(compiler);
+    let cond_expr = compiler.parser.expression()?;
+    compiler.environment.check_identifiers(compiler.parser.get_current_expr_identifiers(), cond_expr.get_line())?;
+    let mut if_body = statement_condition(compiler)?;
 
     let size_ifblock = if_body.len() + 2;
     let jumpif = jump(cond_expr, size_ifblock);
@@ -112,7 +144,7 @@ pub fn if_statement(compiler: &mut Compiler) -> Vec<Box<dyn Statement>> {
     result_stmts.append(&mut if_body);
     if compiler.parser.current_index == compiler.parser.size {
         result_stmts.push(go_to( 1));
-        return result_stmts;
+        return Ok(result_stmts);
     }
 
     let mut new_token = compiler.parser.current_token().clone();
@@ -127,10 +159,10 @@ pub fn if_statement(compiler: &mut Compiler) -> Vec<Box<dyn Statement>> {
         
         if new_token.token_type == TokenType::IF {
             compiler.advance();
-            let sub_if_cond = compiler.parser.expression();
-            compiler.environment.check_identifiers(compiler.parser.get_current_expr_identifiers(), sub_if_cond.get_line());
+            let sub_if_cond = compiler.parser.expression()?;
+            compiler.environment.check_identifiers(compiler.parser.get_current_expr_identifiers(), sub_if_cond.get_line())?;
 
-            let sub_if_body = statement_condition(compiler);
+            let sub_if_body = statement_condition(compiler)?;
             let size_block = sub_if_body.len() + 2;
             stmt_count += size_block;
             conditions.push(sub_if_cond);
@@ -143,7 +175,7 @@ pub fn if_statement(compiler: &mut Compiler) -> Vec<Box<dyn Statement>> {
             break;
         }
         else {
-            let else_statement = statement_condition(compiler);
+            let else_statement = statement_condition(compiler)?;
             stmt_count += else_statement.len() + 1;
 
             else_stmt = Some(else_statement);
@@ -163,58 +195,61 @@ pub fn if_statement(compiler: &mut Compiler) -> Vec<Box<dyn Statement>> {
         result_stmts.append(&mut else_statement);
         result_stmts.push(go_to(1));
     }
-    result_stmts
+    Ok(result_stmts)
 }
 
-pub fn while_statement(compiler: &mut Compiler) -> Vec<Box<dyn Statement>> {
+pub fn while_statement(compiler: &mut Compiler) -> LoxResult<Vec<Box<dyn Statement>>> {
     compiler.advance();
-    let cond_expr = compiler.parser.expression();
-    compiler.environment.check_identifiers(compiler.parser.get_current_expr_identifiers(), cond_expr.get_line());
+    let cond_expr = compiler.parser.expression()?;
+    compiler.environment.check_identifiers(compiler.parser.get_current_expr_identifiers(), cond_expr.get_line())?;
 
     let mut stmts: Vec<Box<dyn Statement>> = Vec::new();
-    let mut while_body = statement_condition(compiler);
+    let mut while_body = statement_condition(compiler)?;
 
     let size_whileblock = while_body.len() + 2;
     stmts.push(jump(cond_expr, size_whileblock));
     stmts.append(&mut while_body);
     stmts.push(back_to(size_whileblock - 1));
-    stmts
+    Ok(stmts)
 }
 
-pub fn for_statement(compiler: &mut Compiler) -> Vec<Box<dyn Statement>> {
+pub fn for_statement(compiler: &mut Compiler) -> LoxResult<Vec<Box<dyn Statement>>> {
     compiler.advance();
+    if is_for_in_loop(compiler) {
+        return for_in_statement(compiler);
+    }
     let mut stmts: Vec<Box<dyn Statement>> = Vec::new();
     stmts.push(Box::new(StartBlockStatement{}));
     compiler.environment.start_block();
-    compiler.parser.check_token(TokenType::LEFTPAREN, "(");
+    compiler.parser.check_token(TokenType::LEFTPAREN, "(")?;
     let token = compiler.parser.current_token();
     let line = token.line;
     if token.token_type == TokenType::VAR {
-        stmts.push(Box::new(var_statement(compiler)));
+        stmts.push(Box::new(var_statement(compiler)?));
     }
     else if token.token_type == TokenType::IDENTIFIER {
-        stmts.push(Box::new(expr_statement(compiler)));
+        stmts.push(Box::new(expr_statement(compiler)?));
     }
     else {
-        compiler.parser.check_token(TokenType::SEMICOLON, ";");
+        compiler.parser.check_token(TokenType::SEMICOLON, ";")?;
     }
-    let mut condition: Box<dyn Expression> = Box::new(LiteralExpr::new(Box::new(Bool(true)), line)); 
+    let mut condition: Box<dyn Expression> = Box::new(LiteralExpr::new(Value::Bool(true), line)); 
     if compiler.parser.current_token().token_type != TokenType::SEMICOLON {
-        condition = compiler.parser.expression();
-        compiler.environment.check_identifiers(compiler.parser.get_current_expr_identifiers(), condition.get_line());
+        condition = compiler.parser.expression()?;
+        compiler.environment.check_identifiers(compiler.parser.get_current_expr_identifiers(), condition.get_line())?;
 
     }
     let mut body_stmts: Vec<Box<dyn Statement>> = Vec::new();
 
-    compiler.parser.check_token(TokenType::SEMICOLON, ";");
+    compiler.parser.check_token(TokenType::SEMICOLON, ";")?;
     let mut last_instruction: Option<Box<dyn Expression>> = None;
     if compiler.parser.current_token().token_type != TokenType::RIGHTPAREN {
-        let last_expr = compiler.parser.expression();
-        compiler.environment.check_identifiers(compiler.parser.get_current_expr_identifiers(), last_expr.get_line());
+        let last_expr = compiler.parser.expression()?;
+        compiler.environment.check_identifiers(compiler.parser.get_current_expr_identifiers(), last_expr.get_line())?;
         last_instruction = Some(last_expr);
     }
-    compiler.parser.check_token(TokenType::RIGHTPAREN, ")");
-    let mut for_body = statement_condition(compiler);
+    compiler.parser.check_token(TokenType::RIGHTPAREN, ")")?;
+    let mut for_body = statement_condition(compiler)?;
     body_stmts.append(&mut for_body);
     if let Some(expr) = last_instruction {
         let last_stmt = Box::new(ExprStatement{expression: expr});
@@ -226,10 +261,162 @@ pub fn for_statement(compiler: &mut Compiler) -> Vec<Box<dyn Statement>> {
     compiler.environment.end_block();
     stmts.push(Box::new(EndBlockStatement{}));
 
-    stmts
+    Ok(stmts)
+}
+
+// `yield()` takes no operand, which tells it apart from a generator's `yield value;`.
+fn is_fiber_yield(compiler: &Compiler) -> bool {
+    let index = compiler.parser.current_index;
+    let tokens = compiler.parser.tokens_list;
+    index + 2 < compiler.parser.size
+        && tokens[index + 1].token_type == TokenType::LEFTPAREN
+        && tokens[index + 2].token_type == TokenType::RIGHTPAREN
+}
+
+// `from` only starts a statement when a module path follows it.
+fn is_from_import(compiler: &Compiler) -> bool {
+    let index = compiler.parser.current_index;
+    compiler.parser.is_contextual(index, "from") && compiler.parser.is_token(index + 1, TokenType::STRING)
+}
+
+fn is_for_in_loop(compiler: &Compiler) -> bool {
+    let index = compiler.parser.current_index;
+    let tokens = compiler.parser.tokens_list;
+    index + 3 < compiler.parser.size
+        && tokens[index + 1].token_type == TokenType::VAR
+        && compiler.parser.is_contextual(index + 3, "in")
+}
+
+pub fn for_in_statement(compiler: &mut Compiler) -> LoxResult<Vec<Box<dyn Statement>>> {
+    let mut stmts: Vec<Box<dyn Statement>> = Vec::new();
+    stmts.push(Box::new(StartBlockStatement{}));
+    compiler.environment.start_block();
+    compiler.parser.check_token(TokenType::LEFTPAREN, "(")?;
+    compiler.parser.check_token(TokenType::VAR, "var")?;
+    let identifier = compiler.parser.current_token();
+    let (name, line) = (identifier.lexeme.to_string(), identifier.line);
+    compiler.parser.check_token(TokenType::IDENTIFIER, "identifier")?;
+    compiler.parser.check_contextual("in")?;
+    let iterable = compiler.parser.expression()?;
+    compiler.environment.check_identifiers(compiler.parser.get_current_expr_identifiers(), iterable.get_line())?;
+    let iterator_slot = compiler.environment.declaration(&ITERATOR_KEY.to_string(), &line, Vec::new(), line)?;
+    let iterator = hidden_local(compiler, ITERATOR_KEY, line)?;
+    // The loop variable lives in the per-iteration frame `IterNextStatement` pushes.
+    compiler.environment.start_block();
+    let slot = compiler.environment.declaration(&name, &line, Vec::new(), line)?;
+    compiler.parser.check_token(TokenType::RIGHTPAREN, ")")?;
+
+    let mut body_stmts = statement_condition(compiler)?;
+    compiler.environment.end_block();
+    body_stmts.push(Box::new(EndBlockStatement{}));
+    body_stmts.push(back_to(body_stmts.len() + 1));
+    stmts.push(Box::new(IterInitStatement { iterable, slot: iterator_slot }));
+    stmts.push(Box::new(IterNextStatement { name, line, steps: body_stmts.len() + 1, slot, iterator }));
+    stmts.append(&mut body_stmts);
+    compiler.environment.end_block();
+    stmts.push(Box::new(EndBlockStatement{}));
+
+    Ok(stmts)
 }
 
 
+// `match` starts a statement only when a `{` follows its parenthesized subject,
+// so a call to a function named `match` still parses.
+fn is_match_statement(compiler: &Compiler) -> bool {
+    let parser = &compiler.parser;
+    let index = parser.current_index;
+    if !parser.is_contextual(index, "match") || !parser.is_token(index + 1, TokenType::LEFTPAREN) {
+        return false;
+    }
+    let mut depth = 0;
+    for position in index + 1..parser.size {
+        match parser.tokens_list[position].token_type {
+            TokenType::LEFTPAREN => depth += 1,
+            TokenType::RIGHTPAREN => {
+                depth -= 1;
+                if depth == 0 {
+                    return parser.is_token(position + 1, TokenType::LEFTBRACE);
+                }
+            },
+            _ => {}
+        }
+    }
+    false
+}
+
+// The subject is evaluated once into a hidden local and each case compares it
+// with `==`, so enum variants match by identity. The first matching case runs.
+pub fn match_statement(compiler: &mut Compiler) -> LoxResult<Vec<Box<dyn Statement>>> {
+    let line = compiler.parser.current_token().line;
+    compiler.advance();
+    compiler.environment.start_block();
+    let subject = compiler.parser.expression()?;
+    let slot = compiler.environment.declaration(&MATCH_KEY.to_string(), &line, 
+        compiler.parser.get_current_expr_identifiers(), subject.get_line())?;
+    compiler.parser.check_token(TokenType::LEFTBRACE, "{")?;
+
+    let mut conditions: Vec<Box<dyn Expression>> = Vec::new();
+    let mut case_bodies: Vec<Vec<Box<dyn Statement>>> = Vec::new();
+    let mut default_body: Vec<Box<dyn Statement>> = Vec::new();
+    while compiler.parser.current_token().token_type != TokenType::RIGHTBRACE {
+        if compiler.parser.is_contextual(compiler.parser.current_index, "default") {
+            compiler.advance();
+            compiler.parser.check_token(TokenType::COLON, ":")?;
+            default_body = statement_condition(compiler)?;
+            break;
+        }
+        compiler.parser.check_contextual("case")?;
+        let mut condition = match_pattern(compiler)?;
+        while compiler.parser.current_token().token_type == TokenType::COMMA {
+            compiler.advance();
+            let pattern = match_pattern(compiler)?;
+            let pattern_line = pattern.get_line();
+            condition = Box::new(BinaryExpr::new(BinaryOperator::OR, condition, pattern, pattern_line));
+        }
+        compiler.parser.check_token(TokenType::COLON, ":")?;
+        conditions.push(condition);
+        case_bodies.push(statement_condition(compiler)?);
+    }
+    compiler.parser.check_token(TokenType::RIGHTBRACE, "}")?;
+
+    let mut stmts: Vec<Box<dyn Statement>> = Vec::new();
+    stmts.push(Box::new(StartBlockStatement{}));
+    stmts.push(Box::new(VarStatement {
+        name: MATCH_KEY.to_string(),
+        expression: subject,
+        constant: false,
+        annotation: StaticType::Any,
+        slot
+    }));
+    let mut steps_to_end: usize = case_bodies.iter().map(|body| body.len() + 2).sum::<usize>() + default_body.len();
+    for (condition, mut body) in conditions.into_iter().zip(case_bodies) {
+        let size_block = body.len() + 2;
+        steps_to_end -= size_block;
+        stmts.push(jump(condition, size_block));
+        stmts.append(&mut body);
+        stmts.push(go_to(steps_to_end + 1));
+    }
+    stmts.append(&mut default_body);
+    compiler.environment.end_block();
+    stmts.push(Box::new(EndBlockStatement{}));
+    Ok(stmts)
+}
+
+fn match_pattern(compiler: &mut Compiler) -> LoxResult<Box<dyn Expression>> {
+    let pattern = compiler.parser.expression()?;
+    let line = pattern.get_line();
+    compiler.environment.check_identifiers(compiler.parser.get_current_expr_identifiers(), line)?;
+    let subject = hidden_local(compiler, MATCH_KEY, line)?;
+    Ok(Box::new(BinaryExpr::new(BinaryOperator::EQUALEQUAL, Box::new(subject), pattern, line)))
+}
+
+// Reads a local the compiler declared for a statement's own bookkeeping.
+fn hidden_local(compiler: &mut Compiler, name: &str, line: u32) -> LoxResult<IdentifierExpr> {
+    let local = IdentifierExpr::new(name.to_string(), None, line);
+    compiler.environment.check_identifiers(Vec::from([Identifier::new(name.to_string(), line, local.resolution.clone())]), line)?;
+    Ok(local)
+}
+
 fn jump(cond: Box<dyn Expression>, steps: usize) -> Box<dyn Statement> {
     Box::new(JumpStatement { 
         condition: cond, 
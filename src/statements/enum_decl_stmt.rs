@@ -0,0 +1,38 @@
+use crate::compiler::Compiler;
+use crate::error_handler::{handle_error, ErrorType, LoxResult};
+use crate::scanner::declarations::TokenType;
+use crate::statements::EnumDeclStatement;
+
+
+pub fn enum_decl_statement(compiler: &mut Compiler) -> LoxResult<EnumDeclStatement> {
+    compiler.advance();
+    let token = compiler.parser.current_token();
+    let (enum_name, line) = (token.lexeme.to_string(), token.line);
+    compiler.parser.check_token(TokenType::IDENTIFIER, "Identifier")?;
+    compiler.parser.check_token(TokenType::LEFTBRACE, "{")?;
+
+    let mut variants: Vec<String> = Vec::new();
+    while compiler.parser.current_token().token_type != TokenType::RIGHTBRACE {
+        let token = compiler.parser.current_token();
+        let (variant, variant_line) = (token.lexeme.to_string(), token.line);
+        compiler.parser.check_token(TokenType::IDENTIFIER, "Identifier")?;
+        if variants.contains(&variant) {
+            return handle_error(&variant_line, ErrorType::SyntacticError, 
+                format!("Error at '{}': Duplicate variant in enum {}.", variant, enum_name).as_str());
+        }
+        variants.push(variant);
+        if compiler.parser.current_token().token_type != TokenType::COMMA {
+            break;
+        }
+        compiler.advance();
+    }
+    compiler.parser.check_token(TokenType::RIGHTBRACE, "}")?;
+    let slot = compiler.environment.declaration(&enum_name, &line, Vec::new(), line)?;
+
+    Ok(EnumDeclStatement {
+        name: enum_name,
+        variants,
+        slot,
+        line
+    })
+}
@@ -0,0 +1,62 @@
+use crate::compiler::Compiler;
+use crate::error_handler::{handle_error, ErrorType, LoxResult};
+use crate::scanner::declarations::TokenType;
+use crate::statements::controlflow_stmts::block_scope;
+use crate::statements::{Statement, ThrowStatement, TryStatement};
+
+
+pub fn throw_statement(compiler: &mut Compiler) -> LoxResult<ThrowStatement> {
+    let line = compiler.parser.current_token().line;
+    compiler.advance();
+    let expr = compiler.parser.expression()?;
+    compiler.environment.check_identifiers(compiler.parser.get_current_expr_identifiers(), expr.get_line())?;
+    compiler.parser.check_token(TokenType::SEMICOLON, ";")?;
+    Ok(ThrowStatement { expression: expr, line })
+}
+
+pub fn try_statement(compiler: &mut Compiler) -> LoxResult<TryStatement> {
+    let line = compiler.parser.current_token().line;
+    compiler.advance();
+    let outer_try = compiler.environment.set_in_try(true);
+    let body = try_block(compiler, "try")?;
+
+    let mut catch_name = None;
+    let mut catch_slot = None;
+    let mut catch_body = Vec::new();
+    if compiler.not_reach_end() && compiler.parser.current_token().token_type == TokenType::CATCH {
+        compiler.advance();
+        compiler.parser.check_token(TokenType::LEFTPAREN, "(")?;
+        let identifier = compiler.parser.current_token();
+        let (name, name_line) = (identifier.lexeme.to_string(), identifier.line);
+        compiler.parser.check_token(TokenType::IDENTIFIER, "identifier")?;
+        compiler.parser.check_token(TokenType::RIGHTPAREN, ")")?;
+        compiler.environment.start_block();
+        catch_slot = compiler.environment.declaration(&name, &name_line, Vec::new(), name_line)?;
+        catch_body = try_block(compiler, "catch")?;
+        compiler.environment.end_block();
+        catch_name = Some(name);
+    }
+
+    let mut finally_body = None;
+    if compiler.not_reach_end() && compiler.parser.current_token().token_type == TokenType::FINALLY {
+        compiler.advance();
+        finally_body = Some(try_block(compiler, "finally")?);
+    }
+
+    compiler.environment.set_in_try(outer_try);
+
+    if catch_name.is_none() && finally_body.is_none() {
+        return handle_error(&line, ErrorType::SyntacticError, 
+            "Error at 'try': Expect 'catch' or 'finally' after try block.");
+    }
+    Ok(TryStatement { body, catch_name, catch_slot, catch_body, finally_body, line })
+}
+
+fn try_block(compiler: &mut Compiler, keyword: &str) -> LoxResult<Vec<Box<dyn Statement>>> {
+    if compiler.parser.current_token().token_type != TokenType::LEFTBRACE {
+        let token = compiler.parser.current_token();
+        return handle_error(&token.line, ErrorType::SyntacticError, 
+            format!("Error at '{}': Expect '{{' after '{}'.", token.lexeme, keyword).as_str());
+    }
+    block_scope(compiler)
+}
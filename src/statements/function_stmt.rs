@@ -1,112 +1,153 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashSet;
 use std::rc::Rc;
 use crate::compiler::Compiler;
-use crate::error_handler::{handle_error, ErrorType};
+use crate::error_handler::{handle_error, ErrorType, LoxResult};
 use crate::function::Function;
-use crate::parser::declarations::{NIL};
+use crate::parser::declarations::Value;
 use crate::parser::expressions::{Expression, LiteralExpr};
 use crate::scanner::declarations::TokenType;
 use crate::statements::controlflow_stmts::block_statements;
-use crate::statements::simple_statement::var_statement;
-use crate::statements::{FunctionDeclStatement, ReturnStatement, Statement}; 
+use crate::statements::simple_statement::{type_annotation, var_statement};
+use crate::typecheck::{Signature, StaticType};
+use crate::statements::{FunctionDeclStatement, FiberYieldStatement, ReturnStatement, Statement, YieldStatement}; 
 
 
-pub fn return_statement(compiler: &mut Compiler) -> ReturnStatement {
+pub fn return_statement(compiler: &mut Compiler) -> LoxResult<ReturnStatement> {
     compiler.advance();
     let token = compiler.parser.current_token();
-    compiler.environment.check_return_validity(&token.line);
+    compiler.environment.check_return_validity(&token.line)?;
     if token.token_type == TokenType::SEMICOLON {
-        let nil_expr: Box<dyn Expression> = Box::new(LiteralExpr::new(Box::new(NIL), token.line) );
+        let nil_expr: Box<dyn Expression> = Box::new(LiteralExpr::new(Value::Nil, token.line) );
         compiler.advance();
-        return ReturnStatement::new(nil_expr);
+        return Ok(ReturnStatement::new(nil_expr));
     }
-    let expr: Box<dyn Expression> = compiler.parser.expression();
-    compiler.environment.check_constructor_return_validity(&expr.get_line());
-    compiler.environment.check_identifiers(compiler.parser.get_current_expr_identifiers(), expr.get_line());
-    compiler.parser.check_token(TokenType::SEMICOLON, ";");
-    ReturnStatement::new(expr)
+    let expr: Box<dyn Expression> = compiler.parser.expression()?;
+    compiler.environment.check_constructor_return_validity(&expr.get_line())?;
+    compiler.environment.check_identifiers(compiler.parser.get_current_expr_identifiers(), expr.get_line())?;
+    compiler.parser.check_token(TokenType::SEMICOLON, ";")?;
+    if expr.is_call() && compiler.environment.can_tail_call() {
+        return Ok(ReturnStatement::tail_call(expr));
+    }
+    Ok(ReturnStatement::new(expr))
 }
 
-pub fn block_func_statement(compiler: &mut Compiler) -> Vec<Box<dyn Statement>> {
+pub fn block_func_statement(compiler: &mut Compiler) -> LoxResult<Vec<Box<dyn Statement>>> {
     let mut stmts: Vec<Box<dyn Statement>> = Vec::new();
     while compiler.not_reach_end() {
         let token = compiler.parser.current_token();
         match token.token_type {
-            TokenType::VAR => {
-                stmts.push(Box::new(var_statement(compiler)));
+            TokenType::VAR | TokenType::CONST => {
+                stmts.push(Box::new(var_statement(compiler)?));
             },
             TokenType::RIGHTBRACE => {
                 compiler.advance();
-                return stmts;
+                return Ok(stmts);
             },
             TokenType::FUN => {
-                stmts.push(Box::new(func_decl_statement(compiler)));
+                stmts.push(Box::new(func_decl_statement(compiler)?));
             },
-            _ => stmts.append(&mut block_statements(compiler, token.token_type))
+            _ => stmts.append(&mut block_statements(compiler, token.token_type)?)
         } 
     }
 
     compiler.parser.current_index -= 1;
     let last_token = compiler.parser.current_token();
     handle_error(&last_token.line, ErrorType::SyntacticError, 
-        format!("Error at {}: Expect '}}'", last_token.lexeme).as_str());
+        format!("Error at {}: Expect '}}'", last_token.lexeme).as_str())
+}
+
+pub fn fiber_yield_statement(compiler: &mut Compiler) -> LoxResult<FiberYieldStatement> {
+    let line = compiler.parser.current_token().line;
+    compiler.advance();
+    compiler.parser.check_token(TokenType::LEFTPAREN, "(")?;
+    compiler.parser.check_token(TokenType::RIGHTPAREN, ")")?;
+    compiler.parser.check_token(TokenType::SEMICOLON, ";")?;
+    Ok(FiberYieldStatement { line })
+}
+
+pub fn yield_statement(compiler: &mut Compiler) -> LoxResult<YieldStatement> {
+    let line = compiler.parser.current_token().line;
+    compiler.environment.check_yield_validity(&line)?;
+    compiler.advance();
+    if compiler.parser.current_token().token_type == TokenType::SEMICOLON {
+        compiler.advance();
+        return Ok(YieldStatement { expression: Box::new(LiteralExpr::new(Value::Nil, line)) });
+    }
+    let expr = compiler.parser.expression()?;
+    compiler.environment.check_identifiers(compiler.parser.get_current_expr_identifiers(), expr.get_line())?;
+    compiler.parser.check_token(TokenType::SEMICOLON, ";")?;
+    Ok(YieldStatement { expression: expr })
 }
 
-pub fn func_decl(compiler: &mut Compiler, funcname: String) -> Function {
+pub fn func_decl(compiler: &mut Compiler, funcname: String) -> LoxResult<Function> {
     compiler.advance();        
     let mut params: Vec<String> = Vec::new();
-    compiler.parser.check_token(TokenType::LEFTPAREN, "(");
+    let mut param_types: Vec<StaticType> = Vec::new();
+    compiler.parser.check_token(TokenType::LEFTPAREN, "(")?;
     let mut current_token = compiler.parser.current_token();
     let line = current_token.line;
     if current_token.token_type != TokenType::RIGHTPAREN {
         loop {
             current_token = compiler.parser.current_token();
             params.push(current_token.lexeme.to_string());
-            compiler.parser.check_token(TokenType::IDENTIFIER, "Identifier");
+            compiler.parser.check_token(TokenType::IDENTIFIER, "Identifier")?;
+            param_types.push(type_annotation(compiler)?);
             if compiler.parser.current_token().token_type != TokenType::COMMA {
                 break;
             } 
             compiler.advance();
         }
     }
-    compiler.parser.check_token(TokenType::RIGHTPAREN, ")");
+    compiler.parser.check_token(TokenType::RIGHTPAREN, ")")?;
+    let return_type = type_annotation(compiler)?;
+    let signature = Signature { name: funcname.clone(), params: param_types, return_type, line };
 
-    has_duplicates_elmts(&params, line);
+    has_duplicates_elmts(&params, line)?;
 
     compiler.environment.set_func_params(&params);
 
-    compiler.parser.check_token(TokenType::LEFTBRACE, "{");
+    compiler.parser.check_token(TokenType::LEFTBRACE, "{")?;
 
-    let statements = block_func_statement(compiler);
-    Function {
+    let statements = block_func_statement(compiler)?;
+    Ok(Function {
         name: funcname.into(),
         params_names: params.into(),
         statements: Rc::new(statements),
-        extra_map: HashMap::new()
-    }
+        captures: Rc::from([]),
+        module: None,
+        signature: Rc::new(signature),
+        generator: false,
+        initializer: false
+    })
 }
 
-pub fn func_decl_statement(compiler: &mut Compiler) -> FunctionDeclStatement {
+pub fn func_decl_statement(compiler: &mut Compiler) -> LoxResult<FunctionDeclStatement> {
     compiler.advance();
+    let generator = compiler.parser.current_token().token_type == TokenType::STAR;
+    if generator {
+        compiler.advance();
+    }
     let ident_str = compiler.parser.current_token().lexeme.to_string();
-    compiler.environment.start_function(&ident_str);
-    let func = func_decl(compiler, ident_str);
-    let extern_declarations = compiler.environment.end_function();
+    let slot = compiler.environment.start_function(&ident_str, generator);
+    let mut func = func_decl(compiler, ident_str)?;
+    func.generator = generator;
+    let captures = compiler.environment.end_function();
 
     let func_decl = FunctionDeclStatement {
         function_decl: func,
-        extern_variables: extern_declarations
+        captures,
+        slot
     };
-    func_decl
+    Ok(func_decl)
 }
 
-fn has_duplicates_elmts(vec: &Vec<String>, line: u32) -> bool {
+fn has_duplicates_elmts(vec: &Vec<String>, line: u32) -> LoxResult<()> {
     let mut seen = HashSet::new();
     for item in vec {
         if !seen.insert(item.clone()) {
-            handle_error(&line, ErrorType::SyntacticError, 
+            return handle_error(&line, ErrorType::SyntacticError, 
                 format!("Error at {}: Already a variable with this name in this scope.", item.clone()).as_str());
         }
     }
-    false
+    Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,57 @@
+use std::path::PathBuf;
+
+use crate::compiler::Compiler;
+use crate::error_handler::LoxResult;
+use crate::scanner::declarations::TokenType;
+use crate::statements::{FromImportStatement, ImportStatement};
+
+
+pub fn import_statement(compiler: &mut Compiler) -> LoxResult<ImportStatement> {
+    let line = compiler.parser.current_token().line;
+    compiler.advance();
+    let path = module_path(compiler)?;
+    let alias = if compiler.parser.is_contextual(compiler.parser.current_index, "as") {
+        compiler.advance();
+        let alias_token = compiler.parser.current_token().lexeme.to_string();
+        compiler.parser.check_token(TokenType::IDENTIFIER, "identifier")?;
+        alias_token
+    }
+    else {
+        PathBuf::from(&path).file_stem().map_or(path.clone(), |stem| stem.to_string_lossy().to_string())
+    };
+    let slot = compiler.environment.declaration(&alias, &line, Vec::new(), line)?;
+    compiler.parser.check_token(TokenType::SEMICOLON, ";")?;
+    Ok(ImportStatement { path, base_dir: base_dir(compiler), alias, line, slot })
+}
+
+pub fn from_import_statement(compiler: &mut Compiler) -> LoxResult<FromImportStatement> {
+    let line = compiler.parser.current_token().line;
+    compiler.advance();
+    let path = module_path(compiler)?;
+    compiler.parser.check_token(TokenType::IMPORT, "import")?;
+    let mut names = Vec::new();
+    let mut slots = Vec::new();
+    loop {
+        let name_token = compiler.parser.current_token();
+        let (name, name_line) = (name_token.lexeme.to_string(), name_token.line);
+        compiler.parser.check_token(TokenType::IDENTIFIER, "identifier")?;
+        slots.push(compiler.environment.declaration(&name, &name_line, Vec::new(), name_line)?);
+        names.push(name);
+        if compiler.parser.current_token().token_type != TokenType::COMMA {
+            break;
+        }
+        compiler.advance();
+    }
+    compiler.parser.check_token(TokenType::SEMICOLON, ";")?;
+    Ok(FromImportStatement { path, base_dir: base_dir(compiler), names, slots, line })
+}
+
+fn module_path(compiler: &mut Compiler) -> LoxResult<String> {
+    let path = compiler.parser.current_token().literal.clone().unwrap_or_default();
+    compiler.parser.check_token(TokenType::STRING, "module path")?;
+    Ok(path)
+}
+
+fn base_dir(compiler: &Compiler) -> PathBuf {
+    compiler.source_path.parent().map_or(PathBuf::new(), |dir| dir.to_path_buf())
+}
@@ -1,55 +1,84 @@
 
 use crate::compiler::Compiler;
-use crate::parser::declarations::NIL;
+use crate::typecheck::StaticType;
+use crate::error_handler::{handle_error, ErrorType, LoxResult};
+use crate::parser::declarations::Value;
 use crate::parser::expressions::LiteralExpr;
 use crate::scanner::declarations::TokenType;
 use crate::statements::{ExprStatement, PrintStatement, VarStatement};
 
 
-pub fn print_statement(compiler: &mut Compiler) -> PrintStatement {
+pub fn print_statement(compiler: &mut Compiler) -> LoxResult<PrintStatement> {
     compiler.advance();
-    let expr = compiler.parser.expression();
-    compiler.environment.check_identifiers(compiler.parser.get_current_expr_identifiers(), expr.get_line());
-    compiler.parser.check_token(TokenType::SEMICOLON, ";");
-    PrintStatement {
+    let expr = compiler.parser.expression()?;
+    compiler.environment.check_identifiers(compiler.parser.get_current_expr_identifiers(), expr.get_line())?;
+    compiler.parser.check_token(TokenType::SEMICOLON, ";")?;
+    Ok(PrintStatement {
         expression: expr
-    }
+    })
 }
 
-pub fn var_statement(compiler: &mut Compiler) -> VarStatement {
+pub fn var_statement(compiler: &mut Compiler) -> LoxResult<VarStatement> {
+    let constant = compiler.parser.current_token().token_type == TokenType::CONST;
     compiler.advance();
     let identifier = compiler.parser.current_token();
     let identifier_str = identifier.lexeme.to_string();
     let identifier_line = identifier.line;
 
-    compiler.parser.check_token(TokenType::IDENTIFIER, "identifier");
+    compiler.parser.check_token(TokenType::IDENTIFIER, "identifier")?;
+    let annotation = type_annotation(compiler)?;
     let token = compiler.parser.current_token();
     if token.token_type == TokenType::EQUAL {
         compiler.advance();
-        let expr = compiler.parser.expression();
-        compiler.environment.declaration(&identifier_str, &identifier_line, 
-            compiler.parser.get_current_expr_identifiers(), expr.get_line());
-        compiler.parser.check_token(TokenType::SEMICOLON, ";"); 
-        return VarStatement {
-            name: identifier_str,
-            expression: expr
+        let expr = compiler.parser.expression()?;
+        let expr_identifiers = compiler.parser.get_current_expr_identifiers();
+        let slot = if constant {
+            compiler.environment.constant_declaration(&identifier_str, &identifier_line, expr_identifiers, expr.get_line())?
+        }
+        else {
+            compiler.environment.declaration(&identifier_str, &identifier_line, expr_identifiers, expr.get_line())?
         };
+        compiler.parser.check_token(TokenType::SEMICOLON, ";")?; 
+        return Ok(VarStatement {
+            name: identifier_str,
+            expression: expr,
+            constant,
+            annotation,
+            slot
+        });
+    }
+    else if constant {
+        handle_error(&identifier_line, ErrorType::SyntacticError, 
+            format!("Error at '{}': Expect '=' after constant name.", identifier_str).as_str())
     }
     else {
-        compiler.environment.declaration(&identifier_str, &identifier_line, 
-            Vec::new(), identifier_line);
-        compiler.parser.check_token(TokenType::SEMICOLON, ";"); 
-        return VarStatement {
+        let slot = compiler.environment.declaration(&identifier_str, &identifier_line, 
+            Vec::new(), identifier_line)?;
+        compiler.parser.check_token(TokenType::SEMICOLON, ";")?; 
+        return Ok(VarStatement {
             name: identifier_str,
-            expression: Box::new(LiteralExpr::new(Box::new(NIL), identifier_line))
-        };
+            expression: Box::new(LiteralExpr::new(Value::Nil, identifier_line)),
+            constant,
+            annotation,
+            slot
+        });
     }
 }
 
-pub fn expr_statement(compiler: &mut Compiler) -> ExprStatement {
-    let expr = compiler.parser.expression();
-    compiler.environment.check_identifiers(compiler.parser.get_current_expr_identifiers(), expr.get_line());
-    compiler.parser.check_token(TokenType::SEMICOLON, ";");
+pub fn expr_statement(compiler: &mut Compiler) -> LoxResult<ExprStatement> {
+    let expr = compiler.parser.expression()?;
+    compiler.environment.check_identifiers(compiler.parser.get_current_expr_identifiers(), expr.get_line())?;
+    compiler.parser.check_token(TokenType::SEMICOLON, ";")?;
 
-    ExprStatement { expression: expr }
-}
\ No newline at end of file
+    Ok(ExprStatement { expression: expr })
+}
+// Parses an optional `: Type` annotation; a missing annotation is `Any`.
+pub fn type_annotation(compiler: &mut Compiler) -> LoxResult<StaticType> {
+    if compiler.parser.current_token().token_type != TokenType::COLON {
+        return Ok(StaticType::Any);
+    }
+    compiler.advance();
+    let type_name = compiler.parser.current_token().lexeme.to_string();
+    compiler.parser.check_token(TokenType::IDENTIFIER, "type name")?;
+    Ok(StaticType::from_name(&type_name))
+}
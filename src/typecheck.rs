@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::error_handler::{ErrorType, LoxError};
+use crate::parser::declarations::{Type, Value};
+use crate::parser::expressions::Expression;
+use crate::statements::Statement;
+
+#[derive(Clone, PartialEq, Default)]
+pub enum StaticType {
+    #[default]
+    Any,
+    Number,
+    String,
+    Bool,
+    Nil,
+    List,
+    Map,
+    Range,
+    Function(Option<Rc<Signature>>),
+    Class(String),
+    Instance(String)
+}
+
+#[derive(Clone, PartialEq, Default)]
+pub struct Signature {
+    pub name: String,
+    pub params: Vec<StaticType>,
+    pub return_type: StaticType,
+    pub line: u32
+}
+
+pub struct ClassInfo {
+    pub super_class: Option<String>,
+    pub fields: HashMap<String, StaticType>,
+    pub methods: HashMap<String, Rc<Signature>>,
+    pub constructor: Option<Rc<Signature>>
+}
+
+pub struct TypeChecker {
+    scopes: Vec<HashMap<String, StaticType>>,
+    classes: HashMap<String, ClassInfo>,
+    functions: Vec<Rc<Signature>>,
+    named_types: Vec<(String, u32)>,
+    errors: Vec<(u32, String)>
+}
+
+impl StaticType {
+    pub fn from_name(name: &str) -> StaticType {
+        match name {
+            "Any" => StaticType::Any,
+            "Number" => StaticType::Number,
+            "String" => StaticType::String,
+            "Bool" | "Boolean" => StaticType::Bool,
+            "Nil" => StaticType::Nil,
+            "List" => StaticType::List,
+            "Map" => StaticType::Map,
+            "Range" => StaticType::Range,
+            "Function" => StaticType::Function(None),
+            _ => StaticType::Instance(name.to_string())
+        }
+    }
+
+    pub fn of_value(value: &Value) -> StaticType {
+        match value.get_type() {
+            Type::NUMBER => StaticType::Number,
+            Type::STRING => StaticType::String,
+            Type::BOOLEAN => StaticType::Bool,
+            Type::NIL => StaticType::Nil,
+            _ => StaticType::Any
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            StaticType::Any => String::from("Any"),
+            StaticType::Number => String::from("Number"),
+            StaticType::String => String::from("String"),
+            StaticType::Bool => String::from("Bool"),
+            StaticType::Nil => String::from("Nil"),
+            StaticType::List => String::from("List"),
+            StaticType::Map => String::from("Map"),
+            StaticType::Range => String::from("Range"),
+            StaticType::Function(_) => String::from("Function"),
+            StaticType::Class(name) => format!("class {}", name),
+            StaticType::Instance(name) => name.clone()
+        }
+    }
+
+    pub fn is_known(&self) -> bool {
+        *self != StaticType::Any
+    }
+}
+
+impl TypeChecker {
+    pub fn new() -> TypeChecker {
+        let clock = Signature {
+            name: String::from("clock"),
+            params: Vec::new(),
+            return_type: StaticType::Number,
+            line: 0
+        };
+        TypeChecker {
+            scopes: Vec::from([
+                HashMap::from([(String::from("clock"), StaticType::Function(Some(Rc::new(clock))))]),
+            ]),
+            classes: HashMap::new(),
+            functions: Vec::new(),
+            named_types: Vec::new(),
+            errors: Vec::new()
+        }
+    }
+
+    // Walks the compiled program once and returns every mismatch, ordered by line.
+    pub fn check_program(mut self, stmts: &Vec<Box<dyn Statement>>) -> Vec<(u32, String)> {
+        for stmt in stmts {
+            stmt.check_types(&mut self);
+        }
+        for (name, line) in std::mem::take(&mut self.named_types) {
+            if !self.classes.contains_key(&name) {
+                self.report(line, format!("Unknown type '{}'.", name));
+            }
+        }
+        self.errors.sort_by_key(|(line, _)| *line);
+        self.errors
+    }
+
+    pub fn report(&mut self, line: u32, message: String) {
+        self.errors.push((line, message));
+    }
+
+    pub fn start_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    pub fn end_scope(&mut self) {
+        if self.scopes.len() > 1 {
+            self.scopes.pop();
+        }
+    }
+
+    pub fn declare(&mut self, name: &String, static_type: StaticType) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), static_type);
+        }
+    }
+
+    pub fn lookup(&self, name: &String) -> StaticType {
+        for scope in self.scopes.iter().rev() {
+            if let Some(static_type) = scope.get(name) {
+                return static_type.clone();
+            }
+        }
+        StaticType::Any
+    }
+
+    // Class names can be used before their declaration, so they are validated at the end.
+    pub fn annotation_used(&mut self, static_type: &StaticType, line: u32) {
+        if let StaticType::Instance(name) = static_type {
+            self.named_types.push((name.clone(), line));
+        }
+    }
+
+    pub fn accepts(&self, expected: &StaticType, actual: &StaticType) -> bool {
+        match (expected, actual) {
+            (StaticType::Any, _) | (_, StaticType::Any) => true,
+            (StaticType::Function(_), StaticType::Function(_)) => true,
+            (StaticType::Instance(expected), StaticType::Instance(actual)) => self.is_subclass(actual, expected),
+            _ => expected == actual
+        }
+    }
+
+    fn is_subclass(&self, class_name: &str, ancestor: &str) -> bool {
+        let mut current = Some(class_name.to_string());
+        while let Some(name) = current {
+            if name == *ancestor {
+                return true;
+            }
+            current = self.classes.get(&name).and_then(|class| class.super_class.clone());
+        }
+        false
+    }
+
+    pub fn define_class(&mut self, name: &String, class: ClassInfo) {
+        self.classes.insert(name.clone(), class);
+        self.declare(name, StaticType::Class(name.clone()));
+    }
+
+    pub fn field_type(&self, class_name: &String, field: &String) -> Option<StaticType> {
+        let class = self.classes.get(class_name)?;
+        match class.fields.get(field) {
+            Some(field_type) => Some(field_type.clone()),
+            None => self.field_type(class.super_class.as_ref()?, field)
+        }
+    }
+
+    pub fn method_signature(&self, class_name: &String, method: &String) -> Option<Rc<Signature>> {
+        let class = self.classes.get(class_name)?;
+        match class.methods.get(method) {
+            Some(signature) => Some(signature.clone()),
+            None => self.method_signature(class.super_class.as_ref()?, method)
+        }
+    }
+
+    fn constructor_signature(&self, class_name: &String) -> Option<Rc<Signature>> {
+        let class = self.classes.get(class_name)?;
+        match &class.constructor {
+            Some(signature) => Some(signature.clone()),
+            None => self.constructor_signature(class.super_class.as_ref()?)
+        }
+    }
+
+    pub fn member_type(&self, owner: &StaticType, member: &String) -> StaticType {
+        if let StaticType::Instance(class_name) = owner {
+            if let Some(field_type) = self.field_type(class_name, member) {
+                return field_type;
+            }
+            if let Some(signature) = self.method_signature(class_name, member) {
+                return StaticType::Function(Some(signature));
+            }
+        }
+        StaticType::Any
+    }
+
+    pub fn check_function(&mut self, params: &[String], signature: &Rc<Signature>,
+        statements: &Vec<Box<dyn Statement>>, this: Option<StaticType>) {
+        for param_type in signature.params.iter().chain([&signature.return_type]) {
+            self.annotation_used(param_type, signature.line);
+        }
+        self.start_scope();
+        if let Some(this_type) = this {
+            self.declare(&String::from("this"), this_type);
+        }
+        for (param, param_type) in params.iter().zip(signature.params.iter()) {
+            self.declare(param, param_type.clone());
+        }
+        self.functions.push(signature.clone());
+        for stmt in statements {
+            stmt.check_types(self);
+        }
+        self.functions.pop();
+        self.end_scope();
+    }
+
+    pub fn check_return(&mut self, actual: &StaticType, line: u32) {
+        let Some(signature) = self.functions.last().cloned() else {
+            return;
+        };
+        if signature.name != "init" && !self.accepts(&signature.return_type, actual) {
+            self.report(line, format!("Type mismatch: '{}' must return {} but got {}.",
+                signature.name, signature.return_type.name(), actual.name()));
+        }
+    }
+
+    pub fn check_call(&mut self, callee: &StaticType, args: &[Box<dyn Expression>], line: u32) -> StaticType {
+        let arg_types: Vec<StaticType> = args.iter().map(|arg| arg.static_type(self)).collect();
+        match callee {
+            StaticType::Function(Some(signature)) => {
+                self.check_arguments(signature, &arg_types, line);
+                signature.return_type.clone()
+            },
+            StaticType::Class(class_name) => {
+                match self.constructor_signature(class_name) {
+                    Some(signature) => self.check_arguments(&signature, &arg_types, line),
+                    None if !arg_types.is_empty() => {
+                        self.report(line, format!("Expected 0 arguments but got {}", arg_types.len()));
+                    },
+                    None => {}
+                }
+                StaticType::Instance(class_name.clone())
+            },
+            StaticType::Any | StaticType::Function(None) => StaticType::Any,
+            other => {
+                self.report(line, format!("Can only call functions and classes, not {}.", other.name()));
+                StaticType::Any
+            }
+        }
+    }
+
+    fn check_arguments(&mut self, signature: &Signature, arg_types: &[StaticType], line: u32) {
+        if signature.params.len() != arg_types.len() {
+            self.report(line, format!("Expected {} arguments but got {}", signature.params.len(), arg_types.len()));
+            return;
+        }
+        for (position, (expected, actual)) in signature.params.iter().zip(arg_types).enumerate() {
+            if !self.accepts(expected, actual) {
+                self.report(line, format!("Type mismatch: argument {} of '{}' expects {} but got {}.",
+                    position + 1, signature.name, expected.name(), actual.name()));
+            }
+        }
+    }
+
+    pub fn check_operands(&mut self, left: &StaticType, right: &StaticType, line: u32) {
+        let not_number = |operand: &StaticType| operand.is_known() && *operand != StaticType::Number;
+        if not_number(left) || not_number(right) {
+            self.report(line, String::from("Operands must be numbers."));
+        }
+    }
+}
+
+pub fn check(stmts: &Vec<Box<dyn Statement>>) -> Vec<LoxError> {
+    TypeChecker::new().check_program(stmts).into_iter()
+        .map(|(line, message)| LoxError::new(line, ErrorType::TypeError, &message))
+        .collect()
+}
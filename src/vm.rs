@@ -0,0 +1,1251 @@
+use std::{cell::RefCell, collections::HashMap, mem, path::{Path, PathBuf}, rc::Rc};
+
+use crate::enums::Enum;
+use crate::error_handler::{handle_error, CallFrame, ErrorType, LoxError, LoxResult, Span};
+use crate::function::clock;
+use crate::interpreter::block_scopes::{max_call_depth, BlockScopes};
+use crate::interpreter::utils::{make_range, resolve_index};
+use crate::interpreter::{Interpreter, MAX_NATIVE_DEPTH};
+use crate::iterator::LoxIterator;
+use crate::module::{load, module_key, module_name, resolve_path};
+use crate::native::map_methods::map_key;
+use crate::native::{fiber_methods, gc_methods, has_native_properties, native_property, Host};
+use crate::parser::declarations::{self, List, Map, Type};
+use crate::statements::Statement;
+use crate::vm::chunk::{Chunk, OpCode};
+use crate::vm::coroutine::{Run, RunKind, Scheduler, Suspension};
+use crate::vm::emitter::{Emitter, Unsupported};
+use crate::vm::value::{instance_property, FunctionKind, NameMap, Native, ObjClass, ObjClosure, ObjFunction, ObjInstance,
+    ObjIterator, ObjModule, Upvalue, UpvalueRef, Value};
+pub mod chunk;
+pub mod coroutine;
+pub mod emitter;
+pub mod value;
+
+// What a frame hands back to its caller: the returned value, or for an `__eq__`
+// method whether its result is as truthy as the operator expects. An `Exit`
+// frame returns out of the run that called it, a `Finish` frame is the body
+// of a generator or fiber, which returns to whoever resumed it.
+#[derive(Clone, Copy)]
+enum ReturnAction {
+    Value,
+    Truthy(bool),
+    Exit,
+    Finish
+}
+
+// A `try` the frame is in: where its catch or finally starts and the stack
+// height, relative to the frame, to unwind to.
+#[derive(Clone, Copy)]
+struct Handler {
+    kind: HandlerKind,
+    ip: usize,
+    stack: usize,
+    completions: usize
+}
+
+#[derive(Clone, Copy)]
+enum HandlerKind {
+    // The global naming the class errors are made into.
+    Catch(u16),
+    Finally
+}
+
+// What the `finally` being run carries on with once it is over.
+enum Completion {
+    Normal,
+    Return(Value),
+    Throw(LoxError)
+}
+
+struct Frame {
+    closure: Rc<ObjClosure>,
+    ip: usize,
+    // Stack index of slot 0: the callee, or `this` in a method.
+    base: usize,
+    name: Rc<String>,
+    line: u32,
+    elided: usize,
+    on_return: ReturnAction,
+    // Made by the first `try` or forward capture, which most calls have none of.
+    extra: Option<Box<FrameExtra>>
+}
+
+#[derive(Default)]
+struct FrameExtra {
+    handlers: Vec<Handler>,
+    completions: Vec<Completion>,
+    // Forward captures made by closures created here, until their variable is declared.
+    forwards: Vec<(u16, Rc<RefCell<Upvalue>>)>
+}
+
+pub struct Vm {
+    stack: Vec<Value>,
+    // Callers of the running frame, which the run loop holds itself.
+    frames: Vec<Frame>,
+    // Undefined globals are None; the emitter's `global_names` names them for errors.
+    globals: Vec<Option<Value>>,
+    constants: Vec<bool>,
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    // Native methods take the tree-walker's state, which they don't use for the values the VM passes.
+    native_state: BlockScopes,
+    // Modules are compiled when they are imported, into globals of their own.
+    emitter: Emitter,
+    prelude: Vec<Box<dyn Statement>>,
+    modules: HashMap<PathBuf, Rc<ObjModule>>,
+    runs: Vec<Run>,
+    // What the last `yield` or blocked fiber left, see `run_coroutine`.
+    suspended: Option<(Value, Suspension)>,
+    scheduler: Scheduler,
+    // Stands in for a frame moved onto `frames` while native code runs.
+    placeholder: Rc<ObjClosure>
+}
+
+// Runs the script on the bytecode VM. Nothing runs when the script uses a feature
+// only the tree-walker has: that is an error, or with `fall_back` a false return.
+pub fn run(stmts: &[Box<dyn Statement>], fall_back: bool) -> LoxResult<bool> {
+    let prelude = Interpreter::compile_prelude()?;
+    let mut emitter = Emitter::new();
+    let compiled = emitter.script(&prelude).and_then(|prelude| Ok((prelude, emitter.script(stmts)?)));
+    let (prelude_function, script) = match compiled {
+        Ok(compiled) => compiled,
+        Err(_) if fall_back => return Ok(false),
+        Err(unsupported) => return Err(unsupported_error(unsupported))
+    };
+    let mut vm = Vm::new(emitter, prelude);
+    vm.call_script(prelude_function)
+        .and_then(|_| vm.call_script(script))
+        .and_then(|_| vm.run_fibers())
+        .map_err(uncaught)?;
+    Ok(true)
+}
+
+fn unsupported_error(unsupported: Unsupported) -> LoxError {
+    LoxError::new(unsupported.line, ErrorType::SyntacticError,
+        &format!("Error: The VM backend doesn't support {}; run with --backend tree or auto.", unsupported.construct))
+}
+
+// An instance thrown out of the program is reported by its message and line, as the tree-walker does.
+fn uncaught(mut error: LoxError) -> LoxError {
+    let Some(Value::Instance(instance)) = error.thrown.as_ref().map(|thrown| Value::from_value(&thrown.0)) else {
+        return error;
+    };
+    let fields = instance.fields.borrow();
+    match fields.get("message") {
+        Some(message) => {
+            error.message = message.to_string();
+            if let Some(Value::Number(line)) = fields.get("line") {
+                error.span.line = *line as u32;
+            }
+        },
+        None => error.message = Value::Instance(instance.clone()).to_string()
+    }
+    drop(fields);
+    error.thrown = None;
+    error
+}
+
+impl Frame {
+    fn new(closure: Rc<ObjClosure>, base: usize, name: Rc<String>, line: u32, on_return: ReturnAction) -> Frame {
+        Frame { closure, ip: 0, base, name, line, elided: 0, on_return, extra: None }
+    }
+
+    fn extra(&mut self) -> &mut FrameExtra {
+        self.extra.get_or_insert_with(Box::default)
+    }
+}
+
+impl ReturnAction {
+    fn apply(self, result: Value) -> Value {
+        match self {
+            ReturnAction::Truthy(check) => Value::Bool(result.is_truthy() == check),
+            _ => result
+        }
+    }
+}
+
+impl Vm {
+    pub fn new(emitter: Emitter, prelude: Vec<Box<dyn Statement>>) -> Vm {
+        let mut native_state = BlockScopes::new();
+        fiber_methods::define_globals(&mut native_state);
+        gc_methods::define_globals(&mut native_state);
+        let placeholder = ObjFunction {
+            name: Rc::new(String::new()),
+            arity: 0,
+            chunk: Chunk::default(),
+            upvalues: Vec::new(),
+            kind: FunctionKind::Script,
+            file: None,
+            generator: false
+        };
+        let mut vm = Vm {
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals: Vec::new(),
+            constants: Vec::new(),
+            open_upvalues: Vec::new(),
+            native_state,
+            emitter,
+            prelude,
+            modules: HashMap::new(),
+            runs: Vec::new(),
+            suspended: None,
+            scheduler: Scheduler::new(),
+            placeholder: Rc::new(ObjClosure::new(Rc::new(placeholder)))
+        };
+        vm.sync_globals();
+        vm
+    }
+
+    // Gives the globals compiled since the last call their slots, the builtins their values.
+    fn sync_globals(&mut self) {
+        for index in self.globals.len()..self.emitter.global_names().len() {
+            let name = self.emitter.global_names()[index].clone();
+            let value = self.builtin(&name);
+            self.globals.push(value);
+            self.constants.push(false);
+        }
+    }
+
+    fn builtin(&self, name: &str) -> Option<Value> {
+        match name {
+            "clock" => Some(Value::Native(Rc::new(Native::Clock))),
+            "spawn" => Some(Value::Native(Rc::new(Native::Spawn))),
+            "Channel" | "gc" => self.native_state.global(name).map(|value| Value::from_value(&value)),
+            _ => None
+        }
+    }
+
+    fn call_script(&mut self, function: Rc<ObjFunction>) -> LoxResult<()> {
+        let closure = Rc::new(ObjClosure::new(function));
+        let base = self.stack.len();
+        self.stack.push(Value::Closure(closure.clone()));
+        let name = closure.function.name.clone();
+        self.run(Frame::new(closure, base, name, 0, ReturnAction::Exit))?;
+        Ok(())
+    }
+
+    fn placeholder_frame(&self) -> Frame {
+        Frame::new(self.placeholder.clone(), 0, self.placeholder.function.name.clone(), 0, ReturnAction::Exit)
+    }
+
+    // Runs `frame` and the calls it makes until it returns. An error unwinds to
+    // the innermost `try` around it, a suspension leaves the frames to the coroutine.
+    fn run(&mut self, mut frame: Frame) -> LoxResult<Value> {
+        loop {
+            match self.execute(&mut frame) {
+                Ok(value) => return Ok(value),
+                Err(error) if error.kind == ErrorType::Suspended => return Err(error),
+                Err(error) => self.unwind(&mut frame, error)?
+            }
+        }
+    }
+
+    fn unwind(&mut self, frame: &mut Frame, mut error: LoxError) -> LoxResult<()> {
+        loop {
+            let handler = frame.extra.as_mut().and_then(|extra| extra.handlers.pop());
+            if let Some(handler) = handler.filter(|_| error.is_catchable()) {
+                self.close_upvalues(frame.base + handler.stack);
+                self.stack.truncate(frame.base + handler.stack);
+                frame.extra().completions.truncate(handler.completions);
+                frame.ip = handler.ip;
+                match handler.kind {
+                    HandlerKind::Catch(class) => match self.error_value(frame, error, class) {
+                        Ok(value) => {
+                            self.stack.push(value);
+                            return Ok(());
+                        },
+                        Err(raised) => {
+                            error = raised;
+                            continue;
+                        }
+                    },
+                    HandlerKind::Finally => {
+                        frame.extra().completions.push(Completion::Throw(error));
+                        return Ok(());
+                    }
+                }
+            }
+            self.close_upvalues(frame.base);
+            self.stack.truncate(frame.base);
+            match frame.on_return {
+                ReturnAction::Exit | ReturnAction::Finish => return Err(error),
+                _ => *frame = self.frames.pop().unwrap()
+            }
+        }
+    }
+
+    // The value a catch gets: what was thrown, or an instance of the `Error` class.
+    fn error_value(&mut self, frame: &mut Frame, error: LoxError, class: u16) -> LoxResult<Value> {
+        if let Some(thrown) = &error.thrown {
+            return Ok(Value::from_value(&thrown.0));
+        }
+        let line = error.span.line;
+        let message = Value::Str(error.message.as_str().into());
+        match self.globals[class as usize].clone() {
+            Some(class @ Value::Class(_)) => {
+                let value = self.with_frame(frame, |vm| vm.call_value(class, Vec::from([message]), line))?;
+                if let Value::Instance(instance) = &value {
+                    instance.fields.borrow_mut().insert("line".into(), Value::Number(line as f64));
+                }
+                Ok(value)
+            },
+            _ => Ok(message)
+        }
+    }
+
+    // Hands `result` to the caller, unless a `finally` has to run first. Gives
+    // the result of a frame that returns out of the run.
+    fn return_from(&mut self, frame: &mut Frame, result: Value) -> Option<Value> {
+        let finally = frame.extra.as_ref().and_then(|extra| {
+            extra.handlers.iter().rposition(|handler| matches!(handler.kind, HandlerKind::Finally))
+        });
+        if let Some(position) = finally {
+            let extra = frame.extra();
+            let handler = extra.handlers[position];
+            extra.handlers.truncate(position);
+            extra.completions.truncate(handler.completions);
+            extra.completions.push(Completion::Return(result));
+            self.close_upvalues(frame.base + handler.stack);
+            self.stack.truncate(frame.base + handler.stack);
+            frame.ip = handler.ip;
+            return None;
+        }
+        self.close_upvalues(frame.base);
+        self.stack.truncate(frame.base);
+        match frame.on_return {
+            ReturnAction::Exit | ReturnAction::Finish => Some(result),
+            on_return => {
+                *frame = self.frames.pop().unwrap();
+                self.stack.push(on_return.apply(result));
+                None
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn execute(&mut self, frame: &mut Frame) -> LoxResult<Value> {
+        loop {
+            let op = frame.closure.function.chunk.code[frame.ip];
+            frame.ip += 1;
+            match op {
+                OpCode::Constant(index) => {
+                    let value = frame.closure.function.chunk.constants[index as usize].clone();
+                    self.stack.push(value);
+                },
+                OpCode::Nil => self.stack.push(Value::Nil),
+                OpCode::True => self.stack.push(Value::Bool(true)),
+                OpCode::False => self.stack.push(Value::Bool(false)),
+                OpCode::Pop => {
+                    self.stack.pop();
+                },
+                OpCode::GetLocal(slot) => {
+                    let value = self.stack[frame.base + slot as usize].clone();
+                    self.stack.push(value);
+                },
+                OpCode::SetLocal(slot) => {
+                    let value = self.peek(0).clone();
+                    self.stack[frame.base + slot as usize] = value;
+                },
+                OpCode::GetUpvalue(index) => {
+                    let value = match &*frame.closure.upvalues[index as usize].borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                        Upvalue::Global(global) => match &self.globals[*global as usize] {
+                            Some(value) => value.clone(),
+                            None => return Err(self.undefined(frame, *global))
+                        }
+                    };
+                    self.stack.push(value);
+                },
+                OpCode::SetUpvalue(index) => {
+                    let value = self.peek(0).clone();
+                    let upvalue = frame.closure.upvalues[index as usize].clone();
+                    match &mut *upvalue.borrow_mut() {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                        Upvalue::Global(global) => self.set_global(frame, *global)?
+                    };
+                },
+                OpCode::GetGlobal(index) => match &self.globals[index as usize] {
+                    Some(value) => self.stack.push(value.clone()),
+                    None => return Err(self.undefined(frame, index))
+                },
+                OpCode::GetGlobalOrNil(index) => {
+                    let value = self.globals[index as usize].clone().unwrap_or(Value::Nil);
+                    self.stack.push(value);
+                },
+                OpCode::SetGlobal(index) => self.set_global(frame, index)?,
+                OpCode::DefineGlobal(index) => {
+                    self.globals[index as usize] = self.stack.pop();
+                },
+                OpCode::DefineConstant(index) => {
+                    self.constants[index as usize] = true;
+                    self.globals[index as usize] = self.stack.pop();
+                },
+                OpCode::GetProperty(index) => {
+                    let name = Self::name(frame, index);
+                    let object = self.stack.pop().unwrap();
+                    let value = self.property(frame, &object, &name)?;
+                    self.stack.push(value);
+                },
+                OpCode::SetProperty(index) => {
+                    let value = self.stack.pop().unwrap();
+                    let Value::Instance(instance) = self.stack.pop().unwrap() else {
+                        return Err(self.error(frame, "Can only access property on class instance"));
+                    };
+                    instance.fields.borrow_mut().insert(Self::name(frame, index), value.clone());
+                    self.stack.push(value);
+                },
+                OpCode::GetSuper(property, method) => {
+                    let superclass = self.stack.pop().unwrap();
+                    let this = self.stack.pop().unwrap();
+                    let (Value::Instance(instance), Value::Class(superclass)) = (this, superclass) else {
+                        unreachable!("super outside of a method");
+                    };
+                    let parent = instance.supers.borrow_mut().entry(Self::name(frame, method))
+                        .or_insert_with(|| ObjInstance::new(superclass))
+                        .clone();
+                    let value = self.property(frame, &Value::Instance(parent), &Self::name(frame, property))?;
+                    self.stack.push(value);
+                },
+                OpCode::Equal => self.equality(frame, true)?,
+                OpCode::NotEqual => self.equality(frame, false)?,
+                OpCode::Greater => self.comparison(frame, "__gt__", |num1, num2| num1 > num2)?,
+                OpCode::GreaterEqual => self.comparison(frame, "__ge__", |num1, num2| num1 >= num2)?,
+                OpCode::Less => self.comparison(frame, "__lt__", |num1, num2| num1 < num2)?,
+                OpCode::LessEqual => self.comparison(frame, "__le__", |num1, num2| num1 <= num2)?,
+                OpCode::Add => {
+                    let value2 = self.stack.pop().unwrap();
+                    let value1 = self.stack.pop().unwrap();
+                    match (&value1, &value2) {
+                        (Value::Number(num1), Value::Number(num2)) => self.stack.push(Value::Number(num1 + num2)),
+                        (Value::Str(str1), Value::Str(str2)) => self.stack.push(Value::Str(format!("{}{}", str1, str2).into())),
+                        _ => self.operator(frame, value1, "__add__", Some(value2), "Operands must be two numbers or two strings.")?
+                    }
+                },
+                OpCode::Subtract => self.arithmetic(frame, "__sub__", |num1, num2| num1 - num2)?,
+                OpCode::Multiply => self.arithmetic(frame, "__mul__", |num1, num2| num1 * num2)?,
+                OpCode::Divide => self.arithmetic(frame, "__div__", |num1, num2| num1 / num2)?,
+                OpCode::Not => {
+                    let value = self.stack.pop().unwrap();
+                    self.stack.push(Value::Bool(!value.is_truthy()));
+                },
+                OpCode::Negate => match self.stack.pop().unwrap() {
+                    Value::Number(num) => self.stack.push(Value::Number(-num)),
+                    value => self.operator(frame, value, "__neg__", None, "Operand must be a number.")?
+                },
+                OpCode::Print => println!("{}", self.stack.pop().unwrap()),
+                OpCode::Jump(target) => frame.ip = target as usize,
+                OpCode::JumpIfFalse(target) => {
+                    if !self.stack.pop().unwrap().is_truthy() {
+                        frame.ip = target as usize;
+                    }
+                },
+                OpCode::And(target) => {
+                    if !self.peek(0).is_truthy() {
+                        frame.ip = target as usize;
+                    } else {
+                        self.stack.pop();
+                    }
+                },
+                OpCode::Or(target) => {
+                    if self.peek(0).is_truthy() {
+                        frame.ip = target as usize;
+                    } else {
+                        self.stack.pop();
+                    }
+                },
+                OpCode::NilCoalesce(target) => {
+                    if !matches!(self.peek(0), Value::Nil) {
+                        frame.ip = target as usize;
+                    } else {
+                        self.stack.pop();
+                    }
+                },
+                OpCode::JumpIfNil(target) => {
+                    if matches!(self.peek(0), Value::Nil) {
+                        frame.ip = target as usize;
+                    }
+                },
+                OpCode::CheckCall(argc) => self.check_call(frame, argc as usize)?,
+                OpCode::Call(argc) => {
+                    let line = Self::line(frame);
+                    self.call(frame, argc as usize, line, ReturnAction::Value)?;
+                },
+                OpCode::TailCall(argc) => self.tail_call(frame, argc as usize)?,
+                OpCode::Closure(index) => {
+                    let Value::Function(function) = &frame.closure.function.chunk.constants[index as usize] else {
+                        unreachable!("closure over a constant that isn't a function");
+                    };
+                    let upvalues = function.upvalues.iter().map(|upvalue| match *upvalue {
+                        UpvalueRef::Local(slot) => self.capture_upvalue(frame.base + slot as usize),
+                        UpvalueRef::Upvalue(index) => frame.closure.upvalues[index as usize].clone(),
+                        UpvalueRef::Forward(id) => {
+                            let upvalue = Rc::new(RefCell::new(Upvalue::Closed(Value::Nil)));
+                            frame.extra.get_or_insert_with(Box::default).forwards.push((id, upvalue.clone()));
+                            upvalue
+                        },
+                        UpvalueRef::Global(global) => Rc::new(RefCell::new(Upvalue::Global(global)))
+                    }).collect();
+                    self.stack.push(Value::Closure(Rc::new(ObjClosure { function: function.clone(), upvalues })));
+                },
+                OpCode::BindForward(id, slot) => {
+                    let slot = frame.base + slot as usize;
+                    let open_upvalues = &mut self.open_upvalues;
+                    frame.extra().forwards.retain(|(forward, upvalue)| {
+                        if *forward != id {
+                            return true;
+                        }
+                        upvalue.replace(Upvalue::Open(slot));
+                        open_upvalues.push(upvalue.clone());
+                        false
+                    });
+                },
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.stack.pop();
+                },
+                OpCode::Return => {
+                    let result = self.stack.pop().unwrap();
+                    if let Some(result) = self.return_from(frame, result) {
+                        return Ok(result);
+                    }
+                },
+                OpCode::Class(index) => {
+                    let name = Rc::new(Self::name(frame, index).to_string());
+                    self.stack.push(Value::Class(Rc::new(ObjClass { name, methods: RefCell::new(NameMap::default()) })));
+                },
+                OpCode::Inherit => {
+                    let Value::Class(superclass) = self.peek(1) else {
+                        return Err(self.error(frame, "Superclass must be a class."));
+                    };
+                    let Value::Class(class) = self.peek(0) else {
+                        unreachable!("inheriting into a value that isn't a class");
+                    };
+                    let methods = superclass.methods.borrow().clone();
+                    class.methods.borrow_mut().extend(methods);
+                    self.stack.pop();
+                },
+                OpCode::Method(index) => {
+                    let Some(Value::Closure(method)) = self.stack.pop() else {
+                        unreachable!("method that isn't a closure");
+                    };
+                    let Value::Class(class) = self.peek(0) else {
+                        unreachable!("method outside of a class");
+                    };
+                    class.methods.borrow_mut().insert(Self::name(frame, index), method);
+                },
+                OpCode::List(count) => {
+                    let items = self.stack.split_off(self.stack.len() - count as usize);
+                    let list = List::new(items.iter().map(Value::to_value).collect());
+                    self.stack.push(Value::Object(Rc::new(list)));
+                },
+                OpCode::MapNew => self.stack.push(Value::Object(Rc::new(Map::new()))),
+                OpCode::MapInsert => {
+                    let value = self.stack.pop().unwrap();
+                    let key = self.stack.pop().unwrap();
+                    let line = Self::line(frame);
+                    let key = map_key(&key.to_value(), &line).map_err(|error| self.native_error(frame, error))?;
+                    let Value::Object(map) = self.peek(0) else {
+                        unreachable!("entry inserted into a value that isn't a map");
+                    };
+                    map.as_map().unwrap().set(key, value.to_value());
+                },
+                OpCode::GetIndex => {
+                    let index = self.stack.pop().unwrap();
+                    let collection = self.stack.pop().unwrap();
+                    if let Value::Instance(_) = collection {
+                        self.overloaded_index(frame, collection, "__index__", [index])?;
+                        continue;
+                    }
+                    let line = Self::line(frame);
+                    let value = Self::index(&collection, &index, line).map_err(|error| self.native_error(frame, error))?;
+                    self.stack.push(value);
+                },
+                OpCode::CheckIndex => {
+                    let line = Self::line(frame);
+                    Self::check_index(self.peek(1), self.peek(0), line).map_err(|error| self.native_error(frame, error))?;
+                },
+                OpCode::SetIndex => {
+                    let value = self.stack.pop().unwrap();
+                    let index = self.stack.pop().unwrap();
+                    let collection = self.stack.pop().unwrap();
+                    if let Value::Instance(_) = collection {
+                        self.overloaded_index(frame, collection, "__setindex__", [index, value])?;
+                        continue;
+                    }
+                    let line = Self::line(frame);
+                    Self::set_index(&collection, &index, &value, line).map_err(|error| self.native_error(frame, error))?;
+                    self.stack.push(value);
+                },
+                OpCode::Range(inclusive) => {
+                    let value2 = self.stack.pop().unwrap();
+                    let value1 = self.stack.pop().unwrap();
+                    let line = Self::line(frame);
+                    let range = make_range(value1.to_value(), value2.to_value(), inclusive, &line)
+                        .map_err(|error| self.native_error(frame, error))?;
+                    self.stack.push(Value::from_value(&range));
+                },
+                OpCode::Iterate => {
+                    let iterable = self.stack.pop().unwrap();
+                    let line = Self::line(frame);
+                    let iterator = self.iterator(frame, iterable, line).map_err(|error| self.native_error(frame, error))?;
+                    self.stack.push(Value::Iterator(iterator));
+                },
+                OpCode::IterNext(target) => {
+                    let Some(Value::Iterator(iterator)) = self.stack.pop() else {
+                        unreachable!("for-in loop over a value that isn't an iterator");
+                    };
+                    let line = Self::line(frame);
+                    match self.next_value(frame, &iterator, line)? {
+                        Some(value) => self.stack.push(value),
+                        None => frame.ip = target as usize
+                    }
+                },
+                OpCode::Enum(index) => {
+                    let Value::Object(template) = &frame.closure.function.chunk.constants[index as usize] else {
+                        unreachable!("enum declared from a constant that isn't an enum");
+                    };
+                    let template = template.as_enum().unwrap();
+                    let variants: Vec<String> = template.variants.iter().map(|variant| variant.0.name.clone()).collect();
+                    self.stack.push(Value::Object(Rc::new(Enum::new(&template.name, &variants))));
+                },
+                OpCode::Try(target, class) => self.push_handler(frame, HandlerKind::Catch(class), target),
+                OpCode::TryFinally(target) => self.push_handler(frame, HandlerKind::Finally, target),
+                OpCode::EndTry => {
+                    frame.extra().handlers.pop();
+                },
+                OpCode::Finally => {
+                    let extra = frame.extra();
+                    extra.handlers.pop();
+                    extra.completions.push(Completion::Normal);
+                },
+                OpCode::EndFinally => match frame.extra().completions.pop().unwrap() {
+                    Completion::Normal => (),
+                    Completion::Return(result) => {
+                        if let Some(result) = self.return_from(frame, result) {
+                            return Ok(result);
+                        }
+                    },
+                    Completion::Throw(error) => return Err(error)
+                },
+                OpCode::Throw => {
+                    let value = self.stack.pop().unwrap();
+                    let line = Self::line(frame);
+                    if let Value::Instance(instance) = &value {
+                        if let Some(field @ Value::Nil) = instance.fields.borrow_mut().get_mut("line") {
+                            *field = Value::Number(line as f64);
+                        }
+                    }
+                    let mut error = LoxError::thrown(value.to_value(), line);
+                    error.trace = self.traceback(frame);
+                    error.span.file = Self::file(frame);
+                    return Err(error);
+                },
+                OpCode::Yield => {
+                    let value = self.stack.pop().unwrap();
+                    let body = self.suspend(frame);
+                    self.suspended = Some((value, body));
+                    return Err(LoxError::suspended());
+                },
+                OpCode::FiberYield => {
+                    if self.can_suspend() {
+                        return Err(self.suspend_fiber(frame));
+                    }
+                    self.with_frame(frame, |vm| vm.yield_in_place())?;
+                },
+                OpCode::Import(path, base_dir) => {
+                    let (path, base_dir) = (Self::name(frame, path), Self::name(frame, base_dir));
+                    let line = Self::line(frame);
+                    let module = self.with_frame(frame, |vm| vm.import(&path, &base_dir, line)).map_err(|mut error| {
+                        if error.kind == ErrorType::RuntimeError && error.trace.is_empty() && error.fiber.is_none() {
+                            error.trace = self.traceback(frame);
+                        }
+                        error
+                    })?;
+                    self.stack.push(Value::Module(module));
+                },
+                OpCode::ModuleMember(name, path) => {
+                    let Some(Value::Module(module)) = self.stack.pop() else {
+                        unreachable!("member of a value that isn't a module");
+                    };
+                    let name = Self::name(frame, name);
+                    match module.members.get(&name).and_then(|index| self.globals[*index as usize].clone()) {
+                        Some(value) => self.stack.push(value),
+                        None => {
+                            let message = format!("Module '{}' has no member '{}'.", Self::name(frame, path), name);
+                            return Err(self.error(frame, &message));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn set_global(&mut self, frame: &Frame, index: u16) -> LoxResult<()> {
+        if self.globals[index as usize].is_none() {
+            return Err(self.undefined(frame, index));
+        }
+        if self.constants[index as usize] {
+            let message = format!("Cannot assign to constant '{}'.", self.emitter.global_names()[index as usize]);
+            return Err(self.error(frame, &message));
+        }
+        self.globals[index as usize] = Some(self.peek(0).clone());
+        Ok(())
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn name(frame: &Frame, index: u16) -> Rc<str> {
+        match &frame.closure.function.chunk.constants[index as usize] {
+            Value::Str(name) => name.clone(),
+            _ => unreachable!("name constant that isn't a string")
+        }
+    }
+
+    fn line(frame: &Frame) -> u32 {
+        frame.closure.function.chunk.lines[frame.ip - 1]
+    }
+
+    fn file(frame: &Frame) -> Option<String> {
+        frame.closure.function.file.as_ref().map(|file| file.to_string())
+    }
+
+    // A runtime error at the running instruction, with the calls active there innermost first.
+    fn error(&self, frame: &Frame, message: &str) -> LoxError {
+        self.error_at(frame, Self::line(frame), message)
+    }
+
+    fn error_at(&self, frame: &Frame, line: u32, message: &str) -> LoxError {
+        let mut error = LoxError::new(line, ErrorType::RuntimeError, message);
+        error.trace = self.traceback(frame);
+        error.span.file = Self::file(frame);
+        error
+    }
+
+    // An error raised outside the run loop gets the calls it leaves, as the
+    // innermost call of the tree-walker records them.
+    fn native_error(&self, frame: &Frame, mut error: LoxError) -> LoxError {
+        if error.kind == ErrorType::RuntimeError && error.trace.is_empty() && error.fiber.is_none() {
+            error.trace = self.traceback(frame);
+            error.span.file = Self::file(frame);
+        }
+        error
+    }
+
+    fn undefined(&self, frame: &Frame, index: u16) -> LoxError {
+        self.error(frame, &format!("Undefined variable '{}'.", self.emitter.global_names()[index as usize]))
+    }
+
+    // A module or fiber starts a traceback of its own. Each call is reported
+    // in the file of its caller; script bodies and coroutine bodies aren't calls.
+    fn traceback(&self, frame: &Frame) -> Box<[CallFrame]> {
+        let start = self.runs.iter().rev()
+            .find(|run| matches!(run.kind, RunKind::Fiber | RunKind::Module))
+            .map_or(0, |run| run.barrier);
+        let frames: Vec<&Frame> = self.frames[start..].iter().chain([frame]).collect();
+        frames.iter().enumerate().rev()
+            .filter(|(_, frame)| frame.closure.function.kind != FunctionKind::Script && !matches!(frame.on_return, ReturnAction::Finish))
+            .map(|(index, frame)| {
+                let file = index.checked_sub(1).and_then(|caller| Self::file(frames[caller]));
+                CallFrame { name: frame.name.to_string(), span: Span { line: frame.line, file }, elided: frame.elided }
+            })
+            .collect()
+    }
+
+    // Runs `f` with `frame` on top of the callers, for native code calling back into Lox.
+    fn with_frame<T>(&mut self, frame: &mut Frame, f: impl FnOnce(&mut Vm) -> T) -> T {
+        let placeholder = self.placeholder_frame();
+        self.frames.push(mem::replace(frame, placeholder));
+        let result = f(self);
+        *frame = self.frames.pop().unwrap();
+        result
+    }
+
+    fn push_handler(&mut self, frame: &mut Frame, kind: HandlerKind, target: u32) {
+        let stack = self.stack.len() - frame.base;
+        let extra = frame.extra();
+        extra.handlers.push(Handler { kind, ip: target as usize, stack, completions: extra.completions.len() });
+    }
+
+    fn property(&self, frame: &Frame, object: &Value, name: &str) -> LoxResult<Value> {
+        let undefined = || self.error(frame, &format!("Undefined property '{}'", name));
+        match object {
+            Value::Instance(instance) => instance_property(instance, name).ok_or_else(undefined),
+            Value::Str(_) | Value::Object(_) => {
+                let receiver = object.to_value();
+                match native_property(&receiver, name) {
+                    // Receiving may suspend the fiber, which the VM does itself.
+                    Some(_) if receiver.get_type() == Type::CHANNEL && name == "receive" => {
+                        let channel = receiver.as_channel().unwrap().clone();
+                        Ok(Value::Native(Rc::new(Native::Receive(channel))))
+                    },
+                    Some(property) => Ok(Value::from_value(&property)),
+                    None if has_native_properties(&receiver) => Err(undefined()),
+                    None => Err(self.error(frame, "Can only access property on class instance"))
+                }
+            },
+            Value::Generator(generator) => match name {
+                "next" => Ok(Value::Native(Rc::new(Native::Next(generator.clone())))),
+                "done" => Ok(Value::Bool(generator.is_done())),
+                _ => Err(undefined())
+            },
+            Value::Fiber(fiber) => match name {
+                "done" => Ok(Value::Bool(fiber.is_done())),
+                _ => Err(undefined())
+            },
+            Value::Module(module) => module.members.get(name)
+                .and_then(|index| self.globals[*index as usize].clone())
+                .ok_or_else(undefined),
+            _ => Err(self.error(frame, "Can only access property on class instance"))
+        }
+    }
+
+    fn index(collection: &Value, index: &Value, line: u32) -> LoxResult<Value> {
+        match collection {
+            Value::Object(object) if object.get_type() == Type::LIST => {
+                let list = object.as_list().unwrap();
+                let position = resolve_index(&index.to_value(), list.len(), &line)?;
+                Ok(Value::from_value(&list.get(position)))
+            },
+            Value::Object(object) if object.get_type() == Type::MAP => {
+                let key = index.to_value();
+                match object.as_map().unwrap().get(&map_key(&key, &line)?) {
+                    Some(value) => Ok(Value::from_value(&value)),
+                    None => handle_error(&line, ErrorType::RuntimeError, format!("Undefined key '{}'.", key.to_str()).as_str())
+                }
+            },
+            Value::Str(string) => {
+                let chars: Vec<char> = string.chars().collect();
+                let position = resolve_index(&index.to_value(), chars.len(), &line)?;
+                Ok(Value::Str(chars[position].to_string().into()))
+            },
+            _ => handle_error(&line, ErrorType::RuntimeError, "Can only index lists, maps and strings.")
+        }
+    }
+
+    // An assignment to an element fails on a bad index before its value is evaluated.
+    fn check_index(collection: &Value, index: &Value, line: u32) -> LoxResult<()> {
+        match collection {
+            Value::Object(object) if object.get_type() == Type::LIST => {
+                resolve_index(&index.to_value(), object.as_list().unwrap().len(), &line).map(|_| ())
+            },
+            Value::Object(object) if object.get_type() == Type::MAP => map_key(&index.to_value(), &line).map(|_| ()),
+            Value::Str(_) => handle_error(&line, ErrorType::RuntimeError, "Strings are immutable."),
+            Value::Instance(_) => Ok(()),
+            _ => handle_error(&line, ErrorType::RuntimeError, "Can only index lists, maps and strings.")
+        }
+    }
+
+    fn set_index(collection: &Value, index: &Value, value: &Value, line: u32) -> LoxResult<()> {
+        let Value::Object(object) = collection else {
+            unreachable!("assignment to an element of a value CheckIndex rejects");
+        };
+        if let Some(list) = object.as_list() {
+            let position = resolve_index(&index.to_value(), list.len(), &line)?;
+            list.set(position, value.to_value());
+        } else if let Some(map) = object.as_map() {
+            map.set(map_key(&index.to_value(), &line)?, value.to_value());
+        }
+        Ok(())
+    }
+
+    fn overloaded_index<const N: usize>(&mut self, frame: &mut Frame, instance: Value, method: &str, args: [Value; N]) -> LoxResult<()> {
+        match Self::operator_method(&instance, method) {
+            Some(method) => self.call_operator(frame, method, args, ReturnAction::Value),
+            None => Err(self.error(frame, "Can only index lists, maps and strings."))
+        }
+    }
+
+    // The hidden iterator of a for-in loop, with the tree-walker's iterator protocol.
+    fn iterator(&mut self, frame: &mut Frame, iterable: Value, line: u32) -> LoxResult<Rc<ObjIterator>> {
+        match iterable {
+            Value::Instance(instance) => {
+                if instance_property(&instance, "iterator").is_none() {
+                    return Ok(Rc::new(ObjIterator::Instance(instance)));
+                }
+                match self.call_method(frame, &instance, "iterator", line)? {
+                    Value::Instance(iterator) => Ok(Rc::new(ObjIterator::Instance(iterator))),
+                    iterator => self.iterator(frame, iterator, line)
+                }
+            },
+            Value::Generator(generator) => Ok(Rc::new(ObjIterator::Generator(generator))),
+            Value::Iterator(iterator) => Ok(iterator),
+            Value::Str(_) | Value::Object(_) => {
+                let iterator = LoxIterator::from_object(iterable.to_value(), &mut self.native_state, &line)?;
+                Ok(Rc::new(ObjIterator::Items(iterator)))
+            },
+            _ => handle_error(&line, ErrorType::RuntimeError,
+                "Can only iterate over lists, maps, strings, ranges, enums, generators and iterators.")
+        }
+    }
+
+    // A generator's errors already carry the calls they left.
+    fn next_value(&mut self, frame: &mut Frame, iterator: &ObjIterator, line: u32) -> LoxResult<Option<Value>> {
+        match iterator {
+            ObjIterator::Items(items) => items.next_value(&mut self.native_state, &line)
+                .map(|value| value.map(|value| Value::from_value(&value)))
+                .map_err(|error| self.native_error(frame, error)),
+            ObjIterator::Generator(generator) => self.with_frame(frame, |vm| vm.resume(generator)),
+            ObjIterator::Instance(instance) => {
+                let has_next = self.call_method(frame, instance, "hasNext", line).map_err(|error| self.native_error(frame, error))?;
+                if !has_next.is_truthy() {
+                    return Ok(None);
+                }
+                self.call_method(frame, instance, "next", line).map(Some).map_err(|error| self.native_error(frame, error))
+            }
+        }
+    }
+
+    fn call_method(&mut self, frame: &mut Frame, instance: &Rc<ObjInstance>, name: &str, line: u32) -> LoxResult<Value> {
+        match instance_property(instance, name) {
+            Some(method @ (Value::Closure(_) | Value::BoundMethod(_))) => {
+                self.with_frame(frame, |vm| vm.call_value(method, Vec::new(), line))
+            },
+            _ => handle_error(&line, ErrorType::RuntimeError, format!("Iterator must define a '{}' method.", name).as_str())
+        }
+    }
+
+    // Compiles and runs a module the first time it is imported; its globals
+    // are given their own slots among the VM's.
+    fn import(&mut self, spec: &str, base_dir: &str, line: u32) -> LoxResult<Rc<ObjModule>> {
+        let path = resolve_path(Path::new(base_dir), spec, &line)?;
+        let key = module_key(&path);
+        if let Some(module) = self.modules.get(&key) {
+            return Ok(module.clone());
+        }
+        let file: Rc<str> = path.display().to_string().into();
+        let members = load(&path, &key, &line, |stmts| {
+            let (prelude, script, members) = self.emitter.module(&self.prelude, &stmts, file).map_err(unsupported_error)?;
+            self.sync_globals();
+            self.runs.push(Run { kind: RunKind::Module, barrier: self.frames.len() });
+            let result = self.call_script(prelude).and_then(|_| self.call_script(script));
+            self.runs.pop();
+            result.map(|_| members)
+        })?;
+        let module = Rc::new(ObjModule { name: module_name(&path), members });
+        self.modules.insert(key, module.clone());
+        Ok(module)
+    }
+
+    fn arithmetic(&mut self, frame: &mut Frame, method: &str, operation: fn(f64, f64) -> f64) -> LoxResult<()> {
+        let value2 = self.stack.pop().unwrap();
+        let value1 = self.stack.pop().unwrap();
+        match (&value1, &value2) {
+            (Value::Number(num1), Value::Number(num2)) => {
+                self.stack.push(Value::Number(operation(*num1, *num2)));
+                Ok(())
+            },
+            _ => self.operator(frame, value1, method, Some(value2), "Operand must be a number.")
+        }
+    }
+
+    fn comparison(&mut self, frame: &mut Frame, method: &str, operation: fn(f64, f64) -> bool) -> LoxResult<()> {
+        let value2 = self.stack.pop().unwrap();
+        let value1 = self.stack.pop().unwrap();
+        match (&value1, &value2) {
+            (Value::Number(num1), Value::Number(num2)) => {
+                self.stack.push(Value::Bool(operation(*num1, *num2)));
+                Ok(())
+            },
+            _ => self.operator(frame, value1, method, Some(value2), "Operand must be a number.")
+        }
+    }
+
+    // `check` is true for `==`; an instance operand lets either side's `__eq__` decide.
+    fn equality(&mut self, frame: &mut Frame, check: bool) -> LoxResult<()> {
+        let value2 = self.stack.pop().unwrap();
+        let value1 = self.stack.pop().unwrap();
+        if let Some(method) = Self::operator_method(&value1, "__eq__") {
+            return self.call_operator(frame, method, Some(value2), ReturnAction::Truthy(check));
+        }
+        if let Some(method) = Self::operator_method(&value2, "__eq__") {
+            return self.call_operator(frame, method, Some(value1), ReturnAction::Truthy(check));
+        }
+        self.stack.push(Value::Bool(value1.equals(&value2) == check));
+        Ok(())
+    }
+
+    // Operators other than equality only look for a method on their first operand.
+    fn operator(&mut self, frame: &mut Frame, receiver: Value, method: &str, arg: Option<Value>, message: &str) -> LoxResult<()> {
+        match Self::operator_method(&receiver, method) {
+            Some(method) => self.call_operator(frame, method, arg, ReturnAction::Value),
+            None => Err(self.error(frame, message))
+        }
+    }
+
+    fn operator_method(receiver: &Value, method: &str) -> Option<Value> {
+        let Value::Instance(instance) = receiver else {
+            return None;
+        };
+        // Native methods aren't functions to the tree-walker, but `clock` is.
+        instance_property(instance, method).filter(|method| match method {
+            Value::Closure(_) | Value::BoundMethod(_) => true,
+            Value::Native(native) => matches!(native.as_ref(), Native::Clock),
+            _ => false
+        })
+    }
+
+    fn call_operator(&mut self, frame: &mut Frame, method: Value, args: impl IntoIterator<Item = Value>,
+        on_return: ReturnAction) -> LoxResult<()>
+    {
+        let line = Self::line(frame);
+        self.stack.push(method);
+        let start = self.stack.len();
+        self.stack.extend(args);
+        let argc = self.stack.len() - start;
+        if let Value::Native(_) = self.peek(argc) {
+            if argc != 0 {
+                return Err(self.error(frame, &format!("Expected 0 arguments but got {}", argc)));
+            }
+        }
+        self.call_checked(frame, argc, line, on_return)
+    }
+
+    // The callee is checked before its arguments are evaluated, with the messages of the tree-walker.
+    fn check_call(&self, frame: &Frame, argc: usize) -> LoxResult<()> {
+        let arity = match self.peek(0) {
+            Value::Closure(closure) => closure.function.arity,
+            Value::BoundMethod(bound) => bound.method.function.arity,
+            Value::Class(_) => return Ok(()),
+            Value::Native(native) => {
+                let line = Self::line(frame);
+                return Self::check_native(native, argc, line).map_err(|error| self.native_error(frame, error));
+            },
+            _ => return Err(self.error(frame, "Can only call functions and classes."))
+        };
+        if arity != argc {
+            return Err(self.error(frame, &format!("Expected {} arguments but got {}", arity, argc)));
+        }
+        Ok(())
+    }
+
+    fn check_native(native: &Native, argc: usize, line: u32) -> LoxResult<()> {
+        let (min, max) = match native {
+            Native::Method(method) => return method.check_arity(argc, &line),
+            Native::Spawn => (1, 255),
+            Native::Clock | Native::Next(_) | Native::Receive(_) => (0, 0)
+        };
+        if (min..=max).contains(&argc) {
+            return Ok(());
+        }
+        let expected = if min == max { min.to_string() } else { format!("{} to {}", min, max) };
+        handle_error(&line, ErrorType::RuntimeError, format!("Expected {} arguments but got {}", expected, argc).as_str())
+    }
+
+    fn call(&mut self, frame: &mut Frame, argc: usize, line: u32, on_return: ReturnAction) -> LoxResult<()> {
+        let base = self.stack.len() - argc - 1;
+        match self.stack[base].clone() {
+            Value::Closure(closure) => {
+                let name = closure.function.name.clone();
+                self.push_frame(frame, closure, base, name, line, on_return)
+            },
+            Value::BoundMethod(bound) => {
+                self.stack[base] = bound.receiver.clone();
+                let name = bound.method.function.name.clone();
+                self.push_frame(frame, bound.method.clone(), base, name, line, on_return)
+            },
+            Value::Class(class) => {
+                self.stack[base] = Value::Instance(ObjInstance::new(class.clone()));
+                let init = class.methods.borrow().get("init").cloned();
+                match init {
+                    // The constructor's arity is checked in its own frame, named after the class.
+                    Some(init) => {
+                        self.push_frame(frame, init.clone(), base, class.name.clone(), line, on_return)?;
+                        if init.function.arity != argc {
+                            return Err(self.arity_error(frame, init.function.arity, argc));
+                        }
+                        Ok(())
+                    },
+                    None => {
+                        self.stack.truncate(base + 1);
+                        Ok(())
+                    }
+                }
+            },
+            Value::Native(native) => {
+                match native.as_ref() {
+                    // The fiber resumes at this call once the channel has a value.
+                    Native::Receive(channel) if channel.is_empty() && self.can_suspend() => {
+                        self.block_on(channel, line);
+                        frame.ip -= 1;
+                        return Err(self.suspend_fiber(frame));
+                    },
+                    Native::Clock if self.frames.len() >= max_call_depth() => {
+                        return Err(self.error(frame, "Stack overflow."));
+                    },
+                    _ => ()
+                }
+                let args = self.stack.split_off(base + 1);
+                self.stack.pop();
+                let result = self.with_frame(frame, |vm| vm.call_native(&native, args, line));
+                let result = match native.as_ref() {
+                    Native::Next(_) => result?,
+                    _ => result.map_err(|error| self.native_error(frame, error))?
+                };
+                self.stack.push(on_return.apply(result));
+                Ok(())
+            },
+            _ => Err(self.error(frame, "Can only call functions and classes."))
+        }
+    }
+
+    fn call_native(&mut self, native: &Native, args: Vec<Value>, line: u32) -> LoxResult<Value> {
+        match native {
+            Native::Clock => Ok(Value::Number(clock() as f64)),
+            Native::Method(method) => {
+                let args = args.iter().map(Value::to_value).collect();
+                let result = method.call_with_args(args, self, &line)?;
+                Ok(Value::from_value(&result))
+            },
+            Native::Spawn => self.spawn(args, line),
+            Native::Next(generator) => Ok(self.resume(generator)?.unwrap_or(Value::Nil)),
+            Native::Receive(channel) => self.receive(channel, line)
+        }
+    }
+
+    // Calls `callee` from native code: the call runs nested, to its end.
+    fn call_value(&mut self, callee: Value, args: Vec<Value>, line: u32) -> LoxResult<Value> {
+        if self.runs.len() >= MAX_NATIVE_DEPTH {
+            return handle_error(&line, ErrorType::RuntimeError, "Stack overflow.");
+        }
+        let (closure, receiver, name) = match &callee {
+            Value::Closure(closure) => (closure.clone(), callee.clone(), closure.function.name.clone()),
+            Value::BoundMethod(bound) => (bound.method.clone(), bound.receiver.clone(), bound.method.function.name.clone()),
+            Value::Class(class) => {
+                let instance = Value::Instance(ObjInstance::new(class.clone()));
+                let init = class.methods.borrow().get("init").cloned();
+                match init {
+                    Some(init) => (init, instance, class.name.clone()),
+                    None => return Ok(instance)
+                }
+            },
+            Value::Native(native) => {
+                Self::check_native(native, args.len(), line)?;
+                return self.call_native(native, args, line);
+            },
+            _ => return handle_error(&line, ErrorType::RuntimeError, "Can only call functions and classes.")
+        };
+        if self.frames.len() >= max_call_depth() {
+            return handle_error(&line, ErrorType::RuntimeError, "Stack overflow.");
+        }
+        let base = self.stack.len();
+        let argc = args.len();
+        self.stack.push(receiver);
+        self.stack.extend(args);
+        let frame = Frame::new(closure, base, name, line, ReturnAction::Exit);
+        if frame.closure.function.arity != argc {
+            let error = self.arity_error(&frame, frame.closure.function.arity, argc);
+            self.stack.truncate(base);
+            return Err(error);
+        }
+        if frame.closure.function.generator {
+            return Ok(self.make_generator(frame));
+        }
+        self.runs.push(Run { kind: RunKind::Call, barrier: self.frames.len() });
+        let result = self.run(frame);
+        self.runs.pop();
+        result
+    }
+
+    // Raised once the callee's frame is entered, in the file of its caller.
+    fn arity_error(&self, callee: &Frame, arity: usize, argc: usize) -> LoxError {
+        let mut error = self.error_at(callee, callee.line, &format!("Expected {} arguments but got {}", arity, argc));
+        error.span.file = self.frames.last().and_then(Self::file);
+        error
+    }
+
+    // Operator methods check their arity once their frame is pushed, as `call_with_args` does.
+    fn call_checked(&mut self, frame: &mut Frame, argc: usize, line: u32, on_return: ReturnAction) -> LoxResult<()> {
+        self.call(frame, argc, line, on_return)?;
+        let arity = frame.closure.function.arity;
+        if frame.ip == 0 && arity != argc {
+            return Err(self.arity_error(frame, arity, argc));
+        }
+        Ok(())
+    }
+
+    fn push_frame(&mut self, frame: &mut Frame, closure: Rc<ObjClosure>, base: usize, name: Rc<String>,
+        line: u32, on_return: ReturnAction) -> LoxResult<()>
+    {
+        // The script's frame isn't a call, so the callers are the calls in progress.
+        if self.frames.len() >= max_call_depth() {
+            return Err(self.error(frame, "Stack overflow."));
+        }
+        let callee = Frame::new(closure, base, name, line, on_return);
+        if callee.closure.function.generator {
+            let generator = self.make_generator(callee);
+            self.stack.push(on_return.apply(generator));
+            return Ok(());
+        }
+        let caller = mem::replace(frame, callee);
+        self.frames.push(caller);
+        Ok(())
+    }
+
+    // A call to a Lox function takes over the returning frame; anything else is
+    // called normally and the `Return` after it passes its result on. A
+    // coroutine's body returns to whoever resumed it, so it keeps its frame.
+    fn tail_call(&mut self, frame: &mut Frame, argc: usize) -> LoxResult<()> {
+        let start = self.stack.len() - argc - 1;
+        let line = Self::line(frame);
+        let (closure, receiver) = match &self.stack[start] {
+            _ if matches!(frame.on_return, ReturnAction::Finish) => return self.call(frame, argc, line, ReturnAction::Value),
+            Value::Closure(closure) if !closure.function.generator => (closure.clone(), None),
+            Value::BoundMethod(bound) if !bound.method.function.generator => (bound.method.clone(), Some(bound.receiver.clone())),
+            _ => return self.call(frame, argc, line, ReturnAction::Value)
+        };
+        self.close_upvalues(frame.base);
+        self.stack.drain(frame.base..start);
+        if let Some(receiver) = receiver {
+            self.stack[frame.base] = receiver;
+        }
+        frame.name = closure.function.name.clone();
+        frame.closure = closure;
+        frame.ip = 0;
+        frame.line = line;
+        frame.elided += 1;
+        frame.extra = None;
+        Ok(())
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let open = self.open_upvalues.iter().find(|upvalue| matches!(&*upvalue.borrow(), Upvalue::Open(open) if *open == slot));
+        if let Some(upvalue) = open {
+            return upvalue.clone();
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue.clone());
+        upvalue
+    }
+
+    // Moves the variables from `from` up into their upvalues, before their slots are popped.
+    fn close_upvalues(&mut self, from: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let mut upvalue = upvalue.borrow_mut();
+            match *upvalue {
+                Upvalue::Open(slot) if slot >= from => {
+                    *upvalue = Upvalue::Closed(stack[slot].clone());
+                    false
+                },
+                _ => true
+            }
+        });
+    }
+}
+
+// Native methods call back into Lox through the VM.
+impl Host for Vm {
+    fn call(&mut self, callee: &declarations::Value, args: Vec<declarations::Value>, line: &u32) -> LoxResult<declarations::Value> {
+        let args = args.iter().map(Value::from_value).collect();
+        self.call_value(Value::from_value(callee), args, *line).map(|value| value.to_value())
+    }
+
+    fn scopes(&mut self) -> &mut BlockScopes {
+        &mut self.native_state
+    }
+}
@@ -0,0 +1,111 @@
+use crate::vm::value::Value;
+
+// Operands index the chunk's constant pool, the frame's slots, its upvalues or
+// the VM's globals; jump targets are absolute offsets into `code`.
+#[derive(Clone, Copy)]
+pub enum OpCode {
+    Constant(u16),
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal(u16),
+    SetLocal(u16),
+    GetUpvalue(u16),
+    SetUpvalue(u16),
+    GetGlobal(u16),
+    // A superclass is looked up by name and checked by `Inherit`, so a missing one is nil here.
+    GetGlobalOrNil(u16),
+    SetGlobal(u16),
+    DefineGlobal(u16),
+    DefineConstant(u16),
+    GetProperty(u16),
+    SetProperty(u16),
+    // Property, then the name of the method the `super` appears in.
+    GetSuper(u16, u16),
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    Jump(u32),
+    JumpIfFalse(u32),
+    // Short-circuit operators keep the left operand when they jump.
+    And(u32),
+    Or(u32),
+    NilCoalesce(u32),
+    // Checks the callee before its arguments are evaluated, as the tree-walker does.
+    CheckCall(u8),
+    Call(u8),
+    TailCall(u8),
+    Closure(u16),
+    // Forward capture, then the slot of the local just declared for it.
+    BindForward(u16, u16),
+    CloseUpvalue,
+    Return,
+    Class(u16),
+    Inherit,
+    Method(u16),
+    List(u16),
+    // An empty map, then one entry at a time so a bad key fails before the next entry runs.
+    MapNew,
+    MapInsert,
+    GetIndex,
+    // Checks the collection and index of an assignment before the value is evaluated.
+    CheckIndex,
+    SetIndex,
+    // True for an inclusive range.
+    Range(bool),
+    // Keeps nil and jumps past the rest of a `?.` link.
+    JumpIfNil(u32),
+    // Turns the iterable into the hidden iterator of a for-in loop.
+    Iterate,
+    // Pushes the iterator's next value, or pops the iterator and jumps once it is exhausted.
+    IterNext(u32),
+    // A fresh enum, made from the one in the constant pool.
+    Enum(u16),
+    // Protects the code up to `EndTry`: errors jump to the catch, which starts with
+    // the error as the value the global names (the `Error` class) makes of it.
+    Try(u32, u16),
+    EndTry,
+    // Runs the `finally` at the target for an error or a `return` leaving the try;
+    // `Finally` enters it normally, and `EndFinally` carries on with what left the try.
+    TryFinally(u32),
+    Finally,
+    EndFinally,
+    Throw,
+    Yield,
+    FiberYield,
+    // Path, then the directory of the importing file, both as constants.
+    Import(u16, u16),
+    // Name, then path for the error.
+    ModuleMember(u16, u16)
+}
+
+#[derive(Default)]
+pub struct Chunk {
+    pub code: Vec<OpCode>,
+    pub lines: Vec<u32>,
+    pub constants: Vec<Value>
+}
+
+impl Chunk {
+    pub fn write(&mut self, op: OpCode, line: u32) -> usize {
+        self.code.push(op);
+        self.lines.push(line);
+        self.code.len() - 1
+    }
+
+    pub fn add_constant(&mut self, value: Value) -> Option<u16> {
+        self.constants.push(value);
+        u16::try_from(self.constants.len() - 1).ok()
+    }
+}
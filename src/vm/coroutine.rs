@@ -0,0 +1,264 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::mem;
+use std::rc::Rc;
+
+use crate::error_handler::{handle_error, ErrorType, LoxError, LoxResult};
+use crate::fiber::Channel;
+use crate::vm::value::{Coroutine, ObjFiber, ObjGenerator, Upvalue, Value};
+use crate::vm::{Frame, ReturnAction, Vm};
+
+// A generator or fiber body that isn't running: its frames, with bases counted
+// from the start of its part of the stack, and the variables closures captured
+// in it, which stay closed over their values until it resumes.
+pub struct Suspension {
+    frames: Vec<Frame>,
+    stack: Vec<Value>,
+    upvalues: Vec<(usize, Rc<RefCell<Upvalue>>)>
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RunKind {
+    // Lox code called from native code.
+    Call,
+    Module,
+    Generator,
+    Fiber
+}
+
+// A run of the VM nested in the one below it, over the frames from `barrier` up.
+pub struct Run {
+    pub kind: RunKind,
+    pub barrier: usize
+}
+
+pub struct Scheduler {
+    fibers: VecDeque<Rc<ObjFiber>>,
+    current: Option<Rc<ObjFiber>>,
+    next_id: usize
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler { fibers: VecDeque::new(), current: None, next_id: 1 }
+    }
+}
+
+impl Vm {
+    // The body of a generator doesn't run when it is called: the frame and
+    // its arguments make a suspension at the first instruction.
+    pub(super) fn make_generator(&mut self, mut frame: Frame) -> Value {
+        let stack = self.stack.split_off(frame.base);
+        frame.base = 0;
+        frame.on_return = ReturnAction::Finish;
+        let name = frame.name.clone();
+        let body = Suspension { frames: Vec::from([frame]), stack, upvalues: Vec::new() };
+        Value::Generator(Rc::new(ObjGenerator { name, body: RefCell::new(Coroutine::Suspended(body)) }))
+    }
+
+    // Runs the generator until its next `yield`; None once its body has finished.
+    // A body that fails is finished too.
+    pub(super) fn resume(&mut self, generator: &ObjGenerator) -> LoxResult<Option<Value>> {
+        let body = match generator.body.replace(Coroutine::Running) {
+            Coroutine::Suspended(body) => body,
+            other => {
+                generator.body.replace(other);
+                return Ok(None);
+            }
+        };
+        match self.run_coroutine(body, RunKind::Generator) {
+            Ok(Some((value, body))) => {
+                generator.body.replace(Coroutine::Suspended(body));
+                Ok(Some(value))
+            },
+            result => {
+                generator.body.replace(Coroutine::Done);
+                result.map(|_| None)
+            }
+        }
+    }
+
+    // Moves the frames of the innermost run, the running one last, off the VM.
+    pub(super) fn suspend(&mut self, frame: &mut Frame) -> Suspension {
+        let barrier = self.runs.last().expect("suspending outside a coroutine").barrier;
+        let mut frames = self.frames.split_off(barrier);
+        frames.push(mem::replace(frame, self.placeholder_frame()));
+        let base = frames[0].base;
+        let mut upvalues = Vec::new();
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let mut captured = upvalue.borrow_mut();
+            match *captured {
+                Upvalue::Open(slot) if slot >= base => {
+                    *captured = Upvalue::Closed(stack[slot].clone());
+                    upvalues.push((slot - base, upvalue.clone()));
+                    false
+                },
+                _ => true
+            }
+        });
+        let stack = self.stack.split_off(base);
+        for frame in &mut frames {
+            frame.base -= base;
+        }
+        Suspension { frames, stack, upvalues }
+    }
+
+    // Puts the suspended frames back on top of the VM and runs them until the
+    // body returns, or suspends again with the value it yielded.
+    fn run_coroutine(&mut self, body: Suspension, kind: RunKind) -> LoxResult<Option<(Value, Suspension)>> {
+        let Suspension { mut frames, stack, upvalues } = body;
+        let base = self.stack.len();
+        self.stack.extend(stack);
+        for (slot, upvalue) in upvalues {
+            if let Upvalue::Closed(value) = upvalue.replace(Upvalue::Open(base + slot)) {
+                self.stack[base + slot] = value;
+            }
+            self.open_upvalues.push(upvalue);
+        }
+        for frame in &mut frames {
+            frame.base += base;
+        }
+        let top = frames.pop().unwrap();
+        self.runs.push(Run { kind, barrier: self.frames.len() });
+        self.frames.extend(frames);
+        let result = self.run(top);
+        self.runs.pop();
+        match result {
+            Ok(_) => Ok(None),
+            Err(error) if error.kind == ErrorType::Suspended => Ok(self.suspended.take()),
+            Err(error) => Err(error)
+        }
+    }
+
+    // A fiber is suspended along with the calls its body made, but not through
+    // native code calling into Lox or a generator it resumed: the Rust stack is
+    // in use there, so other fibers are run in place instead.
+    pub(super) fn can_suspend(&self) -> bool {
+        self.scheduler.current.is_some() && self.runs.last().is_some_and(|run| run.kind == RunKind::Fiber)
+    }
+
+    pub(super) fn suspend_fiber(&mut self, frame: &mut Frame) -> LoxError {
+        let body = self.suspend(frame);
+        self.suspended = Some((Value::Nil, body));
+        LoxError::suspended()
+    }
+
+    // The fiber resumes once the channel has a value; the `receive()` call runs again then.
+    pub(super) fn block_on(&mut self, channel: &Channel, line: u32) {
+        if let Some(fiber) = &self.scheduler.current {
+            fiber.blocked_on.replace(Some(channel.clone()));
+            fiber.blocked_line.set(line);
+        }
+    }
+
+    pub(super) fn spawn(&mut self, mut args: Vec<Value>, line: u32) -> LoxResult<Value> {
+        let function = args.remove(0);
+        let (closure, receiver) = match &function {
+            Value::Closure(closure) => (closure.clone(), function.clone()),
+            Value::BoundMethod(bound) => (bound.method.clone(), bound.receiver.clone()),
+            _ => return handle_error(&line, ErrorType::RuntimeError, "Can only spawn functions.")
+        };
+        if closure.function.arity != args.len() {
+            return handle_error(&line, ErrorType::RuntimeError,
+                format!("Expected {} arguments but got {}", closure.function.arity, args.len()).as_str());
+        }
+        let name = closure.function.name.clone();
+        let frame = Frame::new(closure, 0, name.clone(), line, ReturnAction::Finish);
+        let stack = [Vec::from([receiver]), args].concat();
+        let body = Suspension { frames: Vec::from([frame]), stack, upvalues: Vec::new() };
+        let fiber = Rc::new(ObjFiber {
+            id: self.scheduler.next_id,
+            name,
+            body: RefCell::new(Coroutine::Suspended(body)),
+            blocked_on: RefCell::new(None),
+            blocked_line: Cell::new(0),
+            active: Cell::new(false)
+        });
+        self.scheduler.next_id += 1;
+        self.scheduler.fibers.push_back(fiber.clone());
+        Ok(Value::Fiber(fiber))
+    }
+
+    // Runs the fiber until it yields, blocks or finishes.
+    fn run_slice(&mut self, fiber: &Rc<ObjFiber>) -> LoxResult<()> {
+        fiber.blocked_on.replace(None);
+        fiber.active.set(true);
+        let previous = self.scheduler.current.replace(fiber.clone());
+        let result = match fiber.body.replace(Coroutine::Running) {
+            Coroutine::Suspended(body) => self.run_coroutine(body, RunKind::Fiber),
+            _ => Ok(None)
+        };
+        self.scheduler.current = previous;
+        fiber.active.set(false);
+        match result {
+            Ok(Some((_, body))) => {
+                fiber.body.replace(Coroutine::Suspended(body));
+                Ok(())
+            },
+            result => {
+                fiber.body.replace(Coroutine::Done);
+                result.map(|_| ()).map_err(|mut error| {
+                    error.fiber.get_or_insert_with(|| format!("fiber {} ({})", fiber.id, fiber.name));
+                    error
+                })
+            }
+        }
+    }
+
+    // A `receive()` that can't suspend its fiber waits for the value here.
+    pub(super) fn receive(&mut self, channel: &Channel, line: u32) -> LoxResult<Value> {
+        if channel.is_empty() {
+            self.run_scheduler(line, |_| !channel.is_empty())?;
+        }
+        let value = channel.0.borrow_mut().pop_front().unwrap();
+        Ok(Value::from_value(&value))
+    }
+
+    // `yield()` outside a fiber's own body gives every other fiber one turn.
+    pub(super) fn yield_in_place(&mut self) -> LoxResult<()> {
+        let fibers: Vec<Rc<ObjFiber>> = self.scheduler.fibers.iter().cloned().collect();
+        for fiber in fibers {
+            if fiber.is_runnable() {
+                self.run_slice(&fiber)?;
+            }
+        }
+        self.remove_finished();
+        Ok(())
+    }
+
+    // Runs fibers round-robin until `ready` holds; reports a deadlock when every
+    // remaining fiber is blocked.
+    fn run_scheduler<F>(&mut self, line: u32, ready: F) -> LoxResult<()>
+    where F: Fn(&Vm) -> bool
+    {
+        while !ready(self) {
+            let Some(position) = self.scheduler.fibers.iter().position(|fiber| fiber.is_runnable()) else {
+                return self.report_deadlock(line);
+            };
+            let fiber = self.scheduler.fibers.remove(position).unwrap();
+            self.scheduler.fibers.push_back(fiber.clone());
+            self.run_slice(&fiber)?;
+            self.remove_finished();
+        }
+        Ok(())
+    }
+
+    // Called once the main program has finished: every spawned fiber runs to completion.
+    pub(super) fn run_fibers(&mut self) -> LoxResult<()> {
+        self.run_scheduler(0, |vm| vm.scheduler.fibers.is_empty())
+    }
+
+    fn remove_finished(&mut self) {
+        self.scheduler.fibers.retain(|fiber| !fiber.is_done());
+    }
+
+    fn report_deadlock(&self, line: u32) -> LoxResult<()> {
+        let blocked = self.scheduler.fibers.iter().find(|fiber| !fiber.active.get());
+        let line = match blocked {
+            Some(fiber) if line == 0 => fiber.blocked_line.get(),
+            _ => line
+        };
+        handle_error(&line, ErrorType::RuntimeError, "Deadlock: all fibers are blocked.")
+    }
+}
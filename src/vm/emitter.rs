@@ -0,0 +1,621 @@
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::rc::Rc;
+
+use crate::iterator::ITERATOR_KEY;
+use crate::statements::{FunctionDeclStatement, Statement};
+use crate::vm::chunk::{Chunk, OpCode};
+use crate::vm::value::{FunctionKind, ObjFunction, UpvalueRef, Value};
+
+// Globals every script and module starts with, see `Vm::builtin`.
+pub const BUILTINS: [&str; 4] = ["clock", "spawn", "Channel", "gc"];
+
+// Raised by what the VM doesn't compile, naming the construct and where it is.
+pub struct Unsupported {
+    pub construct: String,
+    pub line: u32
+}
+
+impl Unsupported {
+    pub fn new(construct: &str, line: u32) -> Unsupported {
+        Unsupported { construct: construct.to_string(), line }
+    }
+}
+
+pub type EmitResult = Result<(), Unsupported>;
+
+struct Local {
+    name: String,
+    depth: usize,
+    captured: bool
+}
+
+// A capture, by a function declared in this one, of a name no scope declares
+// yet. A later declaration in the block it waits in, or in a block around it
+// once that one ends, is what the function captures.
+struct Forward {
+    name: String,
+    depth: usize,
+    // The function, as a constant of this one, and its upvalue for the name.
+    constant: u16,
+    upvalue: u16
+}
+
+struct FunctionState {
+    function: ObjFunction,
+    locals: Vec<Local>,
+    scope_depth: usize,
+    forwards: Vec<Forward>,
+    forward_ids: u16,
+    names: HashMap<String, u16>,
+    // Code offset of each statement of the body, so the statement jumps can be resolved.
+    statement_starts: Vec<usize>,
+    statement_index: usize,
+    pending_jumps: Vec<(usize, usize)>,
+    method: Option<Rc<str>>
+}
+
+impl FunctionState {
+    fn new(name: Rc<String>, kind: FunctionKind, arity: usize, file: Option<Rc<str>>) -> FunctionState {
+        let slot_zero = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            FunctionKind::Script | FunctionKind::Function => ""
+        };
+        let method = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => Some(name.as_str().into()),
+            FunctionKind::Script | FunctionKind::Function => None
+        };
+        // The script's own scope holds globals; a function body starts one scope in.
+        let scope_depth = if kind == FunctionKind::Script { 0 } else { 1 };
+        FunctionState {
+            function: ObjFunction { name, arity, chunk: Chunk::default(), upvalues: Vec::new(), kind, file, generator: false },
+            locals: Vec::from([Local { name: slot_zero.to_string(), depth: scope_depth, captured: false }]),
+            scope_depth,
+            forwards: Vec::new(),
+            forward_ids: 0,
+            names: HashMap::new(),
+            statement_starts: Vec::new(),
+            statement_index: 0,
+            pending_jumps: Vec::new(),
+            method
+        }
+    }
+}
+
+// Compiles the statements the tree-walker runs into bytecode, resolving
+// variables the way the tree-walker's resolver does.
+pub struct Emitter {
+    functions: Vec<FunctionState>,
+    line: u32,
+    // Globals declared so far by the script or module being compiled.
+    declared: HashSet<String>,
+    // Globals are numbered across every script and module run on the same VM,
+    // each module naming its own from the indices in `global_indices`.
+    globals: Vec<Rc<str>>,
+    global_indices: HashMap<Rc<str>, u16>,
+    // The module being compiled, None for the script.
+    file: Option<Rc<str>>
+}
+
+impl Emitter {
+    pub fn new() -> Emitter {
+        let mut emitter = Emitter {
+            functions: Vec::new(),
+            line: 0,
+            declared: HashSet::new(),
+            globals: Vec::new(),
+            global_indices: HashMap::new(),
+            file: None
+        };
+        emitter.builtins();
+        emitter
+    }
+
+    pub fn script(&mut self, stmts: &[Box<dyn Statement>]) -> Result<Rc<ObjFunction>, Unsupported> {
+        let file = self.file.clone();
+        self.functions = Vec::from([FunctionState::new(Rc::new(String::from("script")), FunctionKind::Script, 0, file)]);
+        self.statements(stmts)?;
+        self.implicit_return();
+        Ok(Rc::new(self.functions.pop().unwrap().function))
+    }
+
+    // A module gets globals of its own, starting with the builtins and its own
+    // prelude; returns the prelude, the module's body and the indices of its globals.
+    pub fn module(&mut self, prelude: &[Box<dyn Statement>], stmts: &[Box<dyn Statement>], file: Rc<str>)
+        -> Result<(Rc<ObjFunction>, Rc<ObjFunction>, HashMap<Rc<str>, u16>), Unsupported>
+    {
+        let global_indices = mem::take(&mut self.global_indices);
+        let declared = mem::take(&mut self.declared);
+        let file = self.file.replace(file);
+        self.builtins();
+        let compiled = self.script(prelude).and_then(|prelude| Ok((prelude, self.script(stmts)?)));
+        let members = mem::replace(&mut self.global_indices, global_indices);
+        self.declared = declared;
+        self.file = file;
+        let (prelude, script) = compiled?;
+        Ok((prelude, script, members))
+    }
+
+    // The names of the globals compiled so far, by index.
+    pub fn global_names(&self) -> &[Rc<str>] {
+        &self.globals
+    }
+
+    fn builtins(&mut self) {
+        for name in BUILTINS {
+            // Fewer than 65536 globals are in use before any script is compiled.
+            let _ = self.global(name);
+        }
+    }
+
+    fn global(&mut self, name: &str) -> Result<u16, Unsupported> {
+        if let Some(index) = self.global_indices.get(name) {
+            return Ok(*index);
+        }
+        let index = u16::try_from(self.globals.len()).map_err(|_| self.unsupported("more than 65536 globals"))?;
+        let name: Rc<str> = name.into();
+        self.globals.push(name.clone());
+        self.global_indices.insert(name, index);
+        Ok(index)
+    }
+
+    fn current(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().unwrap()
+    }
+
+    pub fn op(&mut self, op: OpCode, line: u32) -> usize {
+        self.line = line;
+        self.current().function.chunk.write(op, line)
+    }
+
+    pub fn constant(&mut self, value: Value, line: u32) -> EmitResult {
+        let index = self.current().function.chunk.add_constant(value).ok_or(Unsupported::new("more than 65536 constants in one function", line))?;
+        self.op(OpCode::Constant(index), line);
+        Ok(())
+    }
+
+    fn name(&mut self, name: &str) -> Result<u16, Unsupported> {
+        if let Some(index) = self.current().names.get(name) {
+            return Ok(*index);
+        }
+        let line = self.line;
+        let index = self.current().function.chunk.add_constant(Value::Str(name.into())).ok_or(Unsupported::new("more than 65536 constants in one function", line))?;
+        self.current().names.insert(name.to_string(), index);
+        Ok(index)
+    }
+
+    // The bodies of a `try` are blocks of their own, with statement jumps counted inside them.
+    pub fn statements(&mut self, stmts: &[Box<dyn Statement>]) -> EmitResult {
+        let state = self.current();
+        let outer = (mem::take(&mut state.statement_starts), state.statement_index, mem::take(&mut state.pending_jumps));
+        let result = self.block(stmts);
+        let state = self.current();
+        (state.statement_starts, state.statement_index, state.pending_jumps) = outer;
+        result
+    }
+
+    fn block(&mut self, stmts: &[Box<dyn Statement>]) -> EmitResult {
+        for (index, stmt) in stmts.iter().enumerate() {
+            let state = self.current();
+            state.statement_starts.push(state.function.chunk.code.len());
+            state.statement_index = index;
+            stmt.emit(self)?;
+        }
+        let line = self.line;
+        let state = self.current();
+        state.statement_starts.push(state.function.chunk.code.len());
+        for (at, target) in state.pending_jumps.drain(..) {
+            let offset = *state.statement_starts.get(target).ok_or(Unsupported::new("a jump past the end of its block", line))? as u32;
+            match &mut state.function.chunk.code[at] {
+                OpCode::Jump(to) | OpCode::JumpIfFalse(to) | OpCode::IterNext(to) => *to = offset,
+                _ => unreachable!("statement jump patched on another instruction")
+            }
+        }
+        Ok(())
+    }
+
+    // Statement jumps count statements, as the tree-walker's index does.
+    fn statement_jump(&mut self, op: OpCode, target: usize) {
+        let state = self.current();
+        let at = state.function.chunk.code.len();
+        state.pending_jumps.push((at, target));
+        let line = self.line;
+        self.op(op, line);
+    }
+
+    pub fn jump_if_false(&mut self, steps: usize) {
+        let target = self.current().statement_index + steps;
+        self.statement_jump(OpCode::JumpIfFalse(0), target);
+    }
+
+    pub fn go_to(&mut self, steps: usize) {
+        let target = self.current().statement_index + steps;
+        self.statement_jump(OpCode::Jump(0), target);
+    }
+
+    pub fn back_to(&mut self, steps: usize) -> EmitResult {
+        let line = self.line;
+        let state = self.current();
+        let target = state.statement_index.checked_sub(steps).ok_or(Unsupported::new("a jump before the start of its block", line))?;
+        let offset = state.statement_starts[target] as u32;
+        self.op(OpCode::Jump(offset), line);
+        Ok(())
+    }
+
+    // A jump inside an expression, patched by `patch_jump` once its target is emitted.
+    pub fn jump(&mut self, op: OpCode, line: u32) -> usize {
+        self.op(op, line)
+    }
+
+    pub fn patch_jump(&mut self, at: usize) {
+        let chunk = &mut self.current().function.chunk;
+        let offset = chunk.code.len() as u32;
+        match &mut chunk.code[at] {
+            OpCode::And(to) | OpCode::Or(to) | OpCode::NilCoalesce(to) | OpCode::Jump(to) | OpCode::JumpIfNil(to)
+                | OpCode::Try(to, _) | OpCode::TryFinally(to) => *to = offset,
+            _ => unreachable!("expression jump patched on another instruction")
+        }
+    }
+
+    pub fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    pub fn end_scope(&mut self) {
+        let line = self.line;
+        let state = self.current();
+        state.scope_depth -= 1;
+        // Captures waiting in the script's own scope stay globals.
+        let depth = state.scope_depth;
+        state.forwards.retain_mut(|forward| {
+            forward.depth = forward.depth.min(depth);
+            depth > 0
+        });
+        let mut ops = Vec::new();
+        while let Some(local) = state.locals.last() {
+            if local.depth <= state.scope_depth {
+                break;
+            }
+            ops.push(if local.captured { OpCode::CloseUpvalue } else { OpCode::Pop });
+            state.locals.pop();
+        }
+        for op in ops {
+            self.op(op, line);
+        }
+    }
+
+    // Makes the value on top of the stack a local of the current scope; globals are defined by `define_variable`.
+    pub fn declare_variable(&mut self, name: &str) -> EmitResult {
+        let line = self.line;
+        if self.current().scope_depth == 0 {
+            self.declared.insert(name.to_string());
+            return Ok(());
+        }
+        let state = self.current();
+        if state.locals.len() > u16::MAX as usize {
+            return Err(Unsupported::new("more than 65536 locals in one function", line));
+        }
+        let depth = state.scope_depth;
+        let slot = state.locals.len() as u16;
+        let (filled, waiting) = mem::take(&mut state.forwards).into_iter()
+            .partition(|forward| forward.depth == depth && forward.name == name);
+        state.forwards = waiting;
+        state.locals.push(Local { name: name.to_string(), depth, captured: false });
+        for forward in filled {
+            let id = self.current().forward_ids;
+            self.current().forward_ids += 1;
+            self.patch_upvalue(&forward, UpvalueRef::Forward(id));
+            self.current().locals[slot as usize].captured = true;
+            self.op(OpCode::BindForward(id, slot), line);
+        }
+        Ok(())
+    }
+
+    pub fn define_variable(&mut self, name: &str, constant: bool, line: u32) -> EmitResult {
+        if self.current().scope_depth > 0 {
+            return Ok(());
+        }
+        let index = self.global(name)?;
+        self.op(if constant { OpCode::DefineConstant(index) } else { OpCode::DefineGlobal(index) }, line);
+        Ok(())
+    }
+
+    pub fn get_variable(&mut self, name: &str, line: u32) -> EmitResult {
+        let op = match self.resolve(name)? {
+            Resolved::Local(slot) => OpCode::GetLocal(slot),
+            Resolved::Upvalue(index) => OpCode::GetUpvalue(index),
+            Resolved::Global(index) => OpCode::GetGlobal(index)
+        };
+        self.op(op, line);
+        Ok(())
+    }
+
+    pub fn set_variable(&mut self, name: &str, line: u32) -> EmitResult {
+        let op = match self.resolve(name)? {
+            Resolved::Local(slot) => OpCode::SetLocal(slot),
+            Resolved::Upvalue(index) => OpCode::SetUpvalue(index),
+            Resolved::Global(index) => OpCode::SetGlobal(index)
+        };
+        self.op(op, line);
+        Ok(())
+    }
+
+    fn resolve(&mut self, name: &str) -> Result<Resolved, Unsupported> {
+        let top = self.functions.len() - 1;
+        if let Some(slot) = self.resolve_local(top, name) {
+            return Ok(Resolved::Local(slot));
+        }
+        if let Some(index) = self.resolve_upvalue(top, name)? {
+            return Ok(Resolved::Upvalue(index));
+        }
+        let global = self.global(name)?;
+        if self.declared.contains(name) || self.declared_in_root(top) {
+            return Ok(Resolved::Global(global));
+        }
+        self.add_upvalue(top, UpvalueRef::Global(global)).map(Resolved::Upvalue)
+    }
+
+    // Whether a function is declared in the script's own scope, whose undeclared
+    // names are globals rather than forward captures. The scope holding a
+    // class's `super` isn't one the tree-walker has.
+    fn declared_in_root(&self, function: usize) -> bool {
+        let script = &self.functions[0];
+        function <= 1 && script.locals.iter().all(|local| local.depth == 0 || local.name == "super")
+    }
+
+    fn resolve_local(&self, function: usize, name: &str) -> Option<u16> {
+        let position = self.functions[function].locals.iter().rposition(|local| local.name == name)?;
+        Some(position as u16)
+    }
+
+    fn resolve_upvalue(&mut self, function: usize, name: &str) -> Result<Option<u16>, Unsupported> {
+        if function == 0 {
+            return Ok(None);
+        }
+        if let Some(slot) = self.resolve_local(function - 1, name) {
+            self.functions[function - 1].locals[slot as usize].captured = true;
+            return self.add_upvalue(function, UpvalueRef::Local(slot)).map(Some);
+        }
+        match self.resolve_upvalue(function - 1, name)? {
+            Some(index) => self.add_upvalue(function, UpvalueRef::Upvalue(index)).map(Some),
+            None => Ok(None)
+        }
+    }
+
+    fn add_upvalue(&mut self, function: usize, upvalue: UpvalueRef) -> Result<u16, Unsupported> {
+        let line = self.line;
+        let upvalues = &mut self.functions[function].function.upvalues;
+        if let Some(position) = upvalues.iter().position(|existing| *existing == upvalue) {
+            return Ok(position as u16);
+        }
+        upvalues.push(upvalue);
+        u16::try_from(upvalues.len() - 1).map_err(|_| Unsupported::new("more than 65536 captured variables in one function", line))
+    }
+
+    pub fn property(&mut self, name: &str) -> Result<u16, Unsupported> {
+        self.name(name)
+    }
+
+    // `super.name`: the instance and the superclass are on the stack, the enclosing method names the instance's `super`.
+    pub fn super_property(&mut self, name: &str, line: u32) -> EmitResult {
+        let method = self.functions.iter().rev().find_map(|state| state.method.clone())
+            .ok_or(Unsupported::new("'super' outside a method", line))?;
+        self.get_variable("this", line)?;
+        self.get_variable("super", line)?;
+        let property = self.property(name)?;
+        let method = self.name(&method)?;
+        self.op(OpCode::GetSuper(property, method), line);
+        Ok(())
+    }
+
+    pub fn argument_count(&self, count: usize) -> Result<u8, Unsupported> {
+        u8::try_from(count).map_err(|_| self.unsupported("more than 255 arguments"))
+    }
+
+    pub fn unsupported(&self, construct: &str) -> Unsupported {
+        Unsupported::new(construct, self.line)
+    }
+
+    pub fn return_value(&mut self, line: u32) {
+        if self.current().function.kind == FunctionKind::Initializer {
+            self.op(OpCode::Pop, line);
+            self.op(OpCode::GetLocal(0), line);
+        }
+        self.op(OpCode::Return, line);
+    }
+
+    fn implicit_return(&mut self) {
+        let line = self.line;
+        let op = match self.current().function.kind {
+            FunctionKind::Initializer => OpCode::GetLocal(0),
+            _ => OpCode::Nil
+        };
+        self.op(op, line);
+        self.op(OpCode::Return, line);
+    }
+
+    pub fn function_declaration(&mut self, decl: &FunctionDeclStatement) -> EmitResult {
+        let name = decl.function_decl.name.as_str();
+        let line = self.line;
+        self.declare_variable(name)?;
+        self.function(decl, FunctionKind::Function)?;
+        self.define_variable(name, false, line)
+    }
+
+    fn function(&mut self, decl: &FunctionDeclStatement, kind: FunctionKind) -> EmitResult {
+        let func = &decl.function_decl;
+        let line = self.line;
+        let file = self.file.clone();
+        let mut state = FunctionState::new(func.name.clone(), kind, func.params_names.len(), file);
+        state.function.generator = func.generator;
+        self.functions.push(state);
+        for param in func.params_names.iter() {
+            self.declare_variable(param)?;
+        }
+        self.statements(&func.statements)?;
+        self.implicit_return();
+        self.unfilled_forwards()?;
+        let function = self.functions.pop().unwrap().function;
+        let index = self.current().function.chunk.add_constant(Value::Function(Rc::new(function)))
+            .ok_or(Unsupported::new("more than 65536 constants in one function", line))?;
+        self.forward_captures(index);
+        self.op(OpCode::Closure(index), line);
+        Ok(())
+    }
+
+    // Captures nothing in the function declared become captures of the function
+    // itself, unless it is declared in the script's scope: they stay globals then.
+    fn unfilled_forwards(&mut self) -> EmitResult {
+        let top = self.functions.len() - 1;
+        if self.declared_in_root(top) {
+            return Ok(());
+        }
+        for forward in mem::take(&mut self.functions[top].forwards) {
+            let global = self.global(&forward.name)?;
+            let index = self.add_upvalue(top, UpvalueRef::Global(global))?;
+            self.patch_upvalue(&forward, UpvalueRef::Upvalue(index));
+        }
+        Ok(())
+    }
+
+    fn patch_upvalue(&mut self, forward: &Forward, upvalue: UpvalueRef) {
+        let Value::Function(function) = &mut self.current().function.chunk.constants[forward.constant as usize] else {
+            unreachable!("forward capture of a constant that isn't a function");
+        };
+        Rc::get_mut(function).expect("function shared while it is compiled").upvalues[forward.upvalue as usize] = upvalue;
+    }
+
+    // Makes the forward captures of the function just compiled, the constant
+    // at `index`, wait for a declaration in the current block.
+    fn forward_captures(&mut self, index: u16) {
+        let state = self.functions.last_mut().unwrap();
+        let Value::Function(function) = &mut state.function.chunk.constants[index as usize] else {
+            unreachable!("closure over a constant that isn't a function");
+        };
+        for (position, upvalue) in function.upvalues.iter().enumerate() {
+            if let UpvalueRef::Global(global) = upvalue {
+                state.forwards.push(Forward {
+                    name: self.globals[*global as usize].to_string(),
+                    depth: state.scope_depth,
+                    constant: index,
+                    upvalue: position as u16
+                });
+            }
+        }
+    }
+
+    pub fn class_declaration<'a>(&mut self, name: &str, superclass: Option<(&str, u32)>,
+        methods: impl Iterator<Item = &'a FunctionDeclStatement>) -> EmitResult
+    {
+        let line = self.line;
+        let name_index = self.name(name)?;
+        self.declare_variable(name)?;
+        self.op(OpCode::Class(name_index), line);
+        self.define_variable(name, false, line)?;
+        // Methods capture the superclass as `super`, from a scope around them.
+        if let Some((super_name, super_line)) = superclass {
+            match self.resolve(super_name)? {
+                Resolved::Local(slot) => self.op(OpCode::GetLocal(slot), super_line),
+                Resolved::Upvalue(index) => self.op(OpCode::GetUpvalue(index), super_line),
+                Resolved::Global(index) => self.op(OpCode::GetGlobalOrNil(index), super_line)
+            };
+            self.begin_scope();
+            self.declare_variable("super")?;
+            self.get_variable(name, super_line)?;
+            self.op(OpCode::Inherit, super_line);
+        }
+        self.get_variable(name, line)?;
+        for method in methods {
+            let method_name = method.function_decl.name.as_str();
+            let kind = if method_name == "init" { FunctionKind::Initializer } else { FunctionKind::Method };
+            self.function(method, kind)?;
+            let method_index = self.name(method_name)?;
+            self.op(OpCode::Method(method_index), line);
+        }
+        self.op(OpCode::Pop, line);
+        if superclass.is_some() {
+            self.end_scope();
+        }
+        Ok(())
+    }
+
+    // The iterator of a for-in loop is the hidden local the loop reads back.
+    pub fn iter_init(&mut self, line: u32) -> EmitResult {
+        self.op(OpCode::Iterate, line);
+        self.declare_variable(ITERATOR_KEY)?;
+        self.define_variable(ITERATOR_KEY, false, line)
+    }
+
+    // The loop variable is a local of the scope each iteration opens, which
+    // the body's last statement closes.
+    pub fn iter_next(&mut self, name: &str, steps: usize, line: u32) -> EmitResult {
+        self.get_variable(ITERATOR_KEY, line)?;
+        let target = self.current().statement_index + steps;
+        self.statement_jump(OpCode::IterNext(0), target);
+        self.begin_scope();
+        self.declare_variable(name)
+    }
+
+    // Each run of the declaration makes a new enum from the one in the constant pool.
+    pub fn enum_declaration(&mut self, template: Value, name: &str, line: u32) -> EmitResult {
+        self.declare_variable(name)?;
+        let index = self.current().function.chunk.add_constant(template)
+            .ok_or(Unsupported::new("more than 65536 constants in one function", line))?;
+        self.op(OpCode::Enum(index), line);
+        self.define_variable(name, false, line)
+    }
+
+    // The catch is a scope holding the error; `finally` is entered normally
+    // once the body or the catch is over, and by the VM for what leaves them early.
+    pub fn try_statement(&mut self, body: &[Box<dyn Statement>], catch: Option<(&str, &[Box<dyn Statement>])>,
+        finally: Option<&[Box<dyn Statement>]>, line: u32) -> EmitResult
+    {
+        let finally_jump = finally.is_some().then(|| self.jump(OpCode::TryFinally(0), line));
+        let catch_jump = match catch {
+            Some(_) => {
+                let error_class = self.global("Error")?;
+                Some(self.jump(OpCode::Try(0, error_class), line))
+            },
+            None => None
+        };
+        self.statements(body)?;
+        if let Some(catch_jump) = catch_jump {
+            self.op(OpCode::EndTry, line);
+            let end_jump = self.jump(OpCode::Jump(0), line);
+            self.patch_jump(catch_jump);
+            let (name, catch_body) = catch.unwrap();
+            self.begin_scope();
+            self.declare_variable(name)?;
+            self.statements(catch_body)?;
+            self.end_scope();
+            self.patch_jump(end_jump);
+        }
+        if let (Some(finally_jump), Some(finally)) = (finally_jump, finally) {
+            self.op(OpCode::Finally, line);
+            self.patch_jump(finally_jump);
+            self.statements(finally)?;
+            self.op(OpCode::EndFinally, line);
+        }
+        Ok(())
+    }
+
+    pub fn import(&mut self, path: &str, base_dir: &str, line: u32) -> EmitResult {
+        let path = self.name(path)?;
+        let base_dir = self.name(base_dir)?;
+        self.op(OpCode::Import(path, base_dir), line);
+        Ok(())
+    }
+
+    pub fn module_member(&mut self, name: &str, path: &str, line: u32) -> EmitResult {
+        let name = self.name(name)?;
+        let path = self.name(path)?;
+        self.op(OpCode::ModuleMember(name, path), line);
+        Ok(())
+    }
+}
+
+enum Resolved {
+    Local(u16),
+    Upvalue(u16),
+    Global(u16)
+}
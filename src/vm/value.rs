@@ -0,0 +1,338 @@
+use std::{borrow::Cow, cell::{Cell, RefCell}, collections::HashMap, fmt, hash::{BuildHasherDefault, Hasher}, rc::Rc};
+
+use crate::fiber::Channel;
+use crate::gc::{self, Node, Tracer};
+use crate::interpreter::utils::values_equal;
+use crate::iterator::LoxIterator;
+use crate::native::NativeMethod;
+use crate::parser::declarations::{self, Object, Str, Type, ValueObjTrait};
+use crate::vm::chunk::Chunk;
+use crate::vm::coroutine::Suspension;
+
+// Numbers, booleans and nil are stored inline; everything else is a shared handle.
+#[derive(Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Str(Rc<str>),
+    Function(Rc<ObjFunction>),
+    Closure(Rc<ObjClosure>),
+    BoundMethod(Rc<ObjBoundMethod>),
+    Class(Rc<ObjClass>),
+    Instance(Rc<ObjInstance>),
+    Native(Rc<Native>),
+    // Lists, maps, ranges, enums and channels are the tree-walker's objects, which the natives work on.
+    Object(Rc<dyn Object>),
+    Generator(Rc<ObjGenerator>),
+    Fiber(Rc<ObjFiber>),
+    Module(Rc<ObjModule>),
+    Iterator(Rc<ObjIterator>)
+}
+
+// Field and method names are short and come from the script, so they skip SipHash.
+#[derive(Default)]
+pub struct NameHasher(u64);
+
+impl Hasher for NameHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0.rotate_left(5) ^ *byte as u64).wrapping_mul(0x517c_c1b7_2722_0a95);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+pub type NameMap<V> = HashMap<Rc<str>, V, BuildHasherDefault<NameHasher>>;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer
+}
+
+pub struct ObjFunction {
+    pub name: Rc<String>,
+    pub arity: usize,
+    pub chunk: Chunk,
+    pub upvalues: Vec<UpvalueRef>,
+    pub kind: FunctionKind,
+    // The module the function is written in, None in the script run.
+    pub file: Option<Rc<str>>,
+    pub generator: bool
+}
+
+// Where a closure finds a captured variable when it is created: a slot of the
+// enclosing frame or one of the enclosing closure's own upvalues. A variable
+// its scope declares later is a forward capture, nil until `BindForward`
+// points it at the slot; a name nothing declares is the global of that name.
+#[derive(Clone, Copy, PartialEq)]
+pub enum UpvalueRef {
+    Local(u16),
+    Upvalue(u16),
+    Forward(u16),
+    Global(u16)
+}
+
+pub struct ObjClosure {
+    pub function: Rc<ObjFunction>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>
+}
+
+// A captured variable stays on the stack until its scope ends, then moves into the upvalue.
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+    // A captured name no scope declares, by the index of its global.
+    Global(u16)
+}
+
+pub struct ObjClass {
+    pub name: Rc<String>,
+    pub methods: RefCell<NameMap<Rc<ObjClosure>>>
+}
+
+// Methods are looked up on the class when a field doesn't shadow them. `super`
+// is a separate instance of the superclass for each method using it, as in the tree-walker.
+pub struct ObjInstance {
+    pub class: Rc<ObjClass>,
+    pub fields: RefCell<NameMap<Value>>,
+    pub supers: RefCell<NameMap<Rc<ObjInstance>>>
+}
+
+pub struct ObjBoundMethod {
+    pub receiver: Value,
+    pub method: Rc<ObjClosure>
+}
+
+pub enum Native {
+    Clock,
+    Method(NativeMethod),
+    Spawn,
+    // `next` of a generator and `receive` of a channel suspend what calls them, so the VM runs them itself.
+    Next(Rc<ObjGenerator>),
+    Receive(Channel)
+}
+
+// A generator or fiber body that isn't running: suspended with the frames it
+// had open, or not yet started, which is a suspension at its first instruction.
+pub enum Coroutine {
+    Suspended(Suspension),
+    Running,
+    Done
+}
+
+pub struct ObjGenerator {
+    pub name: Rc<String>,
+    pub body: RefCell<Coroutine>
+}
+
+impl ObjGenerator {
+    // Running counts as done, as it does for the tree-walker's generators.
+    pub fn is_done(&self) -> bool {
+        !matches!(*self.body.borrow(), Coroutine::Suspended(_))
+    }
+}
+
+pub struct ObjFiber {
+    pub id: usize,
+    pub name: Rc<String>,
+    pub body: RefCell<Coroutine>,
+    pub blocked_on: RefCell<Option<Channel>>,
+    pub blocked_line: Cell<u32>,
+    pub active: Cell<bool>
+}
+
+// A module's globals, which live among the VM's under the indices its script was compiled with.
+pub struct ObjModule {
+    pub name: String,
+    pub members: HashMap<Rc<str>, u16>
+}
+
+// The hidden variable of a for-in loop. Instances are iterated through their
+// `hasNext` and `next` methods and generators by resuming them; the
+// tree-walker's iterator goes over everything else.
+pub enum ObjIterator {
+    Items(LoxIterator),
+    Generator(Rc<ObjGenerator>),
+    Instance(Rc<ObjInstance>)
+}
+
+// A value of the VM held by one of the tree-walker's objects, such as an instance in a list.
+pub struct VmObject(pub Value);
+
+impl Value {
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    // Compares as the tree-walker does; instances are only equal to themselves.
+    pub fn equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(b1), Value::Bool(b2)) => b1 == b2,
+            (Value::Number(num1), Value::Number(num2)) => num1 == num2,
+            (Value::Str(str1), Value::Str(str2)) => str1 == str2,
+            (Value::Instance(instance1), Value::Instance(instance2)) => Rc::ptr_eq(instance1, instance2),
+            (Value::Object(_), Value::Object(_)) => values_equal(&mut self.to_value(), &mut other.to_value()),
+            _ => false
+        }
+    }
+
+    pub fn from_value(value: &declarations::Value) -> Value {
+        match value {
+            declarations::Value::Nil => Value::Nil,
+            declarations::Value::Bool(boolean) => Value::Bool(*boolean),
+            declarations::Value::Number(num) => Value::Number(*num),
+            declarations::Value::Obj(object) => match object.get_type() {
+                Type::STRING => Value::Str(object.as_str().unwrap().0.as_str().into()),
+                Type::NATIVEMETHOD => Value::Native(Rc::new(Native::Method(object.as_native_method().unwrap().clone()))),
+                Type::VMOBJECT => object.as_vm_object().unwrap().0.clone(),
+                _ => Value::Object(Rc::from(object.dyn_clone()))
+            }
+        }
+    }
+
+    // The value as the natives take it; what only the VM has is wrapped and comes back unchanged.
+    pub fn to_value(&self) -> declarations::Value {
+        match self {
+            Value::Nil => declarations::Value::Nil,
+            Value::Bool(boolean) => declarations::Value::Bool(*boolean),
+            Value::Number(num) => declarations::Value::Number(*num),
+            Value::Str(string) => declarations::Value::obj(Str(string.to_string())),
+            Value::Object(object) => declarations::Value::Obj(object.dyn_clone()),
+            Value::Native(native) => match native.as_ref() {
+                Native::Method(method) => declarations::Value::obj(method.clone()),
+                _ => declarations::Value::obj(VmObject(self.clone()))
+            },
+            value => declarations::Value::obj(VmObject(value.clone()))
+        }
+    }
+
+    // Shared handles are only looked into while this is their one reference,
+    // so what the collector counts as pointing at a container is really there.
+    pub fn trace(&self, tracer: &mut Tracer) {
+        match self {
+            Value::Instance(instance) => tracer.instance(instance),
+            Value::Object(object) if Rc::strong_count(object) == 1 => object.trace(tracer),
+            Value::Closure(closure) if Rc::strong_count(closure) == 1 => closure.trace(tracer),
+            Value::BoundMethod(bound) if Rc::strong_count(bound) == 1 => {
+                bound.receiver.trace(tracer);
+                if Rc::strong_count(&bound.method) == 1 {
+                    bound.method.trace(tracer);
+                }
+            },
+            _ => ()
+        }
+    }
+}
+
+impl ObjClosure {
+    pub fn new(function: Rc<ObjFunction>) -> ObjClosure {
+        ObjClosure { function, upvalues: Vec::new() }
+    }
+
+    pub fn trace(&self, tracer: &mut Tracer) {
+        for upvalue in &self.upvalues {
+            tracer.upvalue(upvalue);
+        }
+    }
+}
+
+impl ObjInstance {
+    // Instances are tracked by the collector, as they can hold each other.
+    pub fn new(class: Rc<ObjClass>) -> Rc<ObjInstance> {
+        let instance = Rc::new(ObjInstance { class, fields: RefCell::new(NameMap::default()), supers: RefCell::new(NameMap::default()) });
+        gc::track(Node::Instance(instance.clone()));
+        instance
+    }
+}
+
+impl ObjFiber {
+    // The body counts as done while it runs, as the tree-walker's does.
+    pub fn is_done(&self) -> bool {
+        !matches!(*self.body.borrow(), Coroutine::Suspended(_))
+    }
+
+    pub fn is_runnable(&self) -> bool {
+        if self.is_done() || self.active.get() {
+            return false;
+        }
+        match self.blocked_on.borrow().as_ref() {
+            Some(channel) => !channel.is_empty(),
+            None => true
+        }
+    }
+}
+
+// Fields shadow methods; a method comes out bound to the instance it was read from.
+pub fn instance_property(instance: &Rc<ObjInstance>, name: &str) -> Option<Value> {
+    if let Some(value) = instance.fields.borrow().get(name) {
+        return Some(value.clone());
+    }
+    let method = instance.class.methods.borrow().get(name)?.clone();
+    Some(Value::BoundMethod(Rc::new(ObjBoundMethod { receiver: Value::Instance(instance.clone()), method })))
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(boolean) => write!(f, "{}", boolean),
+            Value::Number(num) => write!(f, "{}", num),
+            Value::Str(string) => write!(f, "{}", string),
+            Value::Function(function) => write!(f, "<fn {}>", function.name),
+            Value::Closure(closure) => write!(f, "<fn {}>", closure.function.name),
+            Value::BoundMethod(bound) => write!(f, "<fn {}>", bound.method.function.name),
+            Value::Class(class) => write!(f, "{}", class.name),
+            Value::Instance(instance) => write!(f, "{} instance", instance.class.name),
+            Value::Native(native) => match native.as_ref() {
+                Native::Clock => write!(f, "<fn clock>"),
+                Native::Method(method) => write!(f, "{}", method.to_str()),
+                Native::Spawn => write!(f, "<native fn spawn>"),
+                Native::Next(_) => write!(f, "<native fn next>"),
+                Native::Receive(_) => write!(f, "<native fn receive>")
+            },
+            Value::Object(object) => write!(f, "{}", object.to_str()),
+            Value::Generator(generator) => write!(f, "<generator {}>", generator.name),
+            Value::Fiber(fiber) => write!(f, "<fiber {} ({})>", fiber.id, fiber.name),
+            Value::Module(module) => write!(f, "<module {}>", module.name),
+            Value::Iterator(_) => write!(f, "<iterator>")
+        }
+    }
+}
+
+impl Object for VmObject {
+    fn to_str(&self) -> Cow<'static, str> {
+        Cow::Owned(self.0.to_string())
+    }
+
+    fn get_type(&self) -> Type {
+        Type::VMOBJECT
+    }
+
+    fn dyn_clone(&self) -> Box<dyn Object> {
+        Box::new(VmObject(self.0.clone()))
+    }
+
+    fn trace(&self, tracer: &mut Tracer) {
+        self.0.trace(tracer);
+    }
+}
+
+impl ValueObjTrait for VmObject {
+    fn as_vm_object(&self) -> Option<&VmObject> {
+        Some(self)
+    }
+}
+
+impl ToString for VmObject {
+    fn to_string(&self) -> String {
+        self.to_str().to_string()
+    }
+}
@@ -0,0 +1,114 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+// Each script under tests/lox states what running it must produce:
+//   // expect: <line>        next line printed on stdout
+//   // expect error: <line>  line reported on stderr
+//   // exit: <code>          exit status, 0 when absent
+//   // command: <command>    what the script is given to, `run` when absent
+//   // args: <flags>         extra flags passed to the command
+struct Expectation {
+    stdout: Vec<String>,
+    stderr: Vec<String>,
+    exit_code: i32,
+    command: String,
+    args: Vec<String>
+}
+
+fn parse_expectation(source: &str) -> Expectation {
+    let mut expectation = Expectation {
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+        exit_code: 0,
+        command: String::from("run"),
+        args: Vec::new()
+    };
+    for line in source.lines() {
+        let Some((_, comment)) = line.split_once("// ") else { continue };
+        if let Some(text) = comment.strip_prefix("expect: ") {
+            expectation.stdout.push(text.to_string());
+        }
+        else if let Some(text) = comment.strip_prefix("expect error: ") {
+            expectation.stderr.push(text.to_string());
+        }
+        else if let Some(code) = comment.strip_prefix("exit: ") {
+            expectation.exit_code = code.trim().parse().expect("invalid exit code");
+        }
+        else if let Some(command) = comment.strip_prefix("command: ") {
+            expectation.command = command.trim().to_string();
+        }
+        else if let Some(flags) = comment.strip_prefix("args: ") {
+            expectation.args.extend(flags.split_whitespace().map(String::from));
+        }
+    }
+    expectation
+}
+
+fn run_script(path: &Path, command: &str, args: &[String]) -> Result<Output, String> {
+    Command::new(env!("CARGO_BIN_EXE_codecrafters-interpreter"))
+        .arg(command)
+        .args(args)
+        .arg(path.file_name().unwrap())
+        .current_dir(path.parent().unwrap())
+        .output()
+        .map_err(|err| err.to_string())
+}
+
+fn check_script(path: &Path) -> Result<(), String> {
+    let expectation = parse_expectation(&fs::read_to_string(path).map_err(|err| err.to_string())?);
+    let output = run_script(path, &expectation.command, &expectation.args)?;
+    check_output(&expectation, &output)?;
+    // Scripts that do not pick a backend must behave the same on the bytecode VM.
+    if expectation.command == "run" && !expectation.args.iter().any(|arg| arg == "--backend") {
+        let args = [&expectation.args[..], &[String::from("--backend"), String::from("vm")]].concat();
+        let vm_output = run_script(path, "run", &args)?;
+        check_output(&expectation, &vm_output).map_err(|err| format!("--backend vm: {}", err))?;
+        if vm_output.stdout != output.stdout || vm_output.stderr != output.stderr {
+            return Err(format!(
+                "--backend vm differs from the tree-walker:\n{}{}\nvs\n{}{}",
+                String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr),
+                String::from_utf8_lossy(&vm_output.stdout), String::from_utf8_lossy(&vm_output.stderr)
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn check_output(expectation: &Expectation, output: &Output) -> Result<(), String> {
+    let stdout: Vec<&str> = std::str::from_utf8(&output.stdout).unwrap().lines().collect();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if stdout != expectation.stdout {
+        return Err(format!("expected stdout {:?}, got {:?}", expectation.stdout, stdout));
+    }
+    if let Some(missing) = expectation.stderr.iter().find(|line| !stderr.lines().any(|got| got == *line)) {
+        return Err(format!("expected error {:?}, got {:?}", missing, stderr));
+    }
+    if output.status.code() != Some(expectation.exit_code) {
+        return Err(format!("expected exit code {}, got {:?}: {}", expectation.exit_code, output.status.code(), stderr));
+    }
+    Ok(())
+}
+
+fn collect_scripts(dir: &Path, scripts: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            collect_scripts(&path, scripts);
+        }
+        else if path.extension().is_some_and(|ext| ext == "lox") {
+            scripts.push(path);
+        }
+    }
+}
+
+#[test]
+fn lox_scripts() {
+    let mut scripts = Vec::new();
+    collect_scripts(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/lox"), &mut scripts);
+    scripts.sort();
+    let failures: Vec<String> = scripts.iter()
+        .filter_map(|path| check_script(path).err().map(|err| format!("{}: {}", path.display(), err)))
+        .collect();
+    assert!(failures.is_empty(), "{} of {} scripts failed:\n{}", failures.len(), scripts.len(), failures.join("\n"));
+}